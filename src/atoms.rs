@@ -1,295 +1,1061 @@
 use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+use bevy::utils::{HashMap, HashSet};
 use rand::Rng;
-
-// Atom types as described in the blog series
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum AtomType {
-    Empty,
-    Sand,
-    Water,
-    Acid,
-    Fire,
-    Smoke,
-    Steam,
-    Poison,
-    Stone, // Terrain
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use crate::explosions::Explosion;
+use crate::materials::{Behaviour, MaterialRegistry, MaterialRegistryResource, Phase, Placement, Reaction};
+use crate::random::{cell_noise, DeterministicRandom, SimulationRng};
+use crate::world_space::GRID_DOWN;
+
+// Atom types as described in the blog series. An atom type is an id into the
+// MaterialRegistry; the built-in materials keep their names as constants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AtomType(pub u16);
+
+#[allow(non_upper_case_globals)]
+impl AtomType {
+    pub const Empty: AtomType = AtomType(0);
+    pub const Sand: AtomType = AtomType(1);
+    pub const Water: AtomType = AtomType(2);
+    pub const Acid: AtomType = AtomType(3);
+    pub const Fire: AtomType = AtomType(4);
+    pub const Smoke: AtomType = AtomType(5);
+    pub const Steam: AtomType = AtomType(6);
+    pub const Poison: AtomType = AtomType(7);
+    pub const Stone: AtomType = AtomType(8); // Terrain
+
+    // Registry names of the constants above, in id order
+    pub const BUILTIN_NAMES: [&'static str; 9] = [
+        "empty", "sand", "water", "acid", "fire", "smoke", "steam", "poison", "stone",
+    ];
 }
 
+// Temperature atoms drift towards when exposed to empty space
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
 
+// Atoms hotter than this visibly glow, and are fully white-hot at GLOW_FULL_TEMPERATURE
+pub const GLOW_TEMPERATURE: f32 = 500.0;
+pub const GLOW_FULL_TEMPERATURE: f32 = 1500.0;
 
-impl AtomType {
-    pub fn color(&self) -> Color {
-        match self {
-            AtomType::Empty => Color::rgba(0.0, 0.0, 0.0, 0.0),
-            AtomType::Sand => Color::rgb(0.8, 0.7, 0.5),
-            AtomType::Water => Color::rgba(0.2, 0.4, 0.8, 0.8),
-            AtomType::Acid => Color::rgb(0.0, 0.8, 0.0),
-            AtomType::Fire => Color::rgb(1.0, 0.3, 0.0),
-            AtomType::Smoke => Color::rgba(0.3, 0.3, 0.3, 0.5),
-            AtomType::Steam => Color::rgba(0.8, 0.8, 0.9, 0.6),
-            AtomType::Poison => Color::rgb(0.5, 0.0, 0.5),
-            AtomType::Stone => Color::rgb(0.4, 0.4, 0.4),
+// Individual atom component with kinetic properties
+#[derive(Component, Clone)]
+pub struct Atom {
+    pub atom_type: AtomType,
+    pub velocity: Vec2,
+    pub mass: f32,
+    pub lifetime: Option<f32>,
+    pub temperature: f32, // For heat-based interactions
+    pub burning: bool,
+    pub fuel: Option<f32>, // Burn time left once the atom has caught fire; None while untouched
+    // Shade picked from the material's palette when the atom is placed in the
+    // world, so it keeps its look as it moves. None until then.
+    pub color_variant: Option<u8>,
+}
+
+impl Default for Atom {
+    fn default() -> Self {
+        Self {
+            atom_type: AtomType::Empty,
+            velocity: Vec2::ZERO,
+            mass: 0.0,
+            lifetime: None,
+            temperature: 20.0, // Room temperature
+            burning: false,
+            fuel: None,
+            color_variant: None,
         }
     }
+}
 
-    pub fn mass(&self) -> f32 {
-        match self {
-            AtomType::Empty => 0.0,
-            AtomType::Sand => 1.6,
-            AtomType::Water => 1.0,
-            AtomType::Acid => 1.2,
-            AtomType::Fire => 0.1,
-            AtomType::Smoke => 0.05,
-            AtomType::Steam => 0.01,
-            AtomType::Poison => 1.1,
-            AtomType::Stone => 2.5,
-        }
+// Side length of a square simulation chunk, in atoms
+pub const CHUNK_SIZE: i32 = 64;
+
+// Steps a cell keeps being simulated after it or one of its neighbours last
+// changed. Once the countdown runs out the atom sleeps until something wakes it.
+pub const SLEEP_DELAY: u8 = 8;
+
+// Chunk offsets (mod 2) stepped by each checkerboard pass. Chunks in the same pass
+// are two chunks apart, so their write regions can never touch.
+const CHECKERBOARD_PASSES: [IVec2; 4] = [IVec2::new(0, 0), IVec2::new(1, 0), IVec2::new(0, 1), IVec2::new(1, 1)];
+
+// Cells a chunk update is allowed to write: the chunk plus half a chunk on every side
+pub fn chunk_write_region(position: IVec2) -> IRect {
+    let margin = IVec2::splat(CHUNK_SIZE / 2);
+    IRect::from_corners(position * CHUNK_SIZE - margin, (position + 1) * CHUNK_SIZE + margin)
+}
+
+fn region_contains(region: IRect, x: i32, y: i32) -> bool {
+    x >= region.min.x && x < region.max.x && y >= region.min.y && y < region.max.y
+}
+
+// How `update_atoms` schedules chunk updates
+#[derive(Resource, Clone)]
+pub struct SimulationSettings {
+    // Run each checkerboard pass on Bevy's ComputeTaskPool
+    pub parallel: bool,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self { parallel: true }
+    }
+}
+
+// Inclusive rectangle of local chunk cells that need stepping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub min: IVec2,
+    pub max: IVec2,
+}
+
+impl DirtyRect {
+    pub fn new(point: IVec2) -> Self {
+        Self { min: point, max: point }
+    }
+
+    pub fn include(&mut self, point: IVec2) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn contains(&self, point: IVec2) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    // Every cell in the rect, row by row
+    pub fn cells(&self) -> impl Iterator<Item = IVec2> {
+        let (min, max) = (self.min, self.max);
+        (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
     }
 
-    // Approximate density used for simple force calculations
-    pub fn density(&self) -> f32 {
-        match self {
-            AtomType::Empty => 0.0,
-            AtomType::Sand => 1.6,
-            AtomType::Water => 1.0,
-            AtomType::Acid => 1.2,
-            AtomType::Fire => 0.1,
-            AtomType::Smoke => 0.05,
-            AtomType::Steam => 0.01,
-            AtomType::Poison => 1.1,
-            AtomType::Stone => 2.5,
+    // The whole chunk
+    pub fn full() -> Self {
+        Self { min: IVec2::ZERO, max: IVec2::splat(CHUNK_SIZE - 1) }
+    }
+}
+
+// A CHUNK_SIZE x CHUNK_SIZE block of atoms. Chunks without a dirty rect are
+// skipped entirely by the simulation; active ones are only stepped inside it.
+#[derive(Clone)]
+pub struct Chunk {
+    pub position: IVec2,
+    pub atoms: Vec<Atom>,
+    pub updated: Vec<bool>,
+    // Per-cell wake countdown, zero means the atom is asleep
+    pub awake: Vec<u8>,
+    pub dirty_rect: Option<DirtyRect>,
+    // Cells touched since the last step, promoted to `dirty_rect` by `begin_step`
+    pub next_dirty_rect: Option<DirtyRect>,
+    // Cells that need redrawing since the renderer last drew this chunk. Unlike the
+    // dirty rects this only grows on writes, never on wakes, but it covers the
+    // written cell's neighbours too since a cell's look depends on them (wetness).
+    pub changed_rect: Option<DirtyRect>,
+    // Cells that may have turned solid or stopped being solid (been written, or
+    // fallen asleep or woken up) since the terrain colliders last looked
+    pub terrain_rect: Option<DirtyRect>,
+}
+
+impl Chunk {
+    pub fn new(position: IVec2) -> Self {
+        let cells = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        let mut chunk = Self {
+            position,
+            atoms: vec![Atom::default(); cells],
+            updated: vec![false; cells],
+            awake: vec![0; cells],
+            dirty_rect: None,
+            next_dirty_rect: None,
+            changed_rect: None,
+            terrain_rect: Some(DirtyRect::full()),
+        };
+        // A new chunk has never been drawn
+        chunk.mark_all_changed();
+        chunk
+    }
+
+    // World position of the chunk's (0, 0) cell
+    pub fn origin(&self) -> IVec2 {
+        self.position * CHUNK_SIZE
+    }
+
+    pub fn local_index(local: IVec2) -> usize {
+        (local.y * CHUNK_SIZE + local.x) as usize
+    }
+
+    pub fn mark_dirty(&mut self, local: IVec2) {
+        match self.next_dirty_rect.as_mut() {
+            Some(rect) => rect.include(local),
+            None => self.next_dirty_rect = Some(DirtyRect::new(local)),
         }
     }
 
-    pub fn friction(&self) -> f32 {
-        match self {
-            AtomType::Empty => 0.0,
-            AtomType::Sand => 0.8,
-            AtomType::Water => 0.1,
-            AtomType::Acid => 0.2,
-            AtomType::Fire => 0.05,
-            AtomType::Smoke => 0.01,
-            AtomType::Steam => 0.02,
-            AtomType::Poison => 0.15,
-            AtomType::Stone => 0.9,
+    pub fn mark_changed(&mut self, local: IVec2) {
+        match self.changed_rect.as_mut() {
+            Some(rect) => rect.include(local),
+            None => self.changed_rect = Some(DirtyRect::new(local)),
         }
     }
 
-    pub fn heat_capacity(&self) -> f32 {
-        match self {
-            AtomType::Empty => 0.0,
-            AtomType::Sand => 0.8,
-            AtomType::Water => 4.18, // High heat capacity
-            AtomType::Acid => 2.0,
-            AtomType::Fire => 0.5,
-            AtomType::Smoke => 0.3,
-            AtomType::Steam => 2.0,
-            AtomType::Poison => 1.5,
-            AtomType::Stone => 0.8,
+    // Redraw the whole chunk, e.g. because the light falling on it changed
+    pub fn mark_all_changed(&mut self) {
+        self.changed_rect = Some(DirtyRect::full());
+    }
+
+    pub fn mark_terrain(&mut self, local: IVec2) {
+        match self.terrain_rect.as_mut() {
+            Some(rect) => rect.include(local),
+            None => self.terrain_rect = Some(DirtyRect::new(local)),
         }
     }
 
+    // Hand the cells whose solidity may have changed over to the terrain colliders
+    pub fn take_terrain_rect(&mut self) -> Option<DirtyRect> {
+        self.terrain_rect.take()
+    }
 
-    pub fn is_fluid(&self) -> bool {
-        matches!(self, AtomType::Water | AtomType::Acid | AtomType::Poison | AtomType::Steam)
+    // Hand the changed cells over to the renderer, which redraws just that rect
+    pub fn take_changed_rect(&mut self) -> Option<DirtyRect> {
+        self.changed_rect.take()
     }
 
-    pub fn is_gas(&self) -> bool {
-        matches!(self, AtomType::Smoke | AtomType::Steam | AtomType::Fire)
+    // Reset the cell's sleep countdown and schedule it for the next step
+    pub fn wake(&mut self, local: IVec2) {
+        let idx = Self::local_index(local);
+        // Settled powder stops counting as terrain once it wakes up
+        if self.awake[idx] == 0 {
+            self.mark_terrain(local);
+        }
+        self.awake[idx] = SLEEP_DELAY;
+        self.mark_dirty(local);
     }
 
-    pub fn can_burn(&self) -> bool {
-        matches!(self, AtomType::Sand | AtomType::Stone) // Add flammable materials later
+    pub fn is_sleeping(&self, local: IVec2) -> bool {
+        self.awake[Self::local_index(local)] == 0
     }
-}
 
-// Individual atom component with kinetic properties
-#[derive(Component, Clone)]
-pub struct Atom {
-    pub atom_type: AtomType,
-    pub velocity: Vec2,
-    pub mass: f32,
-    pub lifetime: Option<f32>,
-    pub temperature: f32, // For heat-based interactions
-}
+    pub fn is_active(&self) -> bool {
+        self.dirty_rect.is_some()
+    }
 
-impl Default for Atom {
-    fn default() -> Self {
-        Self {
-            atom_type: AtomType::Empty,
-            velocity: Vec2::ZERO,
-            mass: 0.0,
-            lifetime: None,
-            temperature: 20.0, // Room temperature
+    // World positions of the awake cells in this step's dirty rect, bottom to top, right to left
+    pub fn dirty_cells(&self) -> Vec<IVec2> {
+        let Some(rect) = self.dirty_rect else {
+            return Vec::new();
+        };
+
+        let mut cells = Vec::new();
+        for local_y in (rect.min.y..=rect.max.y).rev() {
+            for local_x in (rect.min.x..=rect.max.x).rev() {
+                let local = IVec2::new(local_x, local_y);
+                if !self.is_sleeping(local) {
+                    cells.push(self.origin() + local);
+                }
+            }
         }
+        cells
     }
+
+    // Tick down the wake countdown of every cell stepped last time. Cells that are
+    // still awake keep the chunk scheduled even if nothing new touched them.
+    fn settle(&mut self) {
+        let Some(rect) = self.dirty_rect else {
+            return;
+        };
+
+        for y in rect.min.y..=rect.max.y {
+            for x in rect.min.x..=rect.max.x {
+                let local = IVec2::new(x, y);
+                let idx = Self::local_index(local);
+                if self.awake[idx] > 0 {
+                    self.awake[idx] -= 1;
+                    if self.awake[idx] > 0 {
+                        self.mark_dirty(local);
+                    } else {
+                        self.mark_terrain(local);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Where a ray first ran into something, see `AtomWorld::raycast`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RayHit {
+    // The cell it ran into
+    pub cell: IVec2,
+    // The last free cell before it
+    pub free: IVec2,
+    // Which side of `cell` the ray came in through, pointing back out
+    pub normal: IVec2,
 }
 
-// World grid for atoms
+// Cells around a surface looked at to work out which way it faces
+const SURFACE_NORMAL_RADIUS: i32 = 2;
+
+// Sparse, chunked world grid for atoms
+#[derive(Clone)]
 pub struct AtomWorld {
+    // Extent the world was created with. Bounded worlds never leave it, unbounded
+    // worlds allocate chunks on demand in any direction.
     pub width: usize,
     pub height: usize,
-    pub atoms: Vec<Atom>,
-    pub updated: Vec<bool>,
+    pub bounded: bool,
+    pub chunks: HashMap<IVec2, Chunk>,
+    // Number of simulation steps taken so far
+    pub step: u64,
+    // Randomness source for atom behaviours. Reseeded for every chunk update while
+    // stepping; level generation seeds it directly.
+    pub rng: DeterministicRandom,
+    // While a chunk update runs, writes outside its write region are refused
+    write_clip: Option<IRect>,
+    // Materials the simulation reads while stepping, replaced with the registry
+    // handed to `step`. Chunk updates index it directly.
+    materials: Arc<MaterialRegistry>,
+    // Explosions set off since they were last resolved, see `queue_explosion`
+    explosions: Vec<Explosion>,
 }
 
 impl AtomWorld {
     pub fn new(width: usize, height: usize) -> Self {
-        Self {
+        let mut world = Self {
             width,
             height,
-            atoms: vec![Atom::default(); width * height],
-            updated: vec![false; width * height],
+            bounded: true,
+            chunks: HashMap::default(),
+            step: 0,
+            rng: DeterministicRandom::new(0),
+            write_clip: None,
+            materials: Arc::default(),
+            explosions: Vec::new(),
+        };
+        world.allocate_extent();
+        world
+    }
+
+    pub fn unbounded(width: usize, height: usize) -> Self {
+        let mut world = Self {
+            width,
+            height,
+            bounded: false,
+            chunks: HashMap::default(),
+            step: 0,
+            rng: DeterministicRandom::new(0),
+            write_clip: None,
+            materials: Arc::default(),
+            explosions: Vec::new(),
+        };
+        world.allocate_extent();
+        world
+    }
+
+    fn allocate_extent(&mut self) {
+        let (max, _) = Self::chunk_coords(self.width as i32 - 1, self.height as i32 - 1);
+        for cy in 0..=max.y {
+            for cx in 0..=max.x {
+                let position = IVec2::new(cx, cy);
+                self.chunks.insert(position, Chunk::new(position));
+            }
         }
     }
 
-    pub fn get_index(&self, x: i32, y: i32) -> Option<usize> {
-        if x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32 {
-            Some((y as usize) * self.width + (x as usize))
-        } else {
-            None
+    // Split a world position into chunk coordinates and the local cell inside that chunk
+    pub fn chunk_coords(x: i32, y: i32) -> (IVec2, IVec2) {
+        let pos = IVec2::new(x, y);
+        let size = IVec2::splat(CHUNK_SIZE);
+        (pos.div_euclid(size), pos.rem_euclid(size))
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        !self.bounded || (x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32)
+    }
+
+    fn can_write(&self, x: i32, y: i32) -> bool {
+        self.in_bounds(x, y) && self.write_clip.map_or(true, |clip| region_contains(clip, x, y))
+    }
+
+    // Cell extent covered by the world (max exclusive)
+    pub fn cell_bounds(&self) -> IRect {
+        if self.bounded {
+            return IRect::new(0, 0, self.width as i32, self.height as i32);
         }
+
+        self.chunks.keys().fold(IRect::EMPTY, |rect, pos| {
+            rect.union(IRect::from_corners(*pos * CHUNK_SIZE, (*pos + 1) * CHUNK_SIZE))
+        })
+    }
+
+    // Every allocated cell together with its world position
+    pub fn iter_atoms(&self) -> impl Iterator<Item = (IVec2, &Atom)> + '_ {
+        self.chunks
+            .values()
+            .flat_map(|chunk| {
+                let origin = chunk.origin();
+                chunk.atoms.iter().enumerate().map(move |(i, atom)| {
+                    let local = IVec2::new(i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE);
+                    (origin + local, atom)
+                })
+            })
+            .filter(move |(pos, _)| self.in_bounds(pos.x, pos.y))
     }
 
     pub fn get_atom(&self, x: i32, y: i32) -> Option<&Atom> {
-        self.get_index(x, y).map(|idx| &self.atoms[idx])
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        let (chunk, local) = Self::chunk_coords(x, y);
+        self.chunks.get(&chunk).map(|chunk| &chunk.atoms[Chunk::local_index(local)])
     }
 
+    // Callers of the mutable accessor are expected to change the atom, so the cell is woken
     pub fn get_atom_mut(&mut self, x: i32, y: i32) -> Option<&mut Atom> {
-        self.get_index(x, y).map(|idx| &mut self.atoms[idx])
+        if !self.can_write(x, y) {
+            return None;
+        }
+        self.get_atom(x, y)?;
+        self.mark_dirty(x, y);
+        self.atom_mut_untracked(x, y)
     }
 
-    pub fn set_atom(&mut self, x: i32, y: i32, atom: Atom) {
-        if let Some(idx) = self.get_index(x, y) {
-            self.atoms[idx] = atom;
+    // Mutable access for bookkeeping (velocity, temperature) that shouldn't keep a cell awake
+    fn atom_mut_untracked(&mut self, x: i32, y: i32) -> Option<&mut Atom> {
+        if !self.in_bounds(x, y) {
+            return None;
         }
+        let (chunk, local) = Self::chunk_coords(x, y);
+        self.chunks.get_mut(&chunk).map(|chunk| &mut chunk.atoms[Chunk::local_index(local)])
+    }
+
+    fn chunk_or_insert(&mut self, position: IVec2) -> &mut Chunk {
+        self.chunks.entry(position).or_insert_with(|| Chunk::new(position))
+    }
+
+    pub fn set_atom(&mut self, x: i32, y: i32, mut atom: Atom) {
+        if !self.can_write(x, y) {
+            return;
+        }
+        let (chunk, local) = Self::chunk_coords(x, y);
+        if atom.atom_type == AtomType::Empty && !self.chunks.contains_key(&chunk) {
+            return; // Unallocated space is already empty
+        }
+        // New atoms pick their shade from where and when they spawn. This can't use
+        // the simulation's generator, or how things look would change how they move.
+        if atom.color_variant.is_none() && atom.atom_type != AtomType::Empty {
+            atom.color_variant = Some((cell_noise(IVec2::new(x, y), self.step) * 256.0) as u8);
+        }
+        self.chunk_or_insert(chunk).atoms[Chunk::local_index(local)] = atom;
+        self.mark_dirty(x, y);
     }
 
     pub fn is_empty(&self, x: i32, y: i32) -> bool {
+        // Cells owned by another chunk update act as walls
+        if self.write_clip.is_some_and(|clip| !region_contains(clip, x, y)) {
+            return false;
+        }
         self.get_atom(x, y).map_or(true, |atom| atom.atom_type == AtomType::Empty)
     }
 
+    // Closest empty cell within `radius` (in rings around `cell`, `cell` first)
+    pub fn nearest_empty(&self, cell: IVec2, radius: i32) -> Option<IVec2> {
+        for ring in 0..=radius {
+            for y in -ring..=ring {
+                for x in -ring..=ring {
+                    if x.abs().max(y.abs()) != ring {
+                        continue;
+                    }
+                    let target = cell + IVec2::new(x, y);
+                    if self.in_bounds(target.x, target.y) && self.is_empty(target.x, target.y) {
+                        return Some(target);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Whether the atom at (x, y) can move to (tx, ty): into empty space, or by
+    // swapping with a fluid that is lighter when moving down or sideways and
+    // heavier when moving up. Heavier materials sink, lighter ones float.
+    pub fn can_move_into(&self, x: i32, y: i32, tx: i32, ty: i32) -> bool {
+        if self.is_empty(tx, ty) {
+            return true;
+        }
+        if !self.can_write(tx, ty) || self.is_updated(tx, ty) {
+            return false;
+        }
+        let (Some(atom), Some(target)) = (self.get_atom(x, y), self.get_atom(tx, ty)) else {
+            return false;
+        };
+        let target_material = self.materials.get(target.atom_type);
+        if !target_material.is_fluid() {
+            return false;
+        }
+
+        let (density, target_density) = (self.materials.get(atom.atom_type).density, target_material.density);
+        if ty < y {
+            density < target_density
+        } else {
+            density > target_density
+        }
+    }
+
     pub fn swap_atoms(&mut self, x1: i32, y1: i32, x2: i32, y2: i32) {
-        if let (Some(idx1), Some(idx2)) = (self.get_index(x1, y1), self.get_index(x2, y2)) {
-            self.atoms.swap(idx1, idx2);
-            self.updated[idx1] = true;
-            self.updated[idx2] = true;
+        if !self.can_write(x1, y1) || !self.can_write(x2, y2) {
+            return;
+        }
+        let (chunk1, local1) = Self::chunk_coords(x1, y1);
+        let (chunk2, local2) = Self::chunk_coords(x2, y2);
+        let (idx1, idx2) = (Chunk::local_index(local1), Chunk::local_index(local2));
+
+        // Moving into unallocated space grows the world
+        let first = std::mem::take(&mut self.chunk_or_insert(chunk1).atoms[idx1]);
+        let target = self.chunk_or_insert(chunk2);
+        let second = std::mem::replace(&mut target.atoms[idx2], first);
+        target.updated[idx2] = true;
+        let source = self.chunk_or_insert(chunk1);
+        source.atoms[idx1] = second;
+        source.updated[idx1] = true;
+
+        self.mark_dirty(x1, y1);
+        self.mark_dirty(x2, y2);
+    }
+
+    pub fn is_updated(&self, x: i32, y: i32) -> bool {
+        let (chunk, local) = Self::chunk_coords(x, y);
+        self.chunks
+            .get(&chunk)
+            .map_or(false, |chunk| chunk.updated[Chunk::local_index(local)])
+    }
+
+    // Wake a cell and its 8 neighbours for the next step, crossing chunk borders as
+    // needed. They're all queued for redrawing too, and the cell itself for the
+    // terrain colliders.
+    pub fn mark_dirty(&mut self, x: i32, y: i32) {
+        let (chunk, local) = Self::chunk_coords(x, y);
+        if let Some(chunk) = self.chunks.get_mut(&chunk) {
+            chunk.mark_terrain(local);
+        }
+        let interior = local.cmpgt(IVec2::ZERO).all() && local.cmplt(IVec2::splat(CHUNK_SIZE - 1)).all();
+
+        if interior {
+            if let Some(chunk) = self.chunks.get_mut(&chunk) {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        chunk.wake(local + IVec2::new(dx, dy));
+                        chunk.mark_changed(local + IVec2::new(dx, dy));
+                    }
+                }
+            }
+            return;
+        }
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (chunk, local) = Self::chunk_coords(x + dx, y + dy);
+                if let Some(chunk) = self.chunks.get_mut(&chunk) {
+                    chunk.wake(local);
+                    chunk.mark_changed(local);
+                }
+            }
+        }
+    }
+
+    // Queue a cell for redrawing without waking it, for changes that only show
+    // (like a rising temperature)
+    pub fn mark_changed(&mut self, x: i32, y: i32) {
+        let (chunk, local) = Self::chunk_coords(x, y);
+        if let Some(chunk) = self.chunks.get_mut(&chunk) {
+            chunk.mark_changed(local);
+        }
+    }
+
+    // Set off an explosion. It goes off when explosions::resolve_explosions next
+    // runs, outside the simulation step, so it's safe to call from anywhere.
+    pub fn queue_explosion(&mut self, explosion: Explosion) {
+        self.explosions.push(explosion);
+    }
+
+    pub fn take_explosions(&mut self) -> Vec<Explosion> {
+        std::mem::take(&mut self.explosions)
+    }
+
+    // Wake every cell inside a rectangle (max exclusive), e.g. under a moving rigid body
+    pub fn wake_region(&mut self, rect: IRect) {
+        for y in rect.min.y..rect.max.y {
+            for x in rect.min.x..rect.max.x {
+                if !self.in_bounds(x, y) {
+                    continue;
+                }
+                let (chunk, local) = Self::chunk_coords(x, y);
+                if let Some(chunk) = self.chunks.get_mut(&chunk) {
+                    chunk.wake(local);
+                }
+            }
+        }
+    }
+
+    pub fn is_sleeping(&self, x: i32, y: i32) -> bool {
+        let (chunk, local) = Self::chunk_coords(x, y);
+        self.chunks.get(&chunk).map_or(true, |chunk| chunk.is_sleeping(local))
+    }
+
+    // Promote the cells touched since the last step into this step's dirty rects
+    pub fn begin_step(&mut self) {
+        self.step += 1;
+        for chunk in self.chunks.values_mut() {
+            chunk.settle();
+            chunk.dirty_rect = chunk.next_dirty_rect.take();
+            if chunk.is_active() {
+                chunk.updated.iter_mut().for_each(|u| *u = false);
+            }
+        }
+    }
+
+    // World positions of every awake cell inside an active dirty rect, ordered
+    // bottom to top and right to left like a full-grid sweep would visit them
+    pub fn dirty_cells(&self) -> Vec<IVec2> {
+        let mut active: Vec<&Chunk> = self.chunks.values().filter(|chunk| chunk.is_active()).collect();
+        active.sort_by(|a, b| b.position.y.cmp(&a.position.y).then(b.position.x.cmp(&a.position.x)));
+
+        let mut cells = Vec::new();
+        for row in active.chunk_by(|a, b| a.position.y == b.position.y) {
+            for local_y in (0..CHUNK_SIZE).rev() {
+                for chunk in row {
+                    let rect = chunk.dirty_rect.unwrap();
+                    if local_y < rect.min.y || local_y > rect.max.y {
+                        continue;
+                    }
+                    for local_x in (rect.min.x..=rect.max.x).rev() {
+                        let local = IVec2::new(local_x, local_y);
+                        if !chunk.is_sleeping(local) {
+                            cells.push(chunk.origin() + local);
+                        }
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    // Advance the simulation by one step using the 4-pass checkerboard schedule.
+    // Every chunk update is seeded from one draw of `rng` and the chunk position,
+    // and serial and parallel runs execute the same updates against the same
+    // state, so the grid only depends on the seed and the inputs. Material
+    // properties all come from `materials`.
+    pub fn step(
+        &mut self,
+        materials: &Arc<MaterialRegistry>,
+        settings: &SimulationSettings,
+        rng: &mut SimulationRng,
+        dt: f32,
+    ) {
+        self.begin_step();
+        let step_rng = DeterministicRandom::new(rng.rng().next_u64());
+
+        for offset in CHECKERBOARD_PASSES {
+            let tasks = self.pass_tasks(offset, &step_rng, materials);
+            if settings.parallel && tasks.len() > 1 {
+                self.run_pass_parallel(tasks, dt);
+            } else {
+                for task in tasks {
+                    self.run_chunk_task(task, dt);
+                }
+            }
+        }
+    }
+
+    // Active chunks belonging to one checkerboard pass, in a stable order
+    fn pass_tasks(
+        &self,
+        offset: IVec2,
+        step_rng: &DeterministicRandom,
+        materials: &Arc<MaterialRegistry>,
+    ) -> Vec<ChunkTask> {
+        let mut positions: Vec<IVec2> = self
+            .chunks
+            .values()
+            .filter(|chunk| chunk.is_active() && chunk.position.rem_euclid(IVec2::splat(2)) == offset)
+            .map(|chunk| chunk.position)
+            .collect();
+        positions.sort_by_key(|position| (position.y, position.x));
+
+        positions
+            .into_iter()
+            .filter_map(|position| {
+                let cells = self.chunks[&position].dirty_cells();
+                if cells.is_empty() {
+                    return None;
+                }
+                Some(ChunkTask {
+                    position,
+                    cells,
+                    rng: step_rng.fork(chunk_stream(position)),
+                    materials: Arc::clone(materials),
+                })
+            })
+            .collect()
+    }
+
+    // Step one chunk in place, clipped to its write region
+    fn run_chunk_task(&mut self, task: ChunkTask, dt: f32) {
+        self.write_clip = Some(chunk_write_region(task.position));
+        self.rng = task.rng;
+        self.materials = task.materials;
+        step_cells(self, &task.cells, dt);
+        self.write_clip = None;
+    }
+
+    // Step every chunk of a pass on its own copy of the surrounding 3x3 chunks,
+    // then merge each copy's write region back in order
+    fn run_pass_parallel(&mut self, tasks: Vec<ChunkTask>, dt: f32) {
+        let jobs: Vec<(AtomWorld, Vec<IVec2>)> = tasks
+            .into_iter()
+            .map(|task| (self.neighbourhood(task.position, task.rng, task.materials), task.cells))
+            .collect();
+
+        let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+        let results = pool.scope(|scope| {
+            for (mut local, cells) in jobs {
+                scope.spawn(async move {
+                    step_cells(&mut local, &cells, dt);
+                    local
+                });
+            }
+        });
+
+        for local in results {
+            self.merge_neighbourhood(local);
+        }
+    }
+
+    fn neighbourhood(&self, position: IVec2, rng: DeterministicRandom, materials: Arc<MaterialRegistry>) -> AtomWorld {
+        let mut chunks = HashMap::default();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let neighbour = position + IVec2::new(dx, dy);
+                if let Some(chunk) = self.chunks.get(&neighbour) {
+                    chunks.insert(neighbour, chunk.clone());
+                }
+            }
+        }
+
+        AtomWorld {
+            width: self.width,
+            height: self.height,
+            bounded: self.bounded,
+            chunks,
+            step: self.step,
+            rng,
+            write_clip: Some(chunk_write_region(position)),
+            materials,
+            explosions: Vec::new(),
+        }
+    }
+
+    fn merge_neighbourhood(&mut self, local: AtomWorld) {
+        let clip = local.write_clip.expect("neighbourhoods are always clipped");
+        self.explosions.extend(local.explosions);
+
+        for (position, chunk) in local.chunks {
+            let target = self.chunk_or_insert(position);
+            let origin = target.origin();
+
+            // Atoms can only have changed inside the write region
+            let min = (clip.min - origin).max(IVec2::ZERO);
+            let max = (clip.max - origin).min(IVec2::splat(CHUNK_SIZE));
+            for y in min.y..max.y {
+                let row = Chunk::local_index(IVec2::new(min.x, y))..Chunk::local_index(IVec2::new(max.x, y));
+                target.atoms[row.clone()].clone_from_slice(&chunk.atoms[row.clone()]);
+                for (updated, local_updated) in target.updated[row.clone()].iter_mut().zip(&chunk.updated[row]) {
+                    *updated |= *local_updated;
+                }
+            }
+
+            // Wakes may spill one cell past the region, and only ever raise the countdown
+            for (awake, local_awake) in target.awake.iter_mut().zip(&chunk.awake) {
+                *awake = (*awake).max(*local_awake);
+            }
+            if let Some(rect) = chunk.next_dirty_rect {
+                target.mark_dirty(rect.min);
+                target.mark_dirty(rect.max);
+            }
+            if let Some(rect) = chunk.changed_rect {
+                target.mark_changed(rect.min);
+                target.mark_changed(rect.max);
+            }
+            if let Some(rect) = chunk.terrain_rect {
+                target.mark_terrain(rect.min);
+                target.mark_terrain(rect.max);
+            }
+        }
+    }
+
+    // Hash of every allocated cell, for comparing simulation runs
+    pub fn grid_hash(&self) -> u64 {
+        let mut positions: Vec<&IVec2> = self.chunks.keys().collect();
+        positions.sort_by_key(|position| (position.y, position.x));
+
+        let mut hasher = DefaultHasher::new();
+        for position in positions {
+            position.hash(&mut hasher);
+            for atom in &self.chunks[position].atoms {
+                atom.atom_type.hash(&mut hasher);
+                atom.velocity.x.to_bits().hash(&mut hasher);
+                atom.velocity.y.to_bits().hash(&mut hasher);
+                atom.temperature.to_bits().hash(&mut hasher);
+                atom.burning.hash(&mut hasher);
+                atom.fuel.map(f32::to_bits).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    // Add heat to every atom within `radius` of `center` (negative chills), e.g.
+    // from a spell. Affected atoms are woken so they can change phase.
+    pub fn apply_heat(&mut self, center: IVec2, radius: i32, amount: f32) {
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if dx * dx + dy * dy > radius * radius {
+                    continue;
+                }
+                let pos = center + IVec2::new(dx, dy);
+                let heated = match self.atom_mut_untracked(pos.x, pos.y) {
+                    Some(atom) if atom.atom_type != AtomType::Empty => {
+                        atom.temperature += amount;
+                        true
+                    }
+                    _ => false,
+                };
+                if heated {
+                    self.mark_dirty(pos.x, pos.y);
+                }
+            }
+        }
+    }
+
+    // Walk the cells a ray from `from` to `to` (grid points) passes through, and
+    // report the first time it goes from a free cell into one `blocks` accepts.
+    // Outside a bounded world counts as blocking. A ray that starts inside
+    // blocking cells has to come out into free space before it can hit anything,
+    // so whatever is already buried in something can carry on out of it.
+    pub fn raycast(&self, from: Vec2, to: Vec2, blocks: impl Fn(&Atom) -> bool) -> Option<RayHit> {
+        let blocked = |cell: IVec2| {
+            !self.in_bounds(cell.x, cell.y) || self.get_atom(cell.x, cell.y).map_or(false, &blocks)
+        };
+
+        let mut cell = from.round().as_ivec2();
+        let end = to.round().as_ivec2();
+        let delta = to - from;
+        let step = IVec2::new(delta.x.signum() as i32, delta.y.signum() as i32);
+        // Ray distance (0 at `from`, 1 at `to`) between cell borders on each axis,
+        // and to the next border. Cells are centred on their coordinates, so
+        // borders sit half way between them.
+        let t_delta = Vec2::new(1.0 / delta.x.abs(), 1.0 / delta.y.abs());
+        let mut t_max = Vec2::new(
+            if delta.x != 0.0 { (cell.x as f32 + step.x as f32 * 0.5 - from.x) / delta.x } else { f32::INFINITY },
+            if delta.y != 0.0 { (cell.y as f32 + step.y as f32 * 0.5 - from.y) / delta.y } else { f32::INFINITY },
+        );
+
+        let mut free = (!blocked(cell)).then_some(cell);
+        let steps = (end - cell).abs().element_sum();
+        for _ in 0..steps {
+            let normal = if t_max.x < t_max.y {
+                cell.x += step.x;
+                t_max.x += t_delta.x;
+                IVec2::new(-step.x, 0)
+            } else {
+                cell.y += step.y;
+                t_max.y += t_delta.y;
+                IVec2::new(0, -step.y)
+            };
+
+            if !blocked(cell) {
+                free = Some(cell);
+            } else if let Some(free) = free {
+                return Some(RayHit { cell, free, normal });
+            }
         }
+        None
     }
 
-    pub fn clear_updated(&mut self) {
-        self.updated.iter_mut().for_each(|u| *u = false);
+    // Outward normal (grid space) of the surface around `cell`, pointing away from
+    // the blocking cells within SURFACE_NORMAL_RADIUS. Unlike a ray's normal it
+    // follows slopes. None if `cell` is blocked on all sides or on none.
+    pub fn surface_normal(&self, cell: IVec2, blocks: impl Fn(&Atom) -> bool) -> Option<Vec2> {
+        let radius = SURFACE_NORMAL_RADIUS;
+        let mut towards_surface = Vec2::ZERO;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let offset = IVec2::new(dx, dy);
+                if offset == IVec2::ZERO || offset.length_squared() > radius * radius {
+                    continue;
+                }
+                let neighbour = cell + offset;
+                let blocked = !self.in_bounds(neighbour.x, neighbour.y)
+                    || self.get_atom(neighbour.x, neighbour.y).map_or(false, &blocks);
+                if blocked {
+                    towards_surface += offset.as_vec2();
+                }
+            }
+        }
+        (-towards_surface).try_normalize()
     }
+
+    pub fn active_chunk_count(&self) -> usize {
+        self.chunks.values().filter(|chunk| chunk.is_active()).count()
+    }
+
+    // (non-empty atoms, sleeping atoms), like the example's sleeping stats
+    pub fn sleep_stats(&self) -> (usize, usize) {
+        let mut total = 0;
+        let mut sleeping = 0;
+        for chunk in self.chunks.values() {
+            for (atom, awake) in chunk.atoms.iter().zip(&chunk.awake) {
+                if atom.atom_type != AtomType::Empty {
+                    total += 1;
+                    if *awake == 0 {
+                        sleeping += 1;
+                    }
+                }
+            }
+        }
+        (total, sleeping)
+    }
+}
+
+// One chunk's share of a checkerboard pass
+struct ChunkTask {
+    position: IVec2,
+    cells: Vec<IVec2>,
+    rng: DeterministicRandom,
+    materials: Arc<MaterialRegistry>,
+}
+
+// Stream id a chunk's generator is forked with
+fn chunk_stream(position: IVec2) -> u64 {
+    ((position.x as u32 as u64) << 32) | position.y as u32 as u64
 }
 
 // Resource for the atom world
 #[derive(Resource)]
 pub struct AtomWorldResource(pub AtomWorld);
 
+// Simulated time per step. The grid advances one fixed tick per frame rather than
+// by the frame's wall-clock delta, so a run depends only on its seed and inputs.
+pub const SIMULATION_DT: f32 = 1.0 / 60.0;
+
 // Systems for atom physics
 pub fn update_atoms(
     mut world: ResMut<AtomWorldResource>,
-    time: Res<Time>,
+    materials: Res<MaterialRegistryResource>,
+    settings: Res<SimulationSettings>,
+    mut rng: ResMut<SimulationRng>,
 ) {
-    let dt = time.delta_seconds();
-    world.0.clear_updated();
+    world.0.step(&materials.0, &settings, &mut rng, SIMULATION_DT);
+}
 
+// Full update pipeline for the awake cells of one chunk
+fn step_cells(world: &mut AtomWorld, cells: &[IVec2], dt: f32) {
     // Apply gravity and other forces first
-    apply_gravity(&mut world.0, dt);
+    apply_gravity(world, cells, dt);
 
     // Update atoms from bottom to top, right to left (to simulate gravity)
-    for y in (0..world.0.height).rev() {
-        for x in (0..world.0.width).rev() {
-            update_atom(&mut world.0, x as i32, y as i32, dt);
-        }
+    for pos in cells {
+        update_atom(world, pos.x, pos.y, dt);
     }
 
     // Apply velocity-based movement
-    apply_velocity_movement(&mut world.0, dt);
+    apply_velocity_movement(world, cells, dt);
 
     // Heat transfer between atoms
-    apply_heat_transfer(&mut world.0, dt);
+    apply_heat_transfer(world, cells, dt);
+
+    // Ignition, burning and burning out
+    apply_combustion(world, cells, dt);
+
+    // Melting, boiling, freezing and condensation
+    apply_phase_transitions(world, cells);
 
     // Particle interactions (optimized)
-    apply_particle_interactions(&mut world.0, dt);
+    apply_particle_interactions(world, cells, dt);
 }
 
-fn apply_gravity(world: &mut AtomWorld, dt: f32) {
-    let gravity = Vec2::new(0.0, -30.0); // Gravity force
+fn apply_gravity(world: &mut AtomWorld, cells: &[IVec2], dt: f32) {
+    // Gravity force, in cells per second squared
+    let gravity = GRID_DOWN.as_vec2() * 30.0;
+    let materials = Arc::clone(&world.materials);
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            let idx = y * world.width + x;
-            if world.atoms[idx].atom_type != AtomType::Empty {
+    for pos in cells {
+        if let Some(atom) = world.atom_mut_untracked(pos.x, pos.y) {
+            if atom.atom_type != AtomType::Empty {
                 // Apply gravity based on mass
-                let mass = world.atoms[idx].mass;
-                if mass > 0.0 {
-                    world.atoms[idx].velocity += gravity * dt;
+                if atom.mass > 0.0 {
+                    atom.velocity += gravity * dt;
                 }
 
                 // Apply friction
-                let friction = world.atoms[idx].atom_type.friction();
-                world.atoms[idx].velocity *= 1.0 - friction * dt;
+                let friction = materials.get(atom.atom_type).friction;
+                atom.velocity *= 1.0 - friction * dt;
             }
         }
     }
 }
 
-fn apply_velocity_movement(world: &mut AtomWorld, dt: f32) {
+fn apply_velocity_movement(world: &mut AtomWorld, cells: &[IVec2], dt: f32) {
     let mut movements = Vec::new();
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            let idx = y * world.width + x;
-            if world.atoms[idx].atom_type != AtomType::Empty && world.atoms[idx].velocity.length_squared() > 0.01 {
-                let new_x = x as f32 + world.atoms[idx].velocity.x * dt;
-                let new_y = y as f32 + world.atoms[idx].velocity.y * dt;
+    for pos in cells.iter().rev() {
+        if let Some(atom) = world.get_atom(pos.x, pos.y) {
+            // Static solids like stone and wood stay where they were placed
+            let movable = world.materials.get(atom.atom_type).behaviour != Behaviour::Static;
+            if movable && atom.velocity.length_squared() > 0.01 {
+                let new_x = pos.x as f32 + atom.velocity.x * dt;
+                let new_y = pos.y as f32 + atom.velocity.y * dt;
 
-                movements.push((x, y, new_x as i32, new_y as i32));
+                movements.push((*pos, IVec2::new(new_x as i32, new_y as i32)));
             }
         }
     }
 
     // Apply movements, handling collisions
-    for (old_x, old_y, new_x, new_y) in movements {
-        if let Some(atom) = world.get_atom(old_x as i32, old_y as i32).cloned() {
+    for (old, new) in movements {
+        if let Some(atom) = world.get_atom(old.x, old.y).cloned() {
             // Check if new position is valid
-            if world.is_empty(new_x, new_y) {
-                world.set_atom(old_x as i32, old_y as i32, Atom::default());
-                world.set_atom(new_x, new_y, atom);
-            } else {
+            if world.is_empty(new.x, new.y) {
+                world.set_atom(old.x, old.y, Atom::default());
+                world.set_atom(new.x, new.y, atom);
+            } else if let Some(atom) = world.atom_mut_untracked(old.x, old.y) {
                 // Collision - bounce or stop
-                let mut atom = atom;
                 atom.velocity *= -0.5; // Simple bounce with energy loss
-                world.atoms[old_y * world.width + old_x] = atom;
             }
         }
     }
 }
 
-fn apply_particle_interactions(world: &mut AtomWorld, dt: f32) {
+fn apply_particle_interactions(world: &mut AtomWorld, cells: &[IVec2], dt: f32) {
     // Optimized particle interactions as described in "Particles, for real this time"
     // Only process atoms that are moving or have recently moved
 
     let mut interaction_pairs = Vec::new();
 
     // Find atoms that might interact (moving atoms near other atoms)
-    for y in 0..world.height {
-        for x in 0..world.width {
-            let idx = y * world.width + x;
-            if world.atoms[idx].atom_type != AtomType::Empty {
-                let atom = &world.atoms[idx];
-
+    for &pos in cells.iter().rev() {
+        if let Some(atom) = world.get_atom(pos.x, pos.y) {
+            if atom.atom_type != AtomType::Empty {
                 // Only check moving atoms or atoms near moving objects
-                if atom.velocity.length_squared() > 0.1 || is_near_moving_object(world, x, y) {
+                if atom.velocity.length_squared() > 0.1 || is_near_moving_object(world, pos) {
                     // Check neighboring atoms for interactions
                     for dx in -2..=2 {
                         for dy in -2..=2 {
                             if dx == 0 && dy == 0 { continue; }
 
-                            if let Some(neighbor) = world.get_atom(x as i32 + dx, y as i32 + dy) {
+                            let neighbor_pos = pos + IVec2::new(dx, dy);
+                            if let Some(neighbor) = world.get_atom(neighbor_pos.x, neighbor_pos.y) {
                                 if neighbor.atom_type != AtomType::Empty {
-                                    interaction_pairs.push(((x, y), (x as i32 + dx, y as i32 + dy)));
+                                    interaction_pairs.push((pos, neighbor_pos));
                                 }
                             }
                         }
@@ -303,8 +1069,8 @@ fn apply_particle_interactions(world: &mut AtomWorld, dt: f32) {
     let max_interactions = 1000; // Configurable limit
     for (pos1, pos2) in interaction_pairs.into_iter().take(max_interactions) {
         if let (Some(atom1), Some(atom2)) = (
-            world.get_atom(pos1.0 as i32, pos1.1 as i32),
-            world.get_atom(pos2.0 as i32, pos2.1 as i32)
+            world.get_atom(pos1.x, pos1.y),
+            world.get_atom(pos2.x, pos2.y)
         ) {
             // Apply interaction forces
             let force = calculate_interaction_force(atom1, atom2, pos1, pos2);
@@ -314,7 +1080,7 @@ fn apply_particle_interactions(world: &mut AtomWorld, dt: f32) {
 }
 
 
-fn is_near_moving_object(world: &AtomWorld, x: usize, y: usize) -> bool {
+fn is_near_moving_object(world: &AtomWorld, pos: IVec2) -> bool {
     // Check if this atom is near a moving rigid body or fast-moving atom
     // This is a simplified check - in a real implementation, you'd integrate
     // with the physics system to check for nearby moving objects
@@ -322,7 +1088,7 @@ fn is_near_moving_object(world: &AtomWorld, x: usize, y: usize) -> bool {
     // For now, just check for atoms with high velocity nearby
     for dx in -5..=5 {
         for dy in -5..=5 {
-            if let Some(atom) = world.get_atom(x as i32 + dx, y as i32 + dy) {
+            if let Some(atom) = world.get_atom(pos.x + dx, pos.y + dy) {
                 if atom.velocity.length_squared() > 10.0 {
                     return true;
                 }
@@ -332,204 +1098,407 @@ fn is_near_moving_object(world: &AtomWorld, x: usize, y: usize) -> bool {
     false
 }
 
-fn calculate_interaction_force(atom1: &Atom, atom2: &Atom, pos1: (usize, usize), pos2: (i32, i32)) -> Vec2 {
-    let pos1_vec = Vec2::new(pos1.0 as f32, pos1.1 as f32);
-    let pos2_vec = Vec2::new(pos2.0 as f32, pos2.1 as f32);
+fn calculate_interaction_force(atom1: &Atom, atom2: &Atom, pos1: IVec2, pos2: IVec2) -> Vec2 {
+    let pos1_vec = pos1.as_vec2();
+    let pos2_vec = pos2.as_vec2();
     let direction = (pos2_vec - pos1_vec).normalize_or_zero();
     let distance = pos1_vec.distance(pos2_vec);
 
     if distance < 0.1 { return Vec2::ZERO; }
 
-    let mass1 = atom1.mass;
-    let mass2 = atom2.mass;
-
     // Repulsion force between atoms (prevents atoms from occupying same space)
     let repulsion_strength = 50.0;
-    let repulsion = direction * repulsion_strength / (distance * distance + 1.0);
-
-    // Attraction based on atom types (e.g., fire attracts flammable materials)
-    let attraction = match (atom1.atom_type, atom2.atom_type) {
-        (AtomType::Fire, _) if atom2.atom_type.can_burn() => direction * 10.0,
-        (_, AtomType::Fire) if atom1.atom_type.can_burn() => -direction * 10.0,
-        _ => Vec2::ZERO,
-    };
-
-    repulsion + attraction
+    direction * repulsion_strength / (distance * distance + 1.0)
 }
 
-fn apply_force_to_atoms(world: &mut AtomWorld, pos1: (usize, usize), pos2: (i32, i32), force: Vec2, dt: f32) {
-    let idx1 = pos1.1 * world.width + pos1.0;
-    let mass1 = world.atoms[idx1].mass;
-
-    if let Some(idx2) = world.get_index(pos2.0, pos2.1) {
-        let mass2 = world.atoms[idx2].mass;
+fn apply_force_to_atoms(world: &mut AtomWorld, pos1: IVec2, pos2: IVec2, force: Vec2, dt: f32) {
+    let (Some(mass1), Some(mass2)) = (
+        world.get_atom(pos1.x, pos1.y).map(|atom| atom.mass),
+        world.get_atom(pos2.x, pos2.y).map(|atom| atom.mass),
+    ) else {
+        return;
+    };
 
-        // Apply equal and opposite forces
-        if mass1 > 0.0 {
-            world.atoms[idx1].velocity += force * dt / mass1;
+    // Apply equal and opposite forces
+    if mass1 > 0.0 {
+        if let Some(atom) = world.atom_mut_untracked(pos1.x, pos1.y) {
+            atom.velocity += force * dt / mass1;
         }
-        if mass2 > 0.0 {
-            world.atoms[idx2].velocity -= force * dt / mass2;
+    }
+    if mass2 > 0.0 {
+        if let Some(atom) = world.atom_mut_untracked(pos2.x, pos2.y) {
+            atom.velocity -= force * dt / mass2;
         }
     }
 }
 
 fn update_atom(world: &mut AtomWorld, x: i32, y: i32, dt: f32) {
-    let idx = match world.get_index(x, y) {
-        Some(idx) => idx,
+    if world.is_updated(x, y) {
+        return;
+    }
+
+    let atom_type = match world.get_atom(x, y) {
+        Some(atom) => atom.atom_type,
         None => return,
     };
-
-    if world.updated[idx] {
+    if atom_type == AtomType::Empty {
         return;
     }
 
-    let atom = world.atoms[idx].clone();
-    if atom.atom_type == AtomType::Empty {
-        return;
+    let material = world.materials.get(atom_type);
+    let (behaviour, heat_source) = (material.behaviour, material.heat_source);
+    match behaviour {
+        Behaviour::Powder => update_sand(world, x, y),
+        Behaviour::Liquid => update_water(world, x, y),
+        Behaviour::Acid => update_acid(world, x, y),
+        Behaviour::Fire => update_fire(world, x, y),
+        Behaviour::Gas => update_gas(world, x, y),
+        Behaviour::Poison => update_poison(world, x, y),
+        Behaviour::Static => {} // Stone and other static atoms don't move
     }
 
-    match atom.atom_type {
-        AtomType::Sand => update_sand(world, x, y),
-        AtomType::Water => update_water(world, x, y),
-        AtomType::Acid => update_acid(world, x, y),
-        AtomType::Fire => update_fire(world, x, y),
-        AtomType::Smoke | AtomType::Steam => update_gas(world, x, y),
-        AtomType::Poison => update_poison(world, x, y),
-        _ => {} // Stone and other static atoms don't move
+    // Heat sources stay awake so they keep warming their surroundings
+    if heat_source.is_some() {
+        world.mark_dirty(x, y);
     }
 
     // Update lifetime for temporary atoms
-    if let Some(lifetime) = world.atoms[idx].lifetime.as_mut() {
-        *lifetime -= dt;
-        if *lifetime <= 0.0 {
-            world.atoms[idx] = Atom::default();
+    let expired = match world.atom_mut_untracked(x, y).and_then(|atom| atom.lifetime.as_mut()) {
+        Some(lifetime) => {
+            *lifetime -= dt;
+            *lifetime <= 0.0
         }
+        None => return,
+    };
+
+    if expired {
+        world.set_atom(x, y, Atom::default());
+    } else {
+        // Keep ticking atoms scheduled until they expire
+        world.mark_dirty(x, y);
     }
 }
 
-// Simple heat diffusion between neighboring atoms.
-fn apply_heat_transfer(world: &mut AtomWorld, dt: f32) {
+// Simple heat diffusion between neighboring atoms, weighted by heat capacity.
+// Heat sources like fire pin themselves hot first, and atoms next to empty
+// space slowly lose heat to the surroundings.
+fn apply_heat_transfer(world: &mut AtomWorld, cells: &[IVec2], dt: f32) {
+    let materials = Arc::clone(&world.materials);
+    for &pos in cells {
+        if let Some(atom) = world.atom_mut_untracked(pos.x, pos.y) {
+            if let Some(source) = materials.get(atom.atom_type).heat_source {
+                atom.temperature = atom.temperature.max(source);
+            }
+        }
+    }
+
     // Collect temperature deltas to avoid in-place interference.
-    let mut temp_changes = vec![0.0f32; world.atoms.len()];
+    let mut temp_changes: HashMap<IVec2, f32> = HashMap::default();
     let conductivity = 2.0; // tweakable conductivity coefficient
+    let ambient_conductivity = 0.2;
+
+    for &pos in cells {
+        let atom = match world.get_atom(pos.x, pos.y) {
+            Some(atom) if atom.atom_type != AtomType::Empty => atom,
+            _ => continue,
+        };
+        let heat_capacity = materials.get(atom.atom_type).heat_capacity.max(0.1);
+
+        // 4-neighborhood diffusion
+        for offset in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+            let neighbor_pos = pos + offset;
+            if let Some(neighbor) = world.get_atom(neighbor_pos.x, neighbor_pos.y) {
+                if neighbor.atom_type == AtomType::Empty {
+                    let flow = (atom.temperature - AMBIENT_TEMPERATURE) * ambient_conductivity * dt;
+                    *temp_changes.entry(pos).or_default() -= flow / heat_capacity;
+                    continue;
+                }
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            let idx = y * world.width + x;
-            let atom = &world.atoms[idx];
-            if atom.atom_type == AtomType::Empty {
-                continue;
+                let temp_diff = atom.temperature - neighbor.temperature;
+                // Heat flows from hot to cold
+                let flow = temp_diff * conductivity * dt;
+                *temp_changes.entry(pos).or_default() -= flow / heat_capacity;
+                *temp_changes.entry(neighbor_pos).or_default() += flow / materials.get(neighbor.atom_type).heat_capacity.max(0.1);
             }
+        }
+    }
 
-            // 4-neighborhood diffusion
-            let neighbors = [(1, 0), (-1, 0), (0, 1), (0, -1)];
-            for (dx, dy) in neighbors {
-                if let Some(nidx) = world.get_index(x as i32 + dx, y as i32 + dy) {
-                    let neighbor = &world.atoms[nidx];
-                    if neighbor.atom_type == AtomType::Empty {
-                        continue;
-                    }
+    // Apply accumulated temperature changes
+    for (pos, delta) in temp_changes {
+        let mut glowing = false;
+        if let Some(atom) = world.atom_mut_untracked(pos.x, pos.y) {
+            atom.temperature += delta;
+            glowing = atom.temperature > GLOW_TEMPERATURE;
+        }
+        if glowing {
+            world.mark_changed(pos.x, pos.y);
+        }
+        // Noticeable heating wakes the atom so it can react to its new temperature
+        if delta.abs() > 1.0 && world.is_sleeping(pos.x, pos.y) {
+            world.mark_dirty(pos.x, pos.y);
+        }
+    }
+}
+
+fn apply_phase_transitions(world: &mut AtomWorld, cells: &[IVec2]) {
+    for &pos in cells {
+        let Some(atom) = world.get_atom(pos.x, pos.y) else {
+            continue;
+        };
+        let temperature = atom.temperature;
+        let transition = world.materials.get(atom.atom_type).transition_at(temperature);
+
+        if let Some((target, lifetime)) = transition {
+            let mass = world.materials.get(target).mass;
+            if let Some(atom) = world.get_atom_mut(pos.x, pos.y) {
+                atom.atom_type = target;
+                atom.mass = mass;
+                atom.lifetime = lifetime;
+                atom.burning = false;
+                atom.fuel = None;
+            }
+        }
+    }
+}
 
-                    let temp_diff = atom.temperature - neighbor.temperature;
-                    // Heat flows from hot to cold
-                    let flow = temp_diff * conductivity * dt;
-                    temp_changes[idx] -= flow;
-                    temp_changes[nidx] += flow;
+// Chance per tick that a burning atom sheds a flame or a puff of smoke upwards
+const FLAME_CHANCE: f32 = 0.05;
+const SMOKE_CHANCE: f32 = 0.1;
+
+// Unlit flammable atoms catch once they are hot enough. Burning atoms hold their
+// burn temperature (which is what spreads the fire), shed flames and smoke and
+// use up their fuel, then turn into their residue. Fire needs an empty neighbour
+// to draw air from, so anything sealed in or smothered by smoke goes out, and so
+// does anything touching water. Fuel that was used up stays used up, so a fire
+// that keeps getting smothered doesn't burn forever.
+fn apply_combustion(world: &mut AtomWorld, cells: &[IVec2], dt: f32) {
+    let materials = Arc::clone(&world.materials);
+    for &pos in cells {
+        let Some(atom) = world.get_atom(pos.x, pos.y) else {
+            continue;
+        };
+        let (temperature, burning, fuel_left) = (atom.temperature, atom.burning, atom.fuel);
+        let Some((ignition, flammability, fuel, burn_temperature, residue, residue_lifetime)) =
+            materials.get(atom.atom_type).combustion.as_ref().map(|c| {
+                (c.ignition, c.flammability, c.fuel, c.burn_temperature, c.residue_type, c.residue_lifetime)
+            })
+        else {
+            continue;
+        };
+
+        let (air, quenched) = combustion_surroundings(world, pos);
+        let can_burn = air > 0 && !quenched;
+
+        if !burning {
+            if can_burn && temperature >= ignition {
+                if world.rng.gen::<f32>() < flammability {
+                    if let Some(atom) = world.get_atom_mut(pos.x, pos.y) {
+                        atom.burning = true;
+                        atom.fuel = Some(fuel_left.unwrap_or(fuel));
+                    }
+                } else {
+                    // Stay awake to try again next tick
+                    world.mark_dirty(pos.x, pos.y);
                 }
             }
+            continue;
+        }
+
+        if !can_burn {
+            // Smothered atoms cool off so they don't relight the moment air returns
+            if let Some(atom) = world.get_atom_mut(pos.x, pos.y) {
+                atom.burning = false;
+                atom.temperature = atom.temperature.min(ignition * 0.5);
+            }
+            continue;
+        }
+
+        let remaining = fuel_left.unwrap_or(fuel) - dt;
+        if remaining <= 0.0 {
+            world.set_atom(pos.x, pos.y, Atom {
+                atom_type: residue,
+                velocity: Vec2::ZERO,
+                mass: materials.get(residue).mass,
+                lifetime: residue_lifetime,
+                temperature,
+                ..default()
+            });
+            continue;
+        }
+        if let Some(atom) = world.get_atom_mut(pos.x, pos.y) {
+            atom.fuel = Some(remaining);
+            atom.temperature = atom.temperature.max(burn_temperature);
+        }
+
+        let above = pos + IVec2::NEG_Y;
+        if world.is_empty(above.x, above.y) {
+            let roll = world.rng.gen::<f32>();
+            let (atom_type, lifetime) = if roll < FLAME_CHANCE {
+                (AtomType::Fire, 0.5)
+            } else if roll < FLAME_CHANCE + SMOKE_CHANCE {
+                (AtomType::Smoke, 8.0)
+            } else {
+                continue;
+            };
+            world.set_atom(above.x, above.y, Atom {
+                atom_type,
+                velocity: Vec2::ZERO,
+                mass: materials.get(atom_type).mass,
+                lifetime: Some(lifetime),
+                temperature: burn_temperature,
+                ..default()
+            });
         }
     }
+}
 
-    // Apply accumulated temperature changes
-    for (atom, delta) in world.atoms.iter_mut().zip(temp_changes.into_iter()) {
-        atom.temperature += delta;
+// (neighbours to draw air from, whether a neighbour puts fire out)
+fn combustion_surroundings(world: &AtomWorld, pos: IVec2) -> (usize, bool) {
+    let mut air = 0;
+    let mut quenched = false;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if dx == 0 && dy == 0 {
+                continue;
+            }
+            let Some(neighbor) = world.get_atom(pos.x + dx, pos.y + dy) else {
+                continue;
+            };
+            let neighbor_type = neighbor.atom_type;
+            // An atom's own flames don't choke it, but smoke does
+            if neighbor_type == AtomType::Empty || neighbor_type == AtomType::Fire {
+                air += 1;
+            } else {
+                let material = world.materials.get(neighbor_type);
+                if material.phase == Phase::Liquid && !material.can_burn() && material.heat_source.is_none() {
+                    quenched = true;
+                }
+            }
+        }
     }
+    (air, quenched)
 }
 
 fn update_sand(world: &mut AtomWorld, x: i32, y: i32) {
-    let idx = world.get_index(x, y).unwrap();
+    let velocity_x = world.get_atom(x, y).map_or(0.0, |atom| atom.velocity.x);
 
     // Sand falls down, but also responds to velocity
-    if world.is_empty(x, y + 1) {
+    if world.can_move_into(x, y, x, y + 1) {
         world.swap_atoms(x, y, x, y + 1);
-    } else if world.atoms[idx].velocity.x > 0.1 && world.is_empty(x + 1, y) {
+    } else if velocity_x > 0.1 && world.can_move_into(x, y, x + 1, y) {
         world.swap_atoms(x, y, x + 1, y);
-    } else if world.atoms[idx].velocity.x < -0.1 && world.is_empty(x - 1, y) {
+    } else if velocity_x < -0.1 && world.can_move_into(x, y, x - 1, y) {
         world.swap_atoms(x, y, x - 1, y);
-    } else if world.is_empty(x - 1, y + 1) {
+    } else if world.can_move_into(x, y, x - 1, y + 1) {
         world.swap_atoms(x, y, x - 1, y + 1);
-    } else if world.is_empty(x + 1, y + 1) {
+    } else if world.can_move_into(x, y, x + 1, y + 1) {
         world.swap_atoms(x, y, x + 1, y + 1);
     }
 }
 
 fn update_water(world: &mut AtomWorld, x: i32, y: i32) {
     // Water falls down or flows sideways
-    if world.is_empty(x, y + 1) {
+    if world.can_move_into(x, y, x, y + 1) {
         world.swap_atoms(x, y, x, y + 1);
     } else {
-        let mut rng = rand::thread_rng();
-        let dir = if rng.gen_bool(0.5) { -1 } else { 1 };
+        // Viscous liquids like lava only spread on some ticks
+        let viscosity = world.get_atom(x, y).map_or(0.0, |atom| world.materials.get(atom.atom_type).viscosity);
+        if viscosity > 0.0 && world.rng.gen::<f32>() < viscosity {
+            return;
+        }
+
+        let dir = if world.rng.gen_bool(0.5) { -1 } else { 1 };
 
-        if world.is_empty(x + dir, y) {
+        if world.can_move_into(x, y, x + dir, y) {
             world.swap_atoms(x, y, x + dir, y);
-        } else if world.is_empty(x - dir, y) {
+        } else if world.can_move_into(x, y, x - dir, y) {
             world.swap_atoms(x, y, x - dir, y);
         }
     }
 }
 
 fn update_acid(world: &mut AtomWorld, x: i32, y: i32) {
-    // Acid behaves like water but corrodes things
-    update_water(world, x, y);
+    // Acid corrodes its neighbours, then behaves like water if it wasn't used up
+    if !corrode_neighbours(world, x, y) {
+        update_water(world, x, y);
+    }
+}
+
+// Dissolve at most one neighbour per tick, weighted by its corrosion resistance.
+// Returns true if the corrosive atom was used up doing so.
+fn corrode_neighbours(world: &mut AtomWorld, x: i32, y: i32) -> bool {
+    let materials = Arc::clone(&world.materials);
+    let Some(atom) = world.get_atom(x, y) else {
+        return false;
+    };
+    let Some((strength, consumption, byproduct, byproduct_lifetime)) = materials
+        .get(atom.atom_type)
+        .corrosion
+        .as_ref()
+        .map(|c| (c.strength, c.consumption, c.byproduct_type, c.byproduct_lifetime))
+    else {
+        return false;
+    };
+
+    // Below first, so acid eats its way down
+    for offset in [IVec2::Y, IVec2::X, IVec2::NEG_X, IVec2::NEG_Y] {
+        let target = IVec2::new(x, y) + offset;
+        let Some(neighbor) = world.get_atom(target.x, target.y) else {
+            continue;
+        };
+        let Some(resistance) = materials.get(neighbor.atom_type).corrosion_resistance else {
+            continue;
+        };
+        let temperature = neighbor.temperature;
+        if world.rng.gen::<f32>() >= strength * (1.0 - resistance) {
+            continue;
+        }
 
-    // Corrosion logic will be added with reactions
+        world.set_atom(target.x, target.y, Atom {
+            atom_type: byproduct,
+            velocity: Vec2::ZERO,
+            mass: materials.get(byproduct).mass,
+            lifetime: byproduct_lifetime,
+            temperature,
+            ..default()
+        });
+
+        if world.rng.gen::<f32>() < consumption {
+            world.set_atom(x, y, Atom::default());
+            return true;
+        }
+        return false;
+    }
+    false
 }
 
 fn update_fire(world: &mut AtomWorld, x: i32, y: i32) {
     // Fire rises and spreads
-    if world.is_empty(x, y - 1) {
+    if world.can_move_into(x, y, x, y - 1) {
         world.swap_atoms(x, y, x, y - 1);
     } else {
-        let mut rng = rand::thread_rng();
-        let dir = if rng.gen_bool(0.5) { -1 } else { 1 };
+        let dir = if world.rng.gen_bool(0.5) { -1 } else { 1 };
 
-        if world.is_empty(x + dir, y - 1) {
+        if world.can_move_into(x, y, x + dir, y - 1) {
             world.swap_atoms(x, y, x + dir, y - 1);
-        } else if world.is_empty(x - dir, y - 1) {
+        } else if world.can_move_into(x, y, x - dir, y - 1) {
             world.swap_atoms(x, y, x - dir, y - 1);
         }
     }
-
-    // Fire spreads to flammable materials
-    for dx in -1..=1 {
-        for dy in -1..=1 {
-            if dx == 0 && dy == 0 { continue; }
-            if let Some(atom) = world.get_atom(x + dx, y + dy) {
-                if atom.atom_type.can_burn() && rand::random::<f32>() < 0.01 {
-                    // Chance to ignite
-                    if let Some(mut_atom) = world.get_atom_mut(x + dx, y + dy) {
-                        mut_atom.atom_type = AtomType::Fire;
-                        mut_atom.lifetime = Some(5.0); // Fire burns for 5 seconds
-                    }
-                }
-            }
-        }
-    }
+    // Fire is a heat source, so it spreads by heating flammable neighbours past
+    // their ignition point (see apply_combustion)
 }
 
 fn update_gas(world: &mut AtomWorld, x: i32, y: i32) {
     // Gases rise
-    if world.is_empty(x, y - 1) {
+    if world.can_move_into(x, y, x, y - 1) {
         world.swap_atoms(x, y, x, y - 1);
     } else {
-        let mut rng = rand::thread_rng();
-        let dir = if rng.gen_bool(0.5) { -1 } else { 1 };
+        let dir = if world.rng.gen_bool(0.5) { -1 } else { 1 };
 
-        if world.is_empty(x + dir, y - 1) {
+        if world.can_move_into(x, y, x + dir, y - 1) {
             world.swap_atoms(x, y, x + dir, y - 1);
-        } else if world.is_empty(x - dir, y - 1) {
+        } else if world.can_move_into(x, y, x - dir, y - 1) {
             world.swap_atoms(x, y, x - dir, y - 1);
         }
     }
@@ -540,78 +1509,641 @@ fn update_poison(world: &mut AtomWorld, x: i32, y: i32) {
     // Poison logic will be expanded
 }
 
-// Chemical reactions between atoms
-pub fn process_reactions(mut world: ResMut<AtomWorldResource>) {
-    let world = &mut world.0;
-    let mut reactions = Vec::new();
+// Chemical reactions between atoms, driven by the registry's reaction table
+pub fn process_reactions(
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut rng: ResMut<SimulationRng>,
+) {
+    apply_reactions(&mut world.0, &materials.0, rng.rng());
+}
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            let idx = y * world.width + x;
-            let atom = &world.atoms[idx];
+pub fn apply_reactions(world: &mut AtomWorld, materials: &MaterialRegistry, rng: &mut DeterministicRandom) {
+    // Each cell takes part in at most one reaction per tick, and each touching
+    // pair gets one roll, whichever side of it is looked at first
+    let mut reacted: HashSet<IVec2> = HashSet::default();
+    let mut rolled: HashSet<(IVec2, IVec2)> = HashSet::default();
 
-            if atom.atom_type == AtomType::Empty {
-                continue;
-            }
+    for &pos in world.dirty_cells().iter().rev() {
+        if reacted.contains(&pos) {
+            continue;
+        }
 
-            // Check neighboring atoms for reactions
-            for dx in -1..=1 {
-                for dy in -1..=1 {
-                    if dx == 0 && dy == 0 { continue; }
+        // Check neighboring atoms for reactions
+        'neighbours: for dx in -1..=1 {
+            for dy in -1..=1 {
+                if dx == 0 && dy == 0 { continue; }
 
-                    if let Some(neighbor) = world.get_atom(x as i32 + dx, y as i32 + dy) {
-                        let reaction = check_reaction(atom.atom_type, neighbor.atom_type);
-                        if reaction.is_some() {
-                            reactions.push(((x as i32, y as i32), (x as i32 + dx, y as i32 + dy), reaction.unwrap()));
-                        }
-                    }
+                let neighbor_pos = pos + IVec2::new(dx, dy);
+                if reacted.contains(&neighbor_pos) {
+                    continue;
+                }
+
+                let (Some(atom), Some(neighbor)) = (
+                    world.get_atom(pos.x, pos.y),
+                    world.get_atom(neighbor_pos.x, neighbor_pos.y),
+                ) else {
+                    continue;
+                };
+                let Some(reaction) = materials.reaction(atom.atom_type, neighbor.atom_type) else {
+                    continue;
+                };
+                if !reaction.can_occur(atom.temperature, neighbor.temperature) {
+                    continue;
+                }
+                let pair = (pos.min(neighbor_pos), pos.max(neighbor_pos));
+                if !rolled.insert(pair) {
+                    continue;
                 }
+
+                let temperature = (atom.temperature + neighbor.temperature) / 2.0;
+                if rng.gen::<f32>() < reaction.probability {
+                    apply_reaction(world, materials, reaction, pos, neighbor_pos, temperature);
+                    reacted.insert(pos);
+                    reacted.insert(neighbor_pos);
+                    break 'neighbours;
+                }
+
+                // Keep reactive contacts awake so the pair gets another roll next tick
+                world.mark_dirty(pos.x, pos.y);
             }
         }
     }
+}
 
-    // Apply reactions
-    for ((x1, y1), (x2, y2), products) in reactions {
-        for product in products {
-            // Find empty space for new atoms
+fn apply_reaction(
+    world: &mut AtomWorld,
+    materials: &MaterialRegistry,
+    reaction: &Reaction,
+    pos_a: IVec2,
+    pos_b: IVec2,
+    temperature: f32,
+) {
+    for product in &reaction.products {
+        let target = match product.placement {
+            Placement::ReplaceA => Some(pos_a),
+            Placement::ReplaceB => Some(pos_b),
+            Placement::SpawnNearby => find_empty_near(world, pos_a, pos_b),
+        };
+        let Some(target) = target else {
+            continue; // No room for this product
+        };
+
+        let material = materials.get(product.atom_type);
+        let heat_capacity = material.heat_capacity.max(0.1);
+        world.set_atom(target.x, target.y, Atom {
+            atom_type: product.atom_type,
+            velocity: Vec2::ZERO,
+            mass: material.mass,
+            lifetime: product.lifetime,
+            temperature: temperature + reaction.energy / heat_capacity,
+            ..default()
+        });
+    }
+
+    if let Some(radius) = reaction.explosion {
+        world.queue_explosion(Explosion::new(pos_a, radius));
+    }
+
+    // Reactants that survive still feel the released energy
+    for pos in [pos_a, pos_b] {
+        if let Some(atom) = world.atom_mut_untracked(pos.x, pos.y) {
+            if atom.atom_type == reaction.a || atom.atom_type == reaction.b {
+                let heat_capacity = materials.get(atom.atom_type).heat_capacity.max(0.1);
+                atom.temperature += reaction.energy / heat_capacity;
+            }
+        }
+    }
+}
+
+fn find_empty_near(world: &AtomWorld, pos_a: IVec2, pos_b: IVec2) -> Option<IVec2> {
+    for center in [pos_a, pos_b] {
+        for dy in -1..=1 {
             for dx in -1..=1 {
-                for dy in -1..=1 {
-                    let nx = x1 + dx;
-                    let ny = y1 + dy;
-                    if world.is_empty(nx, ny) {
-                        world.set_atom(nx, ny, Atom {
-                            atom_type: product.0,
-                            velocity: Vec2::ZERO,
-                            mass: product.0.mass(),
-                            lifetime: product.1,
-                            temperature: 20.0,
-                        });
-                        break;
-                    }
+                let pos = center + IVec2::new(dx, dy);
+                if world.get_atom(pos.x, pos.y).is_some_and(|atom| atom.atom_type == AtomType::Empty) {
+                    return Some(pos);
                 }
             }
         }
-
-        // Remove reactants
-        world.set_atom(x1, y1, Atom::default());
-        world.set_atom(x2, y2, Atom::default());
     }
+    None
 }
 
-fn check_reaction(atom1: AtomType, atom2: AtomType) -> Option<Vec<(AtomType, Option<f32>)>> {
-    match (atom1, atom2) {
-        (AtomType::Fire, AtomType::Water) | (AtomType::Water, AtomType::Fire) => {
-            // Fire + Water = Steam
-            Some(vec![(AtomType::Steam, Some(3.0))])
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 30;
+
+    fn atom(materials: &MaterialRegistry, atom_type: AtomType) -> Atom {
+        Atom {
+            atom_type,
+            mass: materials.get(atom_type).mass,
+            temperature: AMBIENT_TEMPERATURE,
+            ..default()
+        }
+    }
+
+    // A 4x4-chunk world with a stone floor and a band of sand and water falling
+    // onto it, laid out with draws from `rng` so it depends on the seed
+    fn seeded_world(rng: &mut SimulationRng) -> AtomWorld {
+        let size = CHUNK_SIZE * 4;
+        let falling = size - CHUNK_SIZE - 8..size - CHUNK_SIZE;
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(size as usize, size as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let roll = rng.rng().gen::<f32>();
+                let atom_type = if y >= size - 4 {
+                    AtomType::Stone
+                } else if falling.contains(&y) && roll < 0.2 {
+                    AtomType::Sand
+                } else if falling.contains(&y) && roll < 0.35 {
+                    AtomType::Water
+                } else {
+                    continue;
+                };
+                world.set_atom(x, y, atom(&materials, atom_type));
+            }
+        }
+        world
+    }
+
+    fn run(world: &mut AtomWorld, rng: &mut SimulationRng, settings: &SimulationSettings, steps: usize) {
+        run_with(world, &Arc::new(MaterialRegistry::builtin()), rng, settings, steps);
+    }
+
+    fn run_with(
+        world: &mut AtomWorld,
+        materials: &Arc<MaterialRegistry>,
+        rng: &mut SimulationRng,
+        settings: &SimulationSettings,
+        steps: usize,
+    ) {
+        for _ in 0..steps {
+            world.step(materials, settings, rng, SIMULATION_DT);
+        }
+    }
+
+    // One chunk of empty space, for setting small scenes up in
+    fn small_world() -> AtomWorld {
+        AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize)
+    }
+
+    // Fill the cells from `min` to `max` (inclusive) with `atom_type`
+    fn fill(world: &mut AtomWorld, materials: &MaterialRegistry, min: IVec2, max: IVec2, atom_type: AtomType) {
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                world.set_atom(x, y, atom(materials, atom_type));
+            }
+        }
+    }
+
+    // A single atom at `cell` walled in by stone on every side
+    fn sealed(world: &mut AtomWorld, materials: &MaterialRegistry, cell: IVec2, inner: Atom) {
+        fill(world, materials, cell - IVec2::ONE, cell + IVec2::ONE, AtomType::Stone);
+        world.set_atom(cell.x, cell.y, inner);
+    }
+
+    fn count(world: &AtomWorld, atom_type: AtomType) -> usize {
+        world
+            .chunks
+            .values()
+            .flat_map(|chunk| &chunk.atoms)
+            .filter(|atom| atom.atom_type == atom_type)
+            .count()
+    }
+
+    fn type_at(world: &AtomWorld, cell: IVec2) -> AtomType {
+        world.get_atom(cell.x, cell.y).map_or(AtomType::Empty, |atom| atom.atom_type)
+    }
+
+    #[test]
+    fn same_seed_gives_same_grid() {
+        let settings = SimulationSettings::default();
+        let mut rng_a = SimulationRng::new(42);
+        let mut rng_b = SimulationRng::new(42);
+        let mut world_a = seeded_world(&mut rng_a);
+        let mut world_b = seeded_world(&mut rng_b);
+
+        run(&mut world_a, &mut rng_a, &settings, STEPS);
+        run(&mut world_b, &mut rng_b, &settings, STEPS);
+
+        assert_eq!(world_a.grid_hash(), world_b.grid_hash());
+    }
+
+    #[test]
+    fn parallel_passes_match_serial_passes() {
+        let mut rng_serial = SimulationRng::new(1234);
+        let mut rng_parallel = SimulationRng::new(1234);
+        let mut serial = seeded_world(&mut rng_serial);
+        let mut parallel = seeded_world(&mut rng_parallel);
+
+        run(&mut serial, &mut rng_serial, &SimulationSettings { parallel: false }, STEPS);
+        run(&mut parallel, &mut rng_parallel, &SimulationSettings { parallel: true }, STEPS);
+
+        assert_eq!(serial.grid_hash(), parallel.grid_hash());
+    }
+
+    #[test]
+    fn restored_snapshot_replays_the_same_steps() {
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(7);
+        let mut world = seeded_world(&mut rng);
+        run(&mut world, &mut rng, &settings, STEPS / 2);
+
+        let saved_world = world.clone();
+        let saved_rng = rng.snapshot();
+        run(&mut world, &mut rng, &settings, STEPS);
+        let first = world.grid_hash();
+        assert_ne!(first, saved_world.grid_hash(), "nothing moved");
+
+        let mut world = saved_world;
+        rng.restore(&saved_rng);
+        run(&mut world, &mut rng, &settings, STEPS);
+        assert_eq!(world.grid_hash(), first);
+    }
+
+    // Sand that turns to stone against hot water, giving off smoke, and a pair
+    // that never reacts
+    const REACTIONS: &str = r#"{
+        "materials": [],
+        "reactions": [
+            {
+                "a": "sand", "b": "water", "probability": 1.0, "min_temperature": 200.0, "energy": 50.0,
+                "products": [
+                    { "material": "stone", "placement": "replace_a" },
+                    { "material": "smoke", "placement": "spawn_nearby", "lifetime": 1.0 }
+                ]
+            },
+            {
+                "a": "sand", "b": "oil", "probability": 0.0,
+                "products": [{ "material": "empty", "placement": "replace_b" }]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn reactions_wait_for_their_temperature_and_place_their_products() {
+        let materials = MaterialRegistry::from_json(REACTIONS).unwrap();
+        let (sand, water) = (IVec2::new(10, 10), IVec2::new(11, 10));
+        let mut world = small_world();
+        world.set_atom(sand.x, sand.y, atom(&materials, AtomType::Sand));
+        world.set_atom(water.x, water.y, atom(&materials, AtomType::Water));
+        let mut rng = DeterministicRandom::new(3);
+
+        world.begin_step();
+        apply_reactions(&mut world, &materials, &mut rng);
+        assert_eq!(type_at(&world, sand), AtomType::Sand, "too cold to react");
+
+        world.get_atom_mut(water.x, water.y).unwrap().temperature = 300.0;
+        world.begin_step();
+        apply_reactions(&mut world, &materials, &mut rng);
+
+        assert_eq!(type_at(&world, sand), AtomType::Stone);
+        assert_eq!(type_at(&world, water), AtomType::Water, "B has no product and is left alone");
+        assert_eq!(count(&world, AtomType::Smoke), 1);
+        let expected = (AMBIENT_TEMPERATURE + 300.0) / 2.0 + 50.0 / materials.get(AtomType::Stone).heat_capacity;
+        assert!((world.get_atom(sand.x, sand.y).unwrap().temperature - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reactions_roll_their_probability() {
+        let materials = MaterialRegistry::from_json(REACTIONS).unwrap();
+        let oil = materials.by_name("oil").unwrap();
+        let mut world = small_world();
+        world.set_atom(10, 10, atom(&materials, AtomType::Sand));
+        world.set_atom(11, 10, atom(&materials, oil));
+        let mut rng = DeterministicRandom::new(3);
+
+        for _ in 0..100 {
+            world.begin_step();
+            apply_reactions(&mut world, &materials, &mut rng);
         }
-        (AtomType::Acid, AtomType::Water) | (AtomType::Water, AtomType::Acid) => {
-            // Acid + Water = Poison
-            Some(vec![(AtomType::Poison, None)])
+        assert_eq!(count(&world, oil), 1, "a 0% reaction went off");
+
+        // Fire puts water out 30% of the time: one roll per pair, from whichever
+        // side gets there first
+        let mut world = small_world();
+        let pairs: Vec<IVec2> = (0..16).flat_map(|y| (0..10).map(move |x| IVec2::new(x * 6, y * 4))).collect();
+        for &cell in &pairs {
+            world.set_atom(cell.x, cell.y, atom(&materials, AtomType::Fire));
+            world.set_atom(cell.x + 1, cell.y, atom(&materials, AtomType::Water));
         }
-        (AtomType::Fire, AtomType::Sand) | (AtomType::Sand, AtomType::Fire) => {
-            // Fire + Sand = Smoke (when sand is flammable)
-            Some(vec![(AtomType::Smoke, Some(2.0))])
+        world.begin_step();
+        apply_reactions(&mut world, &materials, &mut rng);
+
+        let put_out = pairs.len() - count(&world, AtomType::Fire);
+        assert!((30..=66).contains(&put_out), "{put_out} of {} pairs reacted", pairs.len());
+        assert_eq!(count(&world, AtomType::Steam), put_out);
+    }
+
+    #[test]
+    fn atoms_change_phase_past_their_thresholds() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(5);
+        let ice = materials.by_name("ice").unwrap();
+        let (hot, cold, cooled) = (IVec2::new(8, 8), IVec2::new(16, 8), IVec2::new(24, 8));
+        let mut world = small_world();
+        sealed(&mut world, &materials, hot, Atom { temperature: 150.0, ..atom(&materials, AtomType::Water) });
+        sealed(&mut world, &materials, cold, Atom { temperature: -10.0, ..atom(&materials, AtomType::Water) });
+        sealed(&mut world, &materials, cooled, Atom { temperature: 50.0, ..atom(&materials, AtomType::Steam) });
+
+        run_with(&mut world, &materials, &mut rng, &settings, 1);
+
+        assert_eq!(type_at(&world, hot), AtomType::Steam, "water boils");
+        assert_eq!(type_at(&world, cold), ice, "water freezes");
+        assert_eq!(type_at(&world, cooled), AtomType::Water, "steam condenses");
+        let steam = world.get_atom(hot.x, hot.y).unwrap();
+        assert_eq!(steam.mass, materials.get(AtomType::Steam).mass);
+    }
+
+    #[test]
+    fn heat_sources_boil_water_through_a_wall() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(5);
+        let lava = materials.by_name("lava").unwrap();
+        let mut world = small_world();
+        // A stone tank of water standing on a stone slab, with lava underneath
+        fill(&mut world, &materials, IVec2::new(4, 20), IVec2::new(12, 30), AtomType::Stone);
+        fill(&mut world, &materials, IVec2::new(5, 20), IVec2::new(11, 26), AtomType::Empty);
+        fill(&mut world, &materials, IVec2::new(5, 25), IVec2::new(11, 26), AtomType::Water);
+        fill(&mut world, &materials, IVec2::new(5, 28), IVec2::new(11, 29), lava);
+        assert_eq!(count(&world, AtomType::Steam), 0);
+
+        let boiled = (1..=600).find(|_| {
+            run_with(&mut world, &materials, &mut rng, &settings, 1);
+            count(&world, AtomType::Steam) > 0
+        });
+        assert!(boiled.is_some(), "the water never boiled");
+        assert_eq!(count(&world, lava), 14, "the lava stays put under the slab");
+    }
+
+    // Stone that acid can't touch
+    const ACID_PROOF_STONE: &str = r#"{
+        "materials": [
+            { "name": "stone", "phase": "solid", "behaviour": "static", "color": [0.4, 0.4, 0.4, 1.0], "mass": 2.5, "density": 2.5,
+              "friction": 0.9, "heat_capacity": 0.8, "corrosion_resistance": 1.0 }
+        ]
+    }"#;
+
+    #[test]
+    fn acid_cannot_eat_fully_resistant_materials() {
+        let materials = Arc::new(MaterialRegistry::from_json(ACID_PROOF_STONE).unwrap());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(11);
+        let mut world = small_world();
+        sealed(&mut world, &materials, IVec2::new(10, 10), atom(&materials, AtomType::Acid));
+
+        run_with(&mut world, &materials, &mut rng, &settings, 300);
+
+        assert_eq!(count(&world, AtomType::Stone), 8);
+        assert_eq!(count(&world, AtomType::Acid), 1);
+    }
+
+    #[test]
+    fn acid_dissolves_what_it_touches_and_is_used_up() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(11);
+        let mut world = small_world();
+        // A one-wide stone shaft holding a column of sand with a drop of acid on top
+        fill(&mut world, &materials, IVec2::new(9, 10), IVec2::new(11, 31), AtomType::Stone);
+        fill(&mut world, &materials, IVec2::new(10, 10), IVec2::new(10, 30), AtomType::Empty);
+        fill(&mut world, &materials, IVec2::new(10, 21), IVec2::new(10, 30), AtomType::Sand);
+        world.set_atom(10, 20, atom(&materials, AtomType::Acid));
+
+        let mut smoked = false;
+        for _ in 0..600 {
+            run_with(&mut world, &materials, &mut rng, &settings, 1);
+            smoked |= count(&world, AtomType::Smoke) > 0;
         }
-        _ => None,
+
+        assert_eq!(count(&world, AtomType::Acid), 0, "the acid was never used up");
+        assert!(count(&world, AtomType::Sand) < 10, "no sand was dissolved");
+        assert!(smoked, "dissolving gave off no smoke");
+    }
+
+    fn burning(materials: &MaterialRegistry, atom_type: AtomType, fuel: f32) -> Atom {
+        let burn_temperature = materials.get(atom_type).combustion.as_ref().unwrap().burn_temperature;
+        Atom { burning: true, fuel: Some(fuel), temperature: burn_temperature, ..atom(materials, atom_type) }
+    }
+
+    #[test]
+    fn sealed_fires_go_out_and_keep_their_fuel() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(2);
+        let wood = materials.by_name("wood").unwrap();
+        let cell = IVec2::new(10, 10);
+        let mut world = small_world();
+        sealed(&mut world, &materials, cell, burning(&materials, wood, 5.0));
+
+        run_with(&mut world, &materials, &mut rng, &settings, 1);
+
+        let atom = world.get_atom(cell.x, cell.y).unwrap();
+        assert_eq!(atom.atom_type, wood);
+        assert!(!atom.burning, "burned without air");
+        assert_eq!(atom.fuel, Some(5.0));
+        assert!(atom.temperature < materials.get(wood).combustion.as_ref().unwrap().ignition);
+    }
+
+    #[test]
+    fn fires_ignite_when_hot_and_burn_down_to_their_residue() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(2);
+        let wood = materials.by_name("wood").unwrap();
+        let ash = materials.by_name("ash").unwrap();
+        let (cold, hot, spent) = (IVec2::new(8, 30), IVec2::new(16, 30), IVec2::new(24, 30));
+        let mut world = small_world();
+        fill(&mut world, &materials, IVec2::new(0, 31), IVec2::new(31, 31), AtomType::Stone);
+        world.set_atom(cold.x, cold.y, atom(&materials, wood));
+        world.set_atom(hot.x, hot.y, Atom { temperature: 2000.0, ..atom(&materials, wood) });
+        // Walled in at the sides so the ash it leaves stays put
+        fill(&mut world, &materials, spent - IVec2::X, spent + IVec2::X, AtomType::Stone);
+        world.set_atom(spent.x, spent.y, burning(&materials, wood, 0.1));
+
+        run_with(&mut world, &materials, &mut rng, &settings, 60);
+
+        assert_eq!(type_at(&world, cold), wood);
+        assert!(!world.get_atom(cold.x, cold.y).unwrap().burning, "lit below its ignition point");
+        let lit = world.get_atom(hot.x, hot.y).unwrap();
+        assert!(lit.burning && lit.fuel.is_some_and(|fuel| fuel < 8.0), "never caught");
+        assert_eq!(type_at(&world, spent), ash, "ran out of fuel but kept burning");
+    }
+
+    // A one-wide stone shaft from y = 10 to y = 20, filled from the top down with `column`
+    fn shaft(materials: &MaterialRegistry, column: &[AtomType]) -> AtomWorld {
+        let mut world = small_world();
+        fill(&mut world, materials, IVec2::new(9, 10), IVec2::new(11, 21), AtomType::Stone);
+        fill(&mut world, materials, IVec2::new(10, 10), IVec2::new(10, 20), AtomType::Empty);
+        let top = 21 - column.len() as i32;
+        for (y, &atom_type) in (top..).zip(column) {
+            world.set_atom(10, y, atom(materials, atom_type));
+        }
+        world
+    }
+
+    #[test]
+    fn heavier_atoms_sink_through_lighter_fluids() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(9);
+        let mut column = vec![AtomType::Sand];
+        column.extend([AtomType::Water; 5]);
+        let mut world = shaft(&materials, &column);
+
+        run_with(&mut world, &materials, &mut rng, &settings, 60);
+
+        assert_eq!(type_at(&world, IVec2::new(10, 20)), AtomType::Sand);
+        assert_eq!(count(&world, AtomType::Water), 5);
+    }
+
+    #[test]
+    fn lighter_liquids_float_up_through_heavier_ones() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let mut rng = SimulationRng::new(9);
+        let oil = materials.by_name("oil").unwrap();
+        let mut column = vec![AtomType::Water; 5];
+        column.push(oil);
+        let mut world = shaft(&materials, &column);
+
+        run_with(&mut world, &materials, &mut rng, &settings, 60);
+
+        assert_eq!(type_at(&world, IVec2::new(10, 15)), oil);
+        assert!((16..=20).all(|y| type_at(&world, IVec2::new(10, y)) == AtomType::Water));
+    }
+
+    #[test]
+    fn viscous_liquids_spread_slower() {
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let settings = SimulationSettings::default();
+        let lava = materials.by_name("lava").unwrap();
+        // Width covered by a one-wide tower of `atom_type` after it has had a while to spread
+        let spread = |atom_type: AtomType| {
+            let mut rng = SimulationRng::new(9);
+            let mut world = small_world();
+            fill(&mut world, &materials, IVec2::new(0, 31), IVec2::new(63, 31), AtomType::Stone);
+            fill(&mut world, &materials, IVec2::new(32, 23), IVec2::new(32, 30), atom_type);
+            run_with(&mut world, &materials, &mut rng, &settings, 20);
+            let xs: Vec<i32> =
+                world.iter_atoms().filter(|(_, atom)| atom.atom_type == atom_type).map(|(cell, _)| cell.x).collect();
+            xs.iter().max().unwrap() - xs.iter().min().unwrap()
+        };
+
+        assert!(spread(lava) < spread(AtomType::Water));
+    }
+
+    // Run the grid's systems the way the game does for `frames` frames of `frame`
+    // each, starting from a seeded world with a splash of particles in the air.
+    // Returns the grid hash and where the particles ended up.
+    fn run_systems(seed: u64, frame: std::time::Duration, frames: usize) -> (u64, Vec<Vec2>) {
+        use crate::particles::{update_particles, Particles};
+        use bevy::ecs::schedule::Schedule;
+
+        let materials = Arc::new(MaterialRegistry::builtin());
+        let mut rng = SimulationRng::new(seed);
+        let grid = seeded_world(&mut rng);
+        let mut particles = Particles::default();
+        for i in 0..8 {
+            let velocity = Vec2::new(i as f32 * 8.0 - 32.0, -40.0);
+            particles.spawn(atom(&materials, AtomType::Water), Vec2::new(128.0, 100.0), velocity);
+        }
+
+        let mut world = World::new();
+        world.insert_resource(AtomWorldResource(grid));
+        world.insert_resource(MaterialRegistryResource(materials));
+        world.insert_resource(SimulationSettings::default());
+        world.insert_resource(rng);
+        world.insert_resource(particles);
+        world.insert_resource(bevy_rapier2d::prelude::RapierConfiguration::new(1.0));
+        world.insert_resource(Time::<()>::default());
+        let mut schedule = Schedule::default();
+        schedule.add_systems((update_particles, update_atoms, process_reactions).chain());
+
+        for _ in 0..frames {
+            world.resource_mut::<Time>().advance_by(frame);
+            schedule.run(&mut world);
+        }
+        let landed = world.resource::<Particles>().iter().map(|particle| particle.position).collect();
+        (world.resource::<AtomWorldResource>().0.grid_hash(), landed)
+    }
+
+    #[test]
+    fn replayed_seed_matches_whatever_the_frame_rate() {
+        use std::time::Duration;
+
+        let steady = run_systems(99, Duration::from_secs_f32(1.0 / 60.0), 10);
+        let slow = run_systems(99, Duration::from_secs_f32(1.0 / 20.0), 10);
+        let fast = run_systems(99, Duration::from_secs_f32(1.0 / 144.0), 10);
+
+        assert_eq!(steady, slow);
+        assert_eq!(steady, fast);
+        assert_ne!(steady.0, run_systems(100, Duration::from_secs_f32(1.0 / 60.0), 10).0);
+    }
+
+    fn is_solid(atom: &Atom) -> bool {
+        atom.atom_type == AtomType::Stone
+    }
+
+    #[test]
+    fn diagonal_rays_stop_at_the_first_wall_they_enter() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = small_world();
+        fill(&mut world, &materials, IVec2::new(30, 0), IVec2::new(30, 40), AtomType::Stone);
+        fill(&mut world, &materials, IVec2::new(0, 50), IVec2::new(29, 50), AtomType::Stone);
+
+        // Down and to the right into the side of the wall: it crosses into row 20
+        // (at y = 19.5) just before it gets to x = 29.5
+        let hit = world.raycast(Vec2::new(10.0, 10.0), Vec2::new(40.0, 25.0), is_solid).unwrap();
+        assert_eq!(hit, RayHit { cell: IVec2::new(30, 20), free: IVec2::new(29, 20), normal: IVec2::NEG_X });
+
+        // Steeply down onto the floor
+        let hit = world.raycast(Vec2::new(10.0, 30.0), Vec2::new(20.0, 60.0), is_solid).unwrap();
+        assert_eq!(hit.cell.y, 50);
+        assert_eq!(hit.free, hit.cell + IVec2::NEG_Y);
+        assert_eq!(hit.normal, IVec2::NEG_Y);
+
+        assert_eq!(world.raycast(Vec2::new(10.0, 10.0), Vec2::new(25.0, 40.0), is_solid), None, "stopped short");
+    }
+
+    #[test]
+    fn rays_starting_inside_solid_only_hit_after_getting_out() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = small_world();
+        fill(&mut world, &materials, IVec2::new(0, 5), IVec2::new(12, 15), AtomType::Stone);
+        fill(&mut world, &materials, IVec2::new(30, 0), IVec2::new(30, 20), AtomType::Stone);
+
+        let hit = world.raycast(Vec2::new(10.0, 10.0), Vec2::new(40.0, 10.0), is_solid).unwrap();
+        assert_eq!(hit, RayHit { cell: IVec2::new(30, 10), free: IVec2::new(29, 10), normal: IVec2::NEG_X });
+
+        assert_eq!(world.raycast(Vec2::new(2.0, 10.0), Vec2::new(10.0, 10.0), is_solid), None, "never got out");
+        assert_eq!(world.raycast(Vec2::new(10.0, 10.0), Vec2::new(20.0, 10.0), is_solid), None, "nothing past it");
+    }
+
+    #[test]
+    fn surface_normals_follow_floors_and_slopes() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = small_world();
+        // A flat floor on the left, and on the right ground rising to the right at 45 degrees
+        fill(&mut world, &materials, IVec2::new(0, 30), IVec2::new(20, 40), AtomType::Stone);
+        for x in 32..CHUNK_SIZE {
+            fill(&mut world, &materials, IVec2::new(x, 62 - x), IVec2::new(x, 40), AtomType::Stone);
+        }
+
+        let floor = world.surface_normal(IVec2::new(10, 29), is_solid).unwrap();
+        assert!(floor.dot(Vec2::NEG_Y) > 0.99, "floor normal {floor}");
+
+        // Up is -y in the grid, so the slope faces up and to the left
+        let slope = world.surface_normal(IVec2::new(40, 21), is_solid).unwrap();
+        assert!(slope.dot(Vec2::new(-1.0, -1.0).normalize()) > 0.95, "slope normal {slope}");
+
+        assert_eq!(world.surface_normal(IVec2::new(10, 10), is_solid), None, "nothing around");
+        assert_eq!(world.surface_normal(IVec2::new(10, 35), is_solid), None, "buried");
     }
 }
@@ -0,0 +1,390 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use crate::atoms::{Atom, AtomType, AtomWorld, AtomWorldResource};
+use crate::game::{Health, Player};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource};
+use crate::particles::Particles;
+use crate::random::{cell_noise, DeterministicRandom, SimulationRng};
+use crate::rendering::PixelCamera;
+use crate::world_space::{WorldSpace, GRID_DOWN};
+
+// Explosions, grown out of the examples/big_bada_boom.rs prototype. Anything can
+// set one off by queuing it on the AtomWorld (spells on impact, explosive
+// reactions, hazards). Each frame the queue is resolved: the blast carves a crater
+// whose size depends on how hard the ground is, the broken ground flies off as
+// debris particles, a fireball fills the hole, and an ExplosionEvent goes out for
+// the shockwave, sound and camera shake.
+
+// Damage at the centre of an explosion, and how high (in cells) it throws a
+// body sitting there, per cell of radius, when the caller doesn't say otherwise
+const DAMAGE_PER_CELL: f32 = 3.0;
+const LAUNCH_HEIGHT_PER_CELL: f32 = 1.0;
+// Mass of the body launch heights are given for: the player, 1x2 cells at density 1.
+// Heavier bodies get the same impulse and go less far.
+const REFERENCE_MASS: f32 = 2.0;
+// Cell-to-cell spread in how hard the ground is, so craters get ragged edges
+const HARDNESS_JITTER: f32 = 0.2;
+// Chance a destroyed atom flies off as debris rather than being blown to nothing
+const DEBRIS_CHANCE: f32 = 0.35;
+// Speed (grid cells per second) of debris thrown from the centre
+const DEBRIS_SPEED: f32 = 60.0;
+// Extra upward throw on debris, as a share of its outward speed
+const DEBRIS_LIFT: f32 = 0.5;
+// Blast strength above which the air fills with flames
+const FIREBALL_STRENGTH: f32 = 0.5;
+const FIREBALL_LIFETIME: f32 = 0.4;
+// Heat given to atoms that survive the blast, at full strength
+const BLAST_HEAT: f32 = 400.0;
+// How many crater radii out the shockwave still pushes bodies
+const SHOCKWAVE_REACH: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Explosion {
+    // Grid cell it goes off in
+    pub center: IVec2,
+    // Cells out to which the blast can break atoms
+    pub radius: f32,
+    // Health taken at the centre, falling off to nothing at the radius
+    pub damage: f32,
+    // Height (world units) a REFERENCE_MASS body at the centre is thrown against
+    // gravity. The impulse behind it falls off to nothing SHOCKWAVE_REACH radii away.
+    pub launch_height: f32,
+}
+
+impl Explosion {
+    pub fn new(center: IVec2, radius: f32) -> Self {
+        Self {
+            center,
+            radius,
+            damage: radius * DAMAGE_PER_CELL,
+            launch_height: radius * LAUNCH_HEIGHT_PER_CELL,
+        }
+    }
+
+    pub fn with_damage(self, damage: f32) -> Self {
+        Self { damage, ..self }
+    }
+
+    pub fn with_launch_height(self, launch_height: f32) -> Self {
+        Self { launch_height, ..self }
+    }
+
+    // Share of the blast felt `distance` cells from the centre
+    pub fn strength_at(&self, distance: f32) -> f32 {
+        (1.0 - distance / self.radius).clamp(0.0, 1.0)
+    }
+}
+
+// Sent for every explosion once it has gone off
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ExplosionEvent {
+    // World position of the centre
+    pub position: Vec2,
+    pub radius: f32,
+    pub damage: f32,
+    pub launch_height: f32,
+}
+
+// Blow up the atoms around an explosion. An atom breaks if the blast reaching it
+// is stronger than its material's hardness, so soft ground loses a wide crater
+// and hard rock only a dent. Atoms that hold get scorched.
+pub fn detonate(
+    world: &mut AtomWorld,
+    materials: &MaterialRegistry,
+    particles: &mut Particles,
+    rng: &mut DeterministicRandom,
+    explosion: &Explosion,
+) {
+    let flame_temperature = materials.get(AtomType::Fire).heat_source.unwrap_or(800.0);
+    let reach = explosion.radius.ceil() as i32;
+
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let offset = IVec2::new(dx, dy);
+            let cell = explosion.center + offset;
+            let strength = explosion.strength_at(offset.as_vec2().length());
+            if strength <= 0.0 {
+                continue;
+            }
+            let Some(atom) = world.get_atom(cell.x, cell.y) else {
+                continue;
+            };
+
+            let material = materials.get(atom.atom_type);
+            if atom.atom_type != AtomType::Empty && !material.is_gas() {
+                let hardness = material.hardness + (cell_noise(cell, world.step) - 0.5) * HARDNESS_JITTER;
+                if strength <= hardness {
+                    if let Some(atom) = world.get_atom_mut(cell.x, cell.y) {
+                        atom.temperature += BLAST_HEAT * strength;
+                    }
+                    continue;
+                }
+
+                if rng.gen::<f32>() < DEBRIS_CHANCE {
+                    let outward = offset.as_vec2().normalize_or_zero();
+                    let lift = -GRID_DOWN.as_vec2() * DEBRIS_LIFT;
+                    particles.eject(world, cell, (outward + lift) * DEBRIS_SPEED * strength);
+                } else {
+                    world.set_atom(cell.x, cell.y, Atom::default());
+                }
+            }
+
+            if strength > FIREBALL_STRENGTH {
+                world.set_atom(cell.x, cell.y, Atom {
+                    atom_type: AtomType::Fire,
+                    velocity: Vec2::ZERO,
+                    mass: materials.get(AtomType::Fire).mass,
+                    lifetime: Some(FIREBALL_LIFETIME),
+                    temperature: flame_temperature,
+                    ..default()
+                });
+            }
+        }
+    }
+}
+
+// System to set off everything queued since last frame
+pub fn resolve_explosions(
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut particles: ResMut<Particles>,
+    mut rng: ResMut<SimulationRng>,
+    mut events: EventWriter<ExplosionEvent>,
+) {
+    let world = &mut world.0;
+    let space = WorldSpace::of(world);
+
+    for explosion in world.take_explosions() {
+        detonate(world, &materials.0, &mut particles, rng.rng(), &explosion);
+        events.send(ExplosionEvent {
+            position: space.grid_to_world(explosion.center),
+            radius: explosion.radius,
+            damage: explosion.damage,
+            launch_height: explosion.launch_height,
+        });
+    }
+}
+
+// Velocity change (world units per second) the shockwave gives a body of `mass`
+// at `position`. The blast carries the impulse that throws a REFERENCE_MASS body
+// at the centre `launch_height` up against `gravity` (world units per second
+// squared, downward), so the launch speed is sqrt(2gh) for that body and scales
+// with 1 / mass for the rest.
+pub fn shockwave_kick(event: &ExplosionEvent, position: Vec2, mass: f32, gravity: f32) -> Vec2 {
+    let offset = position - event.position;
+    let push = (1.0 - offset.length() / (event.radius * SHOCKWAVE_REACH)).clamp(0.0, 1.0);
+    let launch_speed = (2.0 * gravity * event.launch_height).sqrt();
+    let impulse = REFERENCE_MASS * launch_speed * push;
+    // Rapier hasn't weighed bodies spawned this frame yet
+    let mass = if mass > 0.0 { mass } else { REFERENCE_MASS };
+    offset.try_normalize().unwrap_or(Vec2::Y) * impulse / mass
+}
+
+// System to throw bodies away from explosions and hurt anything with health. The
+// player's own movement would cancel a sideways push straight away, so theirs
+// goes into their knockback instead.
+pub fn explosion_shockwaves(
+    mut commands: Commands,
+    mut events: EventReader<ExplosionEvent>,
+    rapier_config: Res<RapierConfiguration>,
+    mut bodies: Query<(Entity, &RigidBody, &Transform, &mut Velocity, Option<&mut Player>, Option<&mut Health>)>,
+    masses: Query<&ReadMassProperties>,
+) {
+    let gravity = rapier_config.gravity.length();
+
+    for event in events.read() {
+        for (entity, rigid_body, transform, mut velocity, player, health) in bodies.iter_mut() {
+            if *rigid_body != RigidBody::Dynamic {
+                continue;
+            }
+            let position = transform.translation.truncate();
+            let distance = position.distance(event.position);
+
+            let mass = masses.get(entity).map_or(REFERENCE_MASS, |mass| mass.get().mass);
+            let kick = shockwave_kick(event, position, mass, gravity);
+            let is_player = player.is_some();
+            match player {
+                Some(mut player) => {
+                    player.knockback += kick.x;
+                    velocity.linvel.y += kick.y;
+                }
+                None => velocity.linvel += kick,
+            }
+
+            let Some(mut health) = health else {
+                continue;
+            };
+            let hurt = (1.0 - distance / event.radius).clamp(0.0, 1.0);
+            // Bodies killed by an earlier blast this frame are already on their way out
+            if hurt > 0.0 && !health.is_dead() {
+                health.damage(event.damage * hurt);
+                if health.is_dead() && !is_player {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+}
+
+// How far (in explosion radii) from the camera an explosion can still shake it
+const SHAKE_REACH: f32 = 10.0;
+// Radius of an explosion that shakes the camera as hard as it goes on its own
+const FULL_SHAKE_RADIUS: f32 = 20.0;
+// Shake lost per second
+const SHAKE_RECOVERY: f32 = 1.5;
+// Furthest (in world units) the view is thrown, and how often it changes direction
+const MAX_SHAKE: f32 = 4.0;
+const SHAKE_FREQUENCY: f32 = 30.0;
+
+// System to shake the view after nearby explosions. `trauma` builds up from 0 to 1
+// with each blast and wears off; the shake grows with its square, so small blasts
+// barely register and big ones pile up.
+pub fn shake_camera(
+    time: Res<Time>,
+    mut events: EventReader<ExplosionEvent>,
+    mut trauma: Local<f32>,
+    mut cameras: Query<&mut PixelCamera>,
+) {
+    let Ok(mut camera) = cameras.get_single_mut() else {
+        return;
+    };
+
+    for event in events.read() {
+        let closeness = (1.0 - event.position.distance(camera.position) / (event.radius * SHAKE_REACH)).clamp(0.0, 1.0);
+        *trauma = (*trauma + event.radius / FULL_SHAKE_RADIUS * closeness).min(1.0);
+    }
+    *trauma = (*trauma - SHAKE_RECOVERY * time.delta_seconds()).max(0.0);
+
+    let tick = (time.elapsed_seconds() * SHAKE_FREQUENCY) as i32;
+    let noise = Vec2::new(cell_noise(IVec2::new(tick, 0), 0), cell_noise(IVec2::new(tick, 1), 0)) * 2.0 - 1.0;
+    camera.offset = noise * *trauma * *trauma * MAX_SHAKE;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atoms::{CHUNK_SIZE, SIMULATION_DT};
+    use crate::world_space::PIXELS_PER_METER;
+
+    fn filled_world(materials: &MaterialRegistry, atom_type: AtomType) -> AtomWorld {
+        let mut world = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                world.set_atom(x, y, Atom { atom_type, mass: materials.get(atom_type).mass, ..default() });
+            }
+        }
+        world
+    }
+
+    // Cells no longer holding `atom_type`
+    fn broken_cells(world: &AtomWorld, atom_type: AtomType) -> Vec<IVec2> {
+        world
+            .iter_atoms()
+            .filter(|(_, atom)| atom.atom_type != atom_type)
+            .map(|(cell, _)| cell)
+            .collect()
+    }
+
+    fn blast(world: &mut AtomWorld, materials: &MaterialRegistry, particles: &mut Particles, explosion: Explosion) {
+        detonate(world, materials, particles, &mut DeterministicRandom::new(7), &explosion);
+    }
+
+    #[test]
+    fn hard_ground_loses_a_smaller_crater_than_soft() {
+        let materials = MaterialRegistry::builtin();
+        let center = IVec2::splat(CHUNK_SIZE / 2);
+        let explosion = Explosion::new(center, 8.0);
+
+        let mut sand = filled_world(&materials, AtomType::Sand);
+        blast(&mut sand, &materials, &mut Particles::default(), explosion);
+        let mut stone = filled_world(&materials, AtomType::Stone);
+        blast(&mut stone, &materials, &mut Particles::default(), explosion);
+
+        let sand_crater = broken_cells(&sand, AtomType::Sand);
+        let stone_crater = broken_cells(&stone, AtomType::Stone);
+        assert!(!stone_crater.is_empty());
+        assert!(sand_crater.len() > stone_crater.len() * 4, "{} vs {}", sand_crater.len(), stone_crater.len());
+
+        // Nothing breaks past the radius, and stone only where the blast beats its hardness
+        let stone_hardness = materials.get(AtomType::Stone).hardness - HARDNESS_JITTER / 2.0;
+        for cell in &sand_crater {
+            assert!(cell.as_vec2().distance(center.as_vec2()) < explosion.radius);
+        }
+        for cell in &stone_crater {
+            assert!(explosion.strength_at(cell.as_vec2().distance(center.as_vec2())) > stone_hardness);
+        }
+        // Survivors near the centre are scorched
+        let heated = stone.iter_atoms().filter(|(_, atom)| atom.atom_type == AtomType::Stone && atom.temperature > 20.0).count();
+        assert!(heated > 0);
+    }
+
+    #[test]
+    fn broken_ground_flies_outward_as_debris() {
+        let materials = MaterialRegistry::builtin();
+        let center = IVec2::splat(CHUNK_SIZE / 2);
+        let explosion = Explosion::new(center, 8.0);
+        let mut world = filled_world(&materials, AtomType::Sand);
+        let mut particles = Particles::default();
+
+        blast(&mut world, &materials, &mut particles, explosion);
+
+        let destroyed = broken_cells(&world, AtomType::Sand).len();
+        let debris: Vec<_> = particles.iter().collect();
+        // Some of the broken sand flies off, the rest is blown to nothing
+        assert!(debris.len() as f32 > destroyed as f32 * DEBRIS_CHANCE / 2.0, "{} of {}", debris.len(), destroyed);
+        assert!((debris.len() as f32) < destroyed as f32 * DEBRIS_CHANCE * 2.0, "{} of {}", debris.len(), destroyed);
+
+        for particle in debris {
+            assert_eq!(particle.atom.atom_type, AtomType::Sand);
+            let offset = particle.position - center.as_vec2();
+            assert!(offset.length() < explosion.radius);
+            if offset != Vec2::ZERO {
+                assert!(particle.velocity.dot(offset) > 0.0, "{:?} thrown {:?}", offset, particle.velocity);
+            }
+        }
+        // The middle of the crater is on fire
+        assert_eq!(world.get_atom(center.x, center.y).map(|atom| atom.atom_type), Some(AtomType::Fire));
+    }
+
+    fn event(radius: f32, launch_height: f32) -> ExplosionEvent {
+        ExplosionEvent { position: Vec2::ZERO, radius, damage: 0.0, launch_height }
+    }
+
+    #[test]
+    fn shockwave_throws_the_reference_body_its_launch_height() {
+        let gravity = 9.81 * PIXELS_PER_METER;
+        let explosion = Explosion::new(IVec2::ZERO, 10.0);
+        let event = event(explosion.radius, explosion.launch_height);
+
+        let kick = shockwave_kick(&event, Vec2::ZERO, REFERENCE_MASS, gravity);
+        assert_eq!(kick.x, 0.0);
+
+        // Fly it the way Rapier would, one fixed step at a time
+        let (mut height, mut speed) = (0.0, kick.y);
+        while speed > 0.0 {
+            speed -= gravity * SIMULATION_DT;
+            height += speed * SIMULATION_DT;
+        }
+        assert!((height - explosion.launch_height).abs() < 1.0, "reached {height}");
+    }
+
+    #[test]
+    fn shockwave_moves_heavy_and_distant_bodies_less() {
+        let gravity = 9.81 * PIXELS_PER_METER;
+        let event = event(10.0, 10.0);
+        let near = Vec2::new(5.0, 0.0);
+
+        let light = shockwave_kick(&event, near, REFERENCE_MASS, gravity);
+        let heavy = shockwave_kick(&event, near, REFERENCE_MASS * 4.0, gravity);
+        assert!(light.x > 0.0 && light.y == 0.0);
+        assert!((heavy * 4.0 - light).length() < 1e-3);
+
+        let far = shockwave_kick(&event, near * 2.0, REFERENCE_MASS, gravity);
+        assert!(far.x > 0.0 && far.x < light.x);
+        let out_of_reach = Vec2::new(event.radius * SHOCKWAVE_REACH, 0.0);
+        assert_eq!(shockwave_kick(&event, out_of_reach, REFERENCE_MASS, gravity), Vec2::ZERO);
+        // Bodies Rapier hasn't weighed yet count as the reference
+        assert_eq!(shockwave_kick(&event, near, 0.0, gravity), light);
+    }
+}
@@ -1,8 +1,15 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use std::sync::Arc;
 use crate::atoms::{AtomWorldResource, AtomType};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource};
+use crate::random::{DeterministicRandom, SimulationRng};
+use crate::world_space::WorldSpace;
 use crate::rendering;
 use crate::physics;
+use crate::particles;
+use crate::explosions;
+use crate::lighting;
 use crate::magic;
 use crate::level_generation;
 use crate::level_editor;
@@ -13,47 +20,97 @@ pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
+        let materials = MaterialRegistry::load_or_builtin(crate::materials::MATERIALS_PATH);
+
+        // Logged so a run can be reproduced with SimulationRng::new
+        let rng = SimulationRng::from_entropy();
+        println!("Simulation seed: {}", rng.seed());
+
         app
+            .insert_resource(MaterialRegistryResource(Arc::new(materials)))
             .insert_resource(AtomWorldResource(crate::atoms::AtomWorld::new(200, 150)))
+            .insert_resource(crate::atoms::SimulationSettings::default())
+            .insert_resource(rng)
+            .insert_resource(physics::TerrainColliders::default())
+            .insert_resource(physics::TerrainConnectivity::default())
+            .insert_resource(particles::Particles::default())
+            .insert_resource(magic::SpellBudget::default())
+            .insert_resource(lighting::LightMap::default())
             .insert_resource(level_generation::LevelManager::default())
             .insert_resource(level_editor::LevelEditor::default())
             .insert_resource(level_editor::EditorHistory::default())
             .insert_resource(sound::SoundManager::default())
             .insert_resource(touchscreen::TouchControls::default())
             .insert_resource(touchscreen::TouchGestureRecognizer::default())
-            .add_systems(Startup, (setup_game, level_editor::setup_level_editor, sound::setup_audio_buses, touchscreen::setup_touch_controls))
+            .insert_resource(rendering::PixelCameraSettings::default())
+            .add_systems(Startup, (rendering::setup_pixel_camera, setup_game, level_editor::setup_level_editor, sound::setup_audio_buses, touchscreen::setup_touch_controls))
             .add_event::<sound::SpellCastEvent>()
+            .add_event::<explosions::ExplosionEvent>()
             .add_systems(Update, (
-            crate::atoms::update_atoms,
-            crate::atoms::process_reactions,
-            physics::atoms_push_rigid_bodies,
-            physics::rigid_bodies_displace_atoms,
-            player_input,
-            update_player,
-            rendering::render_atoms,
-            rendering::camera_follow_player,
-            brush_tool,
-            spawn_demo_atoms,
-            magic::update_magic_users,
-            magic::cast_spell,
-            magic::update_spell_instances,
-            level_generation::level_transition_system,
-
+                // Rigid bodies are stamped into the grid while it steps, and while the
+                // systems that carve it (explosions, spells, brushes) run, so those eat
+                // into the bodies too. Everything else that writes to the grid runs
+                // after they are lifted back out.
+                (
+                    physics::stamp_rigid_bodies,
+                    particles::update_particles,
+                    crate::atoms::update_atoms,
+                    crate::atoms::process_reactions,
+                    physics::unstamp_rigid_bodies,
+                ).chain(),
+                physics::rigid_bodies_displace_atoms.after(physics::unstamp_rigid_bodies),
+                player_input,
+                update_player,
+                lighting::update_lighting.after(physics::unstamp_rigid_bodies),
+                rendering::render_atoms.after(lighting::update_lighting),
+                rendering::render_rigid_bodies,
+                rendering::render_particles,
+                (rendering::camera_follow_player, explosions::shake_camera, rendering::pixel_perfect_camera).chain(),
+                brush_tool.after(crate::atoms::process_reactions).before(physics::unstamp_rigid_bodies),
+                spawn_demo_atoms.after(physics::unstamp_rigid_bodies),
+                magic::update_magic_users,
+                magic::cast_spell,
+                (
+                    magic::count_live_spells,
+                    magic::spell_homing,
+                    magic::update_spell_instances,
+                    magic::spawn_spell_children,
+                    magic::spell_collision_detection,
+                    magic::special_perks_on_impact,
+                    magic::run_spell_subroutines,
+                ).chain().after(crate::atoms::process_reactions).before(physics::unstamp_rigid_bodies),
+                magic::spell_heat_atoms.after(crate::atoms::process_reactions).before(physics::unstamp_rigid_bodies),
+                (explosions::resolve_explosions, explosions::explosion_shockwaves)
+                    .chain()
+                    .after(magic::run_spell_subroutines)
+                    .before(physics::unstamp_rigid_bodies),
+                level_generation::level_transition_system.after(physics::unstamp_rigid_bodies),
             ))
             .add_systems(Update, (
                 level_editor::toggle_level_editor,
-              level_editor::update_editor_cursor,
-              level_editor::editor_input,
-              level_editor::editor_undo_redo,
-              sound::monitor_atomic_reactions,
-              sound::toggle_sound_system,
-              sound::adjust_volume,
-              touchscreen::process_touch_input,
-              touchscreen::touch_to_game_input,
-              touchscreen::toggle_touchscreen,
-              touchscreen::render_touch_controls)
-            )
-            .add_systems(FixedUpdate, physics::create_terrain_colliders);
+                level_editor::update_editor_cursor,
+                level_editor::editor_input.after(crate::atoms::process_reactions).before(physics::unstamp_rigid_bodies),
+                level_editor::editor_undo_redo.after(physics::unstamp_rigid_bodies),
+                sound::monitor_atomic_reactions,
+                physics::corrosive_atoms_damage_bodies.after(physics::unstamp_rigid_bodies),
+                sound::toggle_sound_system,
+                sound::adjust_volume,
+                touchscreen::process_touch_input,
+                touchscreen::touch_to_game_input,
+                touchscreen::toggle_touchscreen,
+                touchscreen::render_touch_controls,
+                magic::special_perks_on_cast,
+                magic::expire_conjured,
+                magic::update_invisibility,
+            ))
+            .add_systems(FixedUpdate, (
+                physics::atoms_push_rigid_bodies.before(PhysicsSet::SyncBackend),
+                (
+                    physics::settle_rigid_bodies,
+                    physics::detach_rigid_bodies,
+                    physics::update_terrain_colliders,
+                ).chain(),
+            ));
     }
 }
 
@@ -67,6 +124,33 @@ pub struct Player {
     pub coyote_timer: f32,
     pub jump_buffer_time: f32,
     pub jump_buffer_timer: f32,
+    // Sideways speed from being thrown about (by explosions), on top of walking.
+    // Wears off at KNOCKBACK_DRAG.
+    pub knockback: f32,
+}
+
+// Share of the player's knockback lost per second
+const KNOCKBACK_DRAG: f32 = 4.0;
+
+// Hit points for the player and anything else that can be hurt by the world
+#[derive(Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn damage(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
 }
 
 // Brush tool for painting atoms
@@ -90,17 +174,11 @@ impl Default for BrushTool {
 fn setup_game(
     mut commands: Commands,
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut rng: ResMut<SimulationRng>,
 ) {
-    // Setup pixel-perfect camera
-    commands.spawn((
-        Camera2dBundle::default(),
-        rendering::PixelCamera {
-            pixels_per_unit: 32.0,
-        },
-    ));
-
     // Create some initial terrain
-    create_demo_terrain(&mut world.0);
+    create_demo_terrain(&mut world.0, &materials.0, rng.rng());
 
     // Spawn player
     commands.spawn((
@@ -125,8 +203,10 @@ fn setup_game(
             coyote_timer: 0.0,
             jump_buffer_time: 0.1,
             jump_buffer_timer: 0.0,
+            knockback: 0.0,
         },
         magic::MagicUser::default(),
+        Health::new(100.0),
     ));
 
     // Initialize brush tool
@@ -137,30 +217,33 @@ fn setup_game(
     // Note: In Bevy Rapier 0.27, gravity is typically set during plugin initialization
 }
 
-fn create_demo_terrain(world: &mut crate::atoms::AtomWorld) {
-    // Create a simple terrain floor
+fn create_demo_terrain(world: &mut crate::atoms::AtomWorld, materials: &MaterialRegistry, rng: &mut DeterministicRandom) {
+    // Create a simple terrain floor along the bottom rows of the grid
+    let floor = world.height - 10;
     for x in 0..world.width {
-        for y in 0..10 {
+        for y in floor..world.height {
             world.set_atom(x as i32, y as i32, crate::atoms::Atom {
                 atom_type: AtomType::Stone,
                 velocity: Vec2::ZERO,
-                mass: AtomType::Stone.mass(),
+                mass: materials.get(AtomType::Stone).mass,
                 lifetime: None,
                 temperature: 20.0,
+                ..default()
             });
         }
     }
 
     // Add some sand piles
     for x in 50..80 {
-        for y in 10..25 {
-            if rand::random::<f32>() < 0.7 {
+        for y in floor - 15..floor {
+            if rng.next_bool(0.7) {
                 world.set_atom(x as i32, y as i32, crate::atoms::Atom {
                     atom_type: AtomType::Sand,
                     velocity: Vec2::ZERO,
-                    mass: AtomType::Sand.mass(),
+                    mass: materials.get(AtomType::Sand).mass,
                     lifetime: None,
                     temperature: 20.0,
+                    ..default()
                 });
             }
         }
@@ -168,14 +251,15 @@ fn create_demo_terrain(world: &mut crate::atoms::AtomWorld) {
 
     // Add water
     for x in 120..140 {
-        for y in 10..20 {
-            if rand::random::<f32>() < 0.8 {
+        for y in floor - 10..floor {
+            if rng.next_bool(0.8) {
                 world.set_atom(x as i32, y as i32, crate::atoms::Atom {
                     atom_type: AtomType::Water,
                     velocity: Vec2::ZERO,
-                    mass: AtomType::Water.mass(),
+                    mass: materials.get(AtomType::Water).mass,
                     lifetime: None,
                     temperature: 20.0,
+                    ..default()
                 });
             }
         }
@@ -223,7 +307,8 @@ fn update_player(
             movement.x += 1.0;
         }
 
-        velocity.linvel.x = movement.x * player.speed;
+        velocity.linvel.x = movement.x * player.speed + player.knockback;
+        player.knockback *= (1.0 - KNOCKBACK_DRAG * time.delta_seconds()).max(0.0);
 
         // Ground check
         let shape = Collider::cuboid(0.4, 0.1);
@@ -262,22 +347,25 @@ fn update_player(
 
 fn brush_tool(
     brush: Res<BrushTool>,
+    touch_controls: Res<touchscreen::TouchControls>,
     windows: Query<&Window>,
-    camera_query: Query<(&Camera, &GlobalTransform)>,
+    camera_query: Query<(&Camera, &GlobalTransform, &rendering::PixelCamera)>,
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
     mouse_input: Res<ButtonInput<MouseButton>>,
 ) {
     if !brush.is_active && !mouse_input.pressed(MouseButton::Left) {
         return;
     }
 
-    let (camera, camera_transform) = camera_query.single();
+    let (camera, camera_transform, pixel_camera) = camera_query.single();
     let window = windows.single();
+    let space = WorldSpace::of(&world.0);
 
-    if let Some(cursor_pos) = window.cursor_position() {
-        if let Some(world_pos) = camera.viewport_to_world(camera_transform, cursor_pos) {
-            let atom_x = (world_pos.origin.x + (world.0.width as f32 / 2.0)).round() as i32;
-            let atom_y = (world_pos.origin.y + (world.0.height as f32 / 2.0)).round() as i32;
+    // A finger on the touch brush area paints like the mouse does
+    if let Some(screen_pos) = touch_controls.brush_position().or(window.cursor_position()) {
+        if let Some(cell) = space.screen_to_grid(camera, camera_transform, pixel_camera, screen_pos) {
+            let (atom_x, atom_y) = (cell.x, cell.y);
 
             // Paint atoms in a circle around the cursor
             for dx in -brush.size..=brush.size {
@@ -294,9 +382,10 @@ fn brush_tool(
                             world.0.set_atom(x, y, crate::atoms::Atom {
                                 atom_type: brush.atom_type,
                                 velocity: Vec2::ZERO,
-                                mass: brush.atom_type.mass(),
+                                mass: materials.0.get(brush.atom_type).mass,
                                 lifetime: None,
                                 temperature: 20.0,
+                                ..default()
                             });
                         }
                     }
@@ -308,14 +397,14 @@ fn brush_tool(
 
 fn spawn_demo_atoms(
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
 ) {
-    // Spawn different atoms for testing
+    // Spawn different atoms for testing, counting grid ticks rather than frame time
     static mut LAST_SPAWN: f32 = 0.0;
 
     unsafe {
-        LAST_SPAWN += time.delta_seconds();
+        LAST_SPAWN += crate::atoms::SIMULATION_DT;
         if LAST_SPAWN > 0.5 { // Spawn every half second
             LAST_SPAWN = 0.0;
 
@@ -324,9 +413,10 @@ fn spawn_demo_atoms(
                 world.0.set_atom(100, 50, crate::atoms::Atom {
                     atom_type: AtomType::Sand,
                     velocity: Vec2::ZERO,
-                    mass: AtomType::Sand.mass(),
+                    mass: materials.0.get(AtomType::Sand).mass,
                     lifetime: None,
                     temperature: 20.0,
+                    ..default()
                 });
             }
 
@@ -335,9 +425,10 @@ fn spawn_demo_atoms(
                 world.0.set_atom(105, 50, crate::atoms::Atom {
                     atom_type: AtomType::Water,
                     velocity: Vec2::ZERO,
-                    mass: AtomType::Water.mass(),
+                    mass: materials.0.get(AtomType::Water).mass,
                     lifetime: None,
                     temperature: 20.0,
+                    ..default()
                 });
             }
 
@@ -346,9 +437,10 @@ fn spawn_demo_atoms(
                 world.0.set_atom(110, 50, crate::atoms::Atom {
                     atom_type: AtomType::Fire,
                     velocity: Vec2::ZERO,
-                    mass: AtomType::Fire.mass(),
+                    mass: materials.0.get(AtomType::Fire).mass,
                     lifetime: Some(5.0),
                     temperature: 800.0, // Hot fire
+                    ..default()
                 });
             }
 
@@ -357,9 +449,10 @@ fn spawn_demo_atoms(
                 world.0.set_atom(115, 50, crate::atoms::Atom {
                     atom_type: AtomType::Acid,
                     velocity: Vec2::ZERO,
-                    mass: AtomType::Acid.mass(),
+                    mass: materials.0.get(AtomType::Acid).mass,
                     lifetime: None,
                     temperature: 20.0,
+                    ..default()
                 });
             }
         }
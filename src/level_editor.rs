@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 use crate::atoms::{AtomWorldResource, Atom, AtomType};
 use crate::level_generation::{LevelManager, LevelType};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource};
+use crate::rendering::PixelCamera;
+use crate::world_space::WorldSpace;
 
 // Custom Level Editor as described in "A Custom Level Editor" blog post
 // Pixel-based level editor that supports placing enemies and hazards
@@ -118,29 +121,27 @@ pub fn toggle_level_editor(
 
 pub fn update_editor_cursor(
     editor: Res<LevelEditor>,
+    world: Res<AtomWorldResource>,
     windows: Query<&Window>,
-    camera_query: Query<(&Camera, &GlobalTransform)>,
+    camera_query: Query<(&Camera, &GlobalTransform, &PixelCamera)>,
     mut cursor_query: Query<&mut Transform, With<EditorCursor>>,
 ) {
     if !editor.is_active {
         return;
     }
+    let space = WorldSpace::of(&world.0);
 
-    if let Ok((camera, camera_transform)) = camera_query.get_single() {
+    if let Ok((camera, camera_transform, pixel_camera)) = camera_query.get_single() {
         if let Some(window) = windows.iter().next() {
             if let Some(cursor_pos) = window.cursor_position() {
-                if let Some(world_pos) = camera.viewport_to_world(camera_transform, cursor_pos) {
+                if let Some(world_pos) = WorldSpace::screen_to_world(camera, camera_transform, pixel_camera, cursor_pos) {
                     let mut cursor_transform = cursor_query.single_mut();
 
-                    // Snap to grid if enabled
+                    // Snap to the centre of the cell under the cursor if enabled
                     let pos = if true { // snap_to_grid
-                        let grid_size = 1.0;
-                        Vec2::new(
-                            (world_pos.origin.x / grid_size).round() * grid_size,
-                            (world_pos.origin.y / grid_size).round() * grid_size,
-                        )
+                        space.grid_to_world(space.world_to_grid(world_pos))
                     } else {
-                        world_pos.origin.truncate()
+                        world_pos
                     };
 
                     cursor_transform.translation = pos.extend(10.0);
@@ -158,6 +159,7 @@ pub fn editor_input(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut editor: ResMut<LevelEditor>,
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
     cursor_query: Query<&Transform, With<EditorCursor>>,
 ) {
     if !editor.is_active {
@@ -203,18 +205,19 @@ pub fn editor_input(
     // Apply editor action
     if mouse_input.pressed(MouseButton::Left) {
         if let Ok(cursor_transform) = cursor_query.get_single() {
-            let pos = cursor_transform.translation.truncate();
+            // The cursor lives in world space; the brush works on grid cells
+            let pos = WorldSpace::of(&world.0).world_to_grid_point(cursor_transform.translation.truncate());
 
             match editor.mode {
                 EditorMode::Draw => {
-                    draw_with_brush(&mut world.0, pos, editor.selected_atom_type, editor.brush_size);
+                    draw_with_brush(&mut world.0, &materials.0, pos, editor.selected_atom_type, editor.brush_size);
                 }
                 EditorMode::Erase => {
-                    draw_with_brush(&mut world.0, pos, AtomType::Empty, editor.brush_size);
+                    draw_with_brush(&mut world.0, &materials.0, pos, AtomType::Empty, editor.brush_size);
                 }
                 EditorMode::Fill => {
                     // Implement flood fill
-                    flood_fill_area(&mut world.0, pos, editor.selected_atom_type);
+                    flood_fill_area(&mut world.0, &materials.0, pos, editor.selected_atom_type);
                 }
                 _ => {}
             }
@@ -222,7 +225,7 @@ pub fn editor_input(
     }
 }
 
-fn draw_with_brush(world: &mut crate::atoms::AtomWorld, center: Vec2, atom_type: AtomType, size: i32) {
+fn draw_with_brush(world: &mut crate::atoms::AtomWorld, materials: &MaterialRegistry, center: Vec2, atom_type: AtomType, size: i32) {
     let start_x = (center.x - size as f32 / 2.0) as i32;
     let start_y = (center.y - size as f32 / 2.0) as i32;
     let end_x = start_x + size;
@@ -238,18 +241,19 @@ fn draw_with_brush(world: &mut crate::atoms::AtomWorld, center: Vec2, atom_type:
                 world.set_atom(x, y, Atom {
                     atom_type,
                     velocity: Vec2::ZERO,
-                    mass: atom_type.mass(),
+                    mass: materials.get(atom_type).mass,
                     lifetime: if atom_type == AtomType::Fire { Some(10.0) } else { None },
                     temperature: if atom_type == AtomType::Fire { 700.0 } else { 20.0 },
+                    ..default()
                 });
             }
         }
     }
 }
 
-fn flood_fill_area(world: &mut crate::atoms::AtomWorld, start_pos: Vec2, fill_type: AtomType) {
-    let start_x = start_pos.x as i32;
-    let start_y = start_pos.y as i32;
+fn flood_fill_area(world: &mut crate::atoms::AtomWorld, materials: &MaterialRegistry, start_pos: Vec2, fill_type: AtomType) {
+    let start_x = start_pos.x.round() as i32;
+    let start_y = start_pos.y.round() as i32;
 
     if let Some(start_atom) = world.get_atom(start_x, start_y) {
         let target_type = start_atom.atom_type;
@@ -271,9 +275,10 @@ fn flood_fill_area(world: &mut crate::atoms::AtomWorld, start_pos: Vec2, fill_ty
                     world.set_atom(x, y, Atom {
                         atom_type: fill_type,
                         velocity: Vec2::ZERO,
-                        mass: fill_type.mass(),
+                        mass: materials.get(fill_type).mass,
                         lifetime: if fill_type == AtomType::Fire { Some(10.0) } else { None },
                         temperature: if fill_type == AtomType::Fire { 700.0 } else { 20.0 },
+                        ..default()
                     });
 
                     // Add neighbors
@@ -302,13 +307,14 @@ pub fn save_level(
 
 pub fn load_level_editor(
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
     level_manager: Res<LevelManager>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
     if keyboard_input.just_pressed(KeyCode::F3) {
         // Load level from file
         // In a real implementation, this would deserialize from file
-        *world = AtomWorldResource(level_manager.generate_current_level(200, 150));
+        *world = AtomWorldResource(level_manager.generate_current_level(200, 150, &materials.0));
         println!("Level loaded!");
     }
 }
@@ -387,13 +393,9 @@ pub fn validate_level(world: Res<AtomWorldResource>) {
 
     // This would need to be integrated with entity placement system
     // For now, just count atoms
-    for y in 0..world.0.height {
-        for x in 0..world.0.width {
-            if let Some(atom) = world.0.get_atom(x as i32, y as i32) {
-                if atom.atom_type != AtomType::Empty {
-                    atom_count += 1;
-                }
-            }
+    for (_, atom) in world.0.iter_atoms() {
+        if atom.atom_type != AtomType::Empty {
+            atom_count += 1;
         }
     }
 
@@ -2,6 +2,9 @@ use bevy::prelude::*;
 use noise::{NoiseFn, Perlin};
 use rand::prelude::*;
 use crate::atoms::{AtomWorld, Atom, AtomType, AtomWorldResource};
+use crate::lighting::LightMap;
+use crate::materials::{MaterialRegistry, MaterialRegistryResource};
+use crate::random::DeterministicRandom;
 
 // Procedural level generation using noise functions
 // Based on "Legit Levels" blog post
@@ -19,21 +22,23 @@ impl LevelGenerator {
         }
     }
 
-    pub fn generate_level(&self, width: usize, height: usize, level_type: LevelType) -> AtomWorld {
+    pub fn generate_level(&self, width: usize, height: usize, level_type: LevelType, materials: &MaterialRegistry) -> AtomWorld {
         let mut world = AtomWorld::new(width, height);
+        // Scatter decisions come from the level's seed, so a seed always builds the same level
+        world.rng = DeterministicRandom::new(self.seed as u64);
 
         match level_type {
-            LevelType::Cave => self.generate_cave_level(&mut world),
-            LevelType::Island => self.generate_island_level(&mut world),
-            LevelType::Mountain => self.generate_mountain_level(&mut world),
-            LevelType::Volcano => self.generate_volcano_level(&mut world),
-            LevelType::Laboratory => self.generate_laboratory_level(&mut world),
+            LevelType::Cave => self.generate_cave_level(&mut world, materials),
+            LevelType::Island => self.generate_island_level(&mut world, materials),
+            LevelType::Mountain => self.generate_mountain_level(&mut world, materials),
+            LevelType::Volcano => self.generate_volcano_level(&mut world, materials),
+            LevelType::Laboratory => self.generate_laboratory_level(&mut world, materials),
         }
 
         world
     }
 
-    fn generate_cave_level(&self, world: &mut AtomWorld) {
+    fn generate_cave_level(&self, world: &mut AtomWorld, materials: &MaterialRegistry) {
         // Generate cave system using 3D noise for natural cave shapes
         for y in 0..world.height {
             for x in 0..world.width {
@@ -60,24 +65,31 @@ impl LevelGenerator {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type,
                         velocity: Vec2::ZERO,
-                        mass: atom_type.mass(),
+                        mass: materials.get(atom_type).mass,
                         lifetime: None,
                         temperature: 20.0,
+                        ..default()
                     });
                 }
 
-                // Add ore deposits
-                if cave_noise > 0.7 && rand::random::<f32>() < 0.1 {
-                    // Special ore (could be different atom types)
+                // Add ore deposits, the only light deep in the caves
+                if cave_noise > 0.7 && world.rng.gen::<f32>() < 0.1 {
+                    if let Some(ore) = materials.by_name("glow_ore") {
+                        world.set_atom(x as i32, y as i32, Atom {
+                            atom_type: ore,
+                            mass: materials.get(ore).mass,
+                            ..default()
+                        });
+                    }
                 }
             }
         }
 
         // Add water lakes in caves
-        self.add_water_features(world, 0.1);
+        self.add_water_features(world, materials, 0.1);
     }
 
-    fn generate_island_level(&self, world: &mut AtomWorld) {
+    fn generate_island_level(&self, world: &mut AtomWorld, materials: &MaterialRegistry) {
         // Generate floating island using radial falloff
         let center_x = world.width as f32 / 2.0;
         let center_y = world.height as f32 / 2.0;
@@ -98,7 +110,7 @@ impl LevelGenerator {
 
                 let threshold = 0.3 + height_factor * 0.4 + noise * 0.2;
 
-                if rand::random::<f32>() < threshold {
+                if world.rng.gen::<f32>() < threshold {
                     let atom_type = if height_factor > 0.7 {
                         AtomType::Stone
                     } else {
@@ -108,19 +120,20 @@ impl LevelGenerator {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type,
                         velocity: Vec2::ZERO,
-                        mass: atom_type.mass(),
+                        mass: materials.get(atom_type).mass,
                         lifetime: None,
                         temperature: 20.0,
+                        ..default()
                     });
                 }
             }
         }
 
         // Add water around the island
-        self.add_water_features(world, 0.8);
+        self.add_water_features(world, materials, 0.8);
     }
 
-    fn generate_mountain_level(&self, world: &mut AtomWorld) {
+    fn generate_mountain_level(&self, world: &mut AtomWorld, materials: &MaterialRegistry) {
         // Generate mountain ranges
         for y in 0..world.height {
             for x in 0..world.width {
@@ -149,16 +162,17 @@ impl LevelGenerator {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type,
                         velocity: Vec2::ZERO,
-                        mass: atom_type.mass(),
+                        mass: materials.get(atom_type).mass,
                         lifetime: None,
                         temperature: 20.0,
+                        ..default()
                     });
                 }
             }
         }
     }
 
-    fn generate_volcano_level(&self, world: &mut AtomWorld) {
+    fn generate_volcano_level(&self, world: &mut AtomWorld, materials: &MaterialRegistry) {
         // Generate volcano with lava
         let center_x = world.width / 2;
         let center_y = world.height / 2;
@@ -181,9 +195,10 @@ impl LevelGenerator {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type: AtomType::Stone,
                         velocity: Vec2::ZERO,
-                        mass: AtomType::Stone.mass(),
+                        mass: materials.get(AtomType::Stone).mass,
                         lifetime: None,
                         temperature: 20.0,
+                        ..default()
                     });
                 }
                 // Add lava inside volcano
@@ -191,16 +206,17 @@ impl LevelGenerator {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type: AtomType::Fire,
                         velocity: Vec2::ZERO,
-                        mass: AtomType::Fire.mass(),
+                        mass: materials.get(AtomType::Fire).mass,
                         lifetime: Some(30.0),
                         temperature: 1000.0,
+                        ..default()
                     });
                 }
             }
         }
     }
 
-    fn generate_laboratory_level(&self, world: &mut AtomWorld) {
+    fn generate_laboratory_level(&self, world: &mut AtomWorld, materials: &MaterialRegistry) {
         // Generate laboratory with containment areas
         let room_width = 40;
         let room_height = 30;
@@ -220,9 +236,10 @@ impl LevelGenerator {
                             world.set_atom(x as i32, y as i32, Atom {
                                 atom_type: AtomType::Stone,
                                 velocity: Vec2::ZERO,
-                                mass: AtomType::Stone.mass(),
+                                mass: materials.get(AtomType::Stone).mass,
                                 lifetime: None,
                                 temperature: 20.0,
+                                ..default()
                             });
                         }
                     }
@@ -231,27 +248,28 @@ impl LevelGenerator {
                 // Add experimental materials based on room
                 let experiment_type = (room_x + room_y) % 4;
                 match experiment_type {
-                    0 => self.add_acid_experiment(world, start_x + 5, start_y + 5, 10),
-                    1 => self.add_fire_experiment(world, start_x + 5, start_y + 5, 10),
-                    2 => self.add_water_experiment(world, start_x + 5, start_y + 5, 10),
-                    3 => self.add_mixed_experiment(world, start_x + 5, start_y + 5, 10),
+                    0 => self.add_acid_experiment(world, materials, start_x + 5, start_y + 5, 10),
+                    1 => self.add_fire_experiment(world, materials, start_x + 5, start_y + 5, 10),
+                    2 => self.add_water_experiment(world, materials, start_x + 5, start_y + 5, 10),
+                    3 => self.add_mixed_experiment(world, materials, start_x + 5, start_y + 5, 10),
                     _ => {}
                 }
             }
         }
     }
 
-    fn add_water_features(&self, world: &mut AtomWorld, probability: f32) {
+    fn add_water_features(&self, world: &mut AtomWorld, materials: &MaterialRegistry, probability: f32) {
         for y in 0..world.height {
             for x in 0..world.width {
                 if world.get_atom(x as i32, y as i32).map_or(true, |a| a.atom_type == AtomType::Empty) {
-                    if rand::random::<f32>() < probability {
+                    if world.rng.gen::<f32>() < probability {
                         world.set_atom(x as i32, y as i32, Atom {
                             atom_type: AtomType::Water,
                             velocity: Vec2::ZERO,
-                            mass: AtomType::Water.mass(),
+                            mass: materials.get(AtomType::Water).mass,
                             lifetime: None,
                             temperature: 20.0,
+                            ..default()
                         });
                     }
                 }
@@ -259,59 +277,62 @@ impl LevelGenerator {
         }
     }
 
-    fn add_acid_experiment(&self, world: &mut AtomWorld, start_x: usize, start_y: usize, size: usize) {
+    fn add_acid_experiment(&self, world: &mut AtomWorld, materials: &MaterialRegistry, start_x: usize, start_y: usize, size: usize) {
         for x in start_x..start_x + size {
             for y in start_y..start_y + size {
-                if rand::random::<f32>() < 0.6 {
+                if world.rng.gen::<f32>() < 0.6 {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type: AtomType::Acid,
                         velocity: Vec2::ZERO,
-                        mass: AtomType::Acid.mass(),
+                        mass: materials.get(AtomType::Acid).mass,
                         lifetime: None,
                         temperature: 20.0,
+                        ..default()
                     });
                 }
             }
         }
     }
 
-    fn add_fire_experiment(&self, world: &mut AtomWorld, start_x: usize, start_y: usize, size: usize) {
+    fn add_fire_experiment(&self, world: &mut AtomWorld, materials: &MaterialRegistry, start_x: usize, start_y: usize, size: usize) {
         for x in start_x..start_x + size {
             for y in start_y..start_y + size {
-                if rand::random::<f32>() < 0.4 {
+                if world.rng.gen::<f32>() < 0.4 {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type: AtomType::Fire,
                         velocity: Vec2::ZERO,
-                        mass: AtomType::Fire.mass(),
+                        mass: materials.get(AtomType::Fire).mass,
                         lifetime: Some(10.0),
                         temperature: 800.0,
+                        ..default()
                     });
                 }
             }
         }
     }
 
-    fn add_water_experiment(&self, world: &mut AtomWorld, start_x: usize, start_y: usize, size: usize) {
+    fn add_water_experiment(&self, world: &mut AtomWorld, materials: &MaterialRegistry, start_x: usize, start_y: usize, size: usize) {
         for x in start_x..start_x + size {
             for y in start_y..start_y + size {
-                if rand::random::<f32>() < 0.7 {
+                if world.rng.gen::<f32>() < 0.7 {
                     world.set_atom(x as i32, y as i32, Atom {
                         atom_type: AtomType::Water,
                         velocity: Vec2::ZERO,
-                        mass: AtomType::Water.mass(),
+                        mass: materials.get(AtomType::Water).mass,
                         lifetime: None,
                         temperature: 20.0,
+                        ..default()
                     });
                 }
             }
         }
     }
 
-    fn add_mixed_experiment(&self, world: &mut AtomWorld, start_x: usize, start_y: usize, size: usize) {
+    fn add_mixed_experiment(&self, world: &mut AtomWorld, materials: &MaterialRegistry, start_x: usize, start_y: usize, size: usize) {
         // Mix of different atoms for experimentation
         for x in start_x..start_x + size {
             for y in start_y..start_y + size {
-                let atom_type = match rand::random::<f32>() {
+                let atom_type = match world.rng.gen::<f32>() {
                     r if r < 0.25 => AtomType::Water,
                     r if r < 0.5 => AtomType::Acid,
                     r if r < 0.75 => AtomType::Fire,
@@ -321,9 +342,10 @@ impl LevelGenerator {
                 world.set_atom(x as i32, y as i32, Atom {
                     atom_type,
                     velocity: Vec2::ZERO,
-                    mass: atom_type.mass(),
+                    mass: materials.get(atom_type).mass,
                     lifetime: if atom_type == AtomType::Fire { Some(8.0) } else { None },
                     temperature: if atom_type == AtomType::Fire { 700.0 } else { 20.0 },
+                    ..default()
                 });
             }
         }
@@ -339,6 +361,18 @@ pub enum LevelType {
     Laboratory,
 }
 
+impl LevelType {
+    // Light that reaches everywhere in the level, see LightMap::ambient
+    pub fn ambient_light(&self) -> Vec3 {
+        match self {
+            LevelType::Cave => Vec3::splat(0.05),
+            LevelType::Volcano => Vec3::new(0.35, 0.25, 0.25),
+            LevelType::Laboratory => Vec3::splat(0.6),
+            LevelType::Island | LevelType::Mountain => Vec3::ONE,
+        }
+    }
+}
+
 // Level manager for switching between levels
 #[derive(Resource)]
 pub struct LevelManager {
@@ -382,8 +416,8 @@ impl LevelManager {
         self.level_types[self.current_level]
     }
 
-    pub fn generate_current_level(&self, width: usize, height: usize) -> AtomWorld {
-        self.generator.generate_level(width, height, self.get_current_level_type())
+    pub fn generate_current_level(&self, width: usize, height: usize, materials: &MaterialRegistry) -> AtomWorld {
+        self.generator.generate_level(width, height, self.get_current_level_type(), materials)
     }
 }
 
@@ -391,9 +425,12 @@ impl LevelManager {
 pub fn load_level(
     mut commands: Commands,
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut light_map: ResMut<LightMap>,
     level_manager: Res<LevelManager>,
 ) {
-    *world = AtomWorldResource(level_manager.generate_current_level(200, 150));
+    *world = AtomWorldResource(level_manager.generate_current_level(200, 150, &materials.0));
+    light_map.ambient = level_manager.get_current_level_type().ambient_light();
 }
 
 // Level transition system
@@ -401,16 +438,20 @@ pub fn level_transition_system(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut level_manager: ResMut<LevelManager>,
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut light_map: ResMut<LightMap>,
 ) {
     if keyboard_input.just_pressed(KeyCode::BracketRight) { // ] key
         let level_type = level_manager.next_level();
-        *world = AtomWorldResource(level_manager.generator.generate_level(200, 150, level_type));
+        *world = AtomWorldResource(level_manager.generator.generate_level(200, 150, level_type, &materials.0));
+        light_map.ambient = level_type.ambient_light();
         println!("Loaded level: {:?}", level_type);
     }
 
     if keyboard_input.just_pressed(KeyCode::BracketLeft) { // [ key
         let level_type = level_manager.previous_level();
-        *world = AtomWorldResource(level_manager.generator.generate_level(200, 150, level_type));
+        *world = AtomWorldResource(level_manager.generator.generate_level(200, 150, level_type, &materials.0));
+        light_map.ambient = level_type.ambient_light();
         println!("Loaded level: {:?}", level_type);
     }
 }
@@ -0,0 +1,342 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use crate::atoms::{Atom, AtomType, AtomWorld, AtomWorldResource, Chunk, DirtyRect, CHUNK_SIZE, GLOW_FULL_TEMPERATURE, GLOW_TEMPERATURE};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource, Phase};
+use crate::world_space::WorldSpace;
+
+// 2D lighting driven by the atom grid. Emissive atoms (fire, lava, glowing ore,
+// anything red hot) and light sources like spells light up their surroundings.
+// Light fades with distance and is soaked up by whatever it passes through, so
+// the ground casts shadows that soften around corners. It's worked out on the
+// CPU one chunk at a time, and only for chunks where something could have
+// changed it.
+
+// Share of its light a cell passes on to the next one through open space
+const FALLOFF: f32 = 0.9;
+const DIAGONAL_FALLOFF: f32 = 0.86; // FALLOFF ^ sqrt(2)
+// How far past its own edges a chunk looks for light. After this many cells
+// FALLOFF has left too little to see.
+const LIGHT_MARGIN: i32 = 32;
+// Rounds of sweeps per relight. One round lights everything in plain view, the
+// second lets light bend around corners into overhangs.
+const LIGHT_ROUNDS: usize = 2;
+// Brightest light can get. Above 1 it brightens atoms past their own colour.
+const MAX_LIGHT: f32 = 1.5;
+// Smallest change in light worth redrawing a chunk for
+const LIGHT_EPSILON: f32 = 1.0 / 255.0;
+
+// Colour of red-hot atoms at full glow
+const HEAT_LIGHT: Vec3 = Vec3::new(1.0, 0.45, 0.1);
+
+// Something other than an atom that gives off light, like a flying spell
+#[derive(Component, Debug, Clone, Copy)]
+pub struct LightSource {
+    // Light at the source, as an RGB colour whose brightness is its strength
+    pub color: Vec3,
+}
+
+#[derive(Resource)]
+pub struct LightMap {
+    // Light that reaches everywhere regardless of sources, like daylight. Caves
+    // are close to black.
+    pub ambient: Vec3,
+    // Light from sources, per chunk cell
+    chunks: HashMap<IVec2, Vec<Vec3>>,
+    // Light sources and ambient as of the last update
+    sources: Vec<(IVec2, Vec3)>,
+    drawn_ambient: Vec3,
+    step: u64,
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        Self {
+            ambient: Vec3::splat(0.7),
+            chunks: HashMap::default(),
+            sources: Vec::new(),
+            drawn_ambient: Vec3::splat(0.7),
+            step: 0,
+        }
+    }
+}
+
+impl LightMap {
+    // Light falling on a cell, ambient included
+    pub fn light_at(&self, cell: IVec2) -> Vec3 {
+        let (chunk, local) = AtomWorld::chunk_coords(cell.x, cell.y);
+        let light = self
+            .chunks
+            .get(&chunk)
+            .map_or(Vec3::ZERO, |light| light[Chunk::local_index(local)]);
+        (self.ambient + light).min(Vec3::splat(MAX_LIGHT))
+    }
+}
+
+// Light an atom gives off: its material's own, the flames of anything burning,
+// and a glow once it's red hot
+pub fn atom_emission(materials: &MaterialRegistry, atom: &Atom) -> Vec3 {
+    if atom.atom_type == AtomType::Empty {
+        return Vec3::ZERO;
+    }
+    let mut light = material_emission(materials, atom.atom_type);
+    if atom.burning {
+        light = light.max(material_emission(materials, AtomType::Fire));
+    }
+    let heat = (atom.temperature - GLOW_TEMPERATURE) / (GLOW_FULL_TEMPERATURE - GLOW_TEMPERATURE);
+    if heat > 0.0 {
+        light = light.max(HEAT_LIGHT * heat.min(1.0));
+    }
+    light
+}
+
+fn material_emission(materials: &MaterialRegistry, atom_type: AtomType) -> Vec3 {
+    materials.get(atom_type).emission.map_or(Vec3::ZERO, Vec3::from)
+}
+
+// Share of the light reaching a cell that it lets through. Solid ground takes a
+// little light at its surface and throws a shadow behind it.
+fn transmission(materials: &MaterialRegistry, atom: Option<&Atom>) -> f32 {
+    match atom.map_or(Phase::Empty, |atom| materials.get(atom.atom_type).phase) {
+        Phase::Empty | Phase::Gas => 1.0,
+        Phase::Liquid => 0.8,
+        Phase::Powder | Phase::Solid => 0.4,
+    }
+}
+
+// Light from every source within reach of the chunk at `position`
+pub fn light_chunk(world: &AtomWorld, materials: &MaterialRegistry, position: IVec2, sources: &[(IVec2, Vec3)]) -> Vec<Vec3> {
+    let origin = position * CHUNK_SIZE - IVec2::splat(LIGHT_MARGIN);
+    let size = CHUNK_SIZE + LIGHT_MARGIN * 2;
+    let index = |x: i32, y: i32| (y * size + x) as usize;
+
+    let mut light = vec![Vec3::ZERO; (size * size) as usize];
+    let mut passes = vec![1.0; (size * size) as usize];
+    for y in 0..size {
+        for x in 0..size {
+            let cell = origin + IVec2::new(x, y);
+            let atom = world.get_atom(cell.x, cell.y);
+            light[index(x, y)] = atom.map_or(Vec3::ZERO, |atom| atom_emission(materials, atom));
+            passes[index(x, y)] = transmission(materials, atom);
+        }
+    }
+    for &(cell, color) in sources {
+        let local = cell - origin;
+        if local.cmpge(IVec2::ZERO).all() && local.cmplt(IVec2::splat(size)).all() {
+            let i = index(local.x, local.y);
+            light[i] = light[i].max(color);
+        }
+    }
+
+    // Each sweep carries light from the neighbours already visited. Going over the
+    // region forwards then backwards reaches every direction.
+    let forward = [(-1, 0, FALLOFF), (0, -1, FALLOFF), (-1, -1, DIAGONAL_FALLOFF), (1, -1, DIAGONAL_FALLOFF)];
+    let backward = [(1, 0, FALLOFF), (0, 1, FALLOFF), (1, 1, DIAGONAL_FALLOFF), (-1, 1, DIAGONAL_FALLOFF)];
+    let mut spread = |x: i32, y: i32, neighbours: &[(i32, i32, f32); 4]| {
+        let mut value = light[index(x, y)];
+        for &(dx, dy, falloff) in neighbours {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx >= 0 && ny >= 0 && nx < size && ny < size {
+                value = value.max(light[index(nx, ny)] * falloff * passes[index(x, y)]);
+            }
+        }
+        light[index(x, y)] = value;
+    };
+    for _ in 0..LIGHT_ROUNDS {
+        for y in 0..size {
+            for x in 0..size {
+                spread(x, y, &forward);
+            }
+        }
+        for y in (0..size).rev() {
+            for x in (0..size).rev() {
+                spread(x, y, &backward);
+            }
+        }
+    }
+
+    let mut chunk_light = vec![Vec3::ZERO; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+    for y in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            chunk_light[Chunk::local_index(IVec2::new(x, y))] = light[index(x + LIGHT_MARGIN, y + LIGHT_MARGIN)];
+        }
+    }
+    chunk_light
+}
+
+// Chunks whose light region (the chunk plus LIGHT_MARGIN) overlaps the cells from
+// `min` to `max` (inclusive)
+fn chunks_lit_from(min: IVec2, max: IVec2) -> impl Iterator<Item = IVec2> {
+    let (min, _) = AtomWorld::chunk_coords(min.x - LIGHT_MARGIN, min.y - LIGHT_MARGIN);
+    let (max, _) = AtomWorld::chunk_coords(max.x + LIGHT_MARGIN, max.y + LIGHT_MARGIN);
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+}
+
+// Local cells whose light moved by at least LIGHT_EPSILON, as the rect around them
+fn changed_light(old: &[Vec3], new: &[Vec3]) -> Option<DirtyRect> {
+    let mut changed: Option<DirtyRect> = None;
+    for (i, (a, b)) in old.iter().zip(new).enumerate() {
+        if (*a - *b).abs().max_element() < LIGHT_EPSILON {
+            continue;
+        }
+        let local = IVec2::new(i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE);
+        match changed.as_mut() {
+            Some(rect) => rect.include(local),
+            None => changed = Some(DirtyRect::new(local)),
+        }
+    }
+    changed
+}
+
+// System to relight the chunks within reach of cells that changed since they were
+// last drawn, and queue just the cells whose light changed for redrawing. It reads
+// the chunks' changed rects without taking them; render_atoms does that after.
+pub fn update_lighting(
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut light_map: ResMut<LightMap>,
+    lights: Query<(&GlobalTransform, &LightSource)>,
+) {
+    let world = &mut world.0;
+    let space = WorldSpace::of(world);
+
+    // A new level starts from scratch
+    if world.step < light_map.step {
+        light_map.chunks.clear();
+    }
+    light_map.step = world.step;
+
+    let sources: Vec<(IVec2, Vec3)> = lights
+        .iter()
+        .map(|(transform, light)| (space.world_to_grid(transform.translation().truncate()), light.color))
+        .collect();
+
+    let mut relight: HashSet<IVec2> = HashSet::default();
+    for (position, chunk) in world.chunks.iter() {
+        if !light_map.chunks.contains_key(position) {
+            relight.insert(*position);
+        }
+        if let Some(rect) = chunk.changed_rect {
+            relight.extend(chunks_lit_from(chunk.origin() + rect.min, chunk.origin() + rect.max));
+        }
+    }
+    if sources != light_map.sources {
+        // Light has to come off wherever a source was as well as go on where it is
+        for &(cell, _) in light_map.sources.iter().chain(&sources) {
+            relight.extend(chunks_lit_from(cell, cell));
+        }
+    }
+
+    for position in relight {
+        if !world.chunks.contains_key(&position) {
+            continue;
+        }
+        let light = light_chunk(world, &materials.0, position, &sources);
+        let changed = match light_map.chunks.get(&position) {
+            Some(old) => changed_light(old, &light),
+            None => Some(DirtyRect::full()),
+        };
+        if let Some(rect) = changed {
+            light_map.chunks.insert(position, light);
+            let chunk = world.chunks.get_mut(&position).unwrap();
+            chunk.mark_changed(rect.min);
+            chunk.mark_changed(rect.max);
+        }
+    }
+    light_map.sources = sources;
+    light_map.chunks.retain(|position, _| world.chunks.contains_key(position));
+
+    // Ambient light doesn't need relighting, just redrawing everything
+    if light_map.ambient != light_map.drawn_ambient {
+        light_map.drawn_ambient = light_map.ambient;
+        for chunk in world.chunks.values_mut() {
+            chunk.mark_all_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    const SOURCE: IVec2 = IVec2::new(20, 32);
+
+    fn world_with(atoms: &[(IVec2, AtomType)]) -> AtomWorld {
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        for &(cell, atom_type) in atoms {
+            world.set_atom(cell.x, cell.y, Atom {
+                atom_type,
+                mass: materials.get(atom_type).mass,
+                temperature: 20.0,
+                ..default()
+            });
+        }
+        world
+    }
+
+    fn light_at(world: &AtomWorld, cell: IVec2) -> f32 {
+        light_chunk(world, &MaterialRegistry::builtin(), IVec2::ZERO, &[])[Chunk::local_index(cell)].max_element()
+    }
+
+    #[test]
+    fn emissive_atoms_light_up_empty_space_around_them() {
+        let world = world_with(&[(SOURCE, AtomType::Fire)]);
+
+        let near = light_at(&world, SOURCE + IVec2::new(2, 0));
+        let far = light_at(&world, SOURCE + IVec2::new(8, 0));
+        assert!(far > 0.0);
+        assert!(near > far);
+        assert_eq!(light_at(&world_with(&[]), SOURCE + IVec2::new(2, 0)), 0.0);
+    }
+
+    #[test]
+    fn walls_cast_shadows() {
+        let cell = SOURCE + IVec2::new(8, 0);
+        let open = world_with(&[(SOURCE, AtomType::Fire)]);
+
+        let mut atoms = vec![(SOURCE, AtomType::Fire)];
+        for y in SOURCE.y - 12..=SOURCE.y + 12 {
+            for x in SOURCE.x + 3..=SOURCE.x + 5 {
+                atoms.push((IVec2::new(x, y), AtomType::Stone));
+            }
+        }
+        let walled = world_with(&atoms);
+
+        assert!(light_at(&walled, cell) < light_at(&open, cell) * 0.5);
+    }
+
+    // Run update_lighting once, then hand the changed rects over like render_atoms
+    // would. Returns the rects it left.
+    fn relight(world: &mut World) -> HashMap<IVec2, DirtyRect> {
+        world.run_system_once(update_lighting);
+        let grid = &mut world.resource_mut::<AtomWorldResource>().0;
+        grid.chunks
+            .iter_mut()
+            .filter_map(|(position, chunk)| chunk.take_changed_rect().map(|rect| (*position, rect)))
+            .collect()
+    }
+
+    #[test]
+    fn only_cells_whose_light_changed_are_redrawn() {
+        // Three chunks side by side, so the last one is out of reach of the first
+        let mut world = World::new();
+        world.insert_resource(AtomWorldResource(AtomWorld::new(CHUNK_SIZE as usize * 3, CHUNK_SIZE as usize)));
+        world.insert_resource(MaterialRegistryResource(std::sync::Arc::new(MaterialRegistry::builtin())));
+        world.insert_resource(LightMap::default());
+
+        assert_eq!(relight(&mut world).len(), 3, "new chunks are drawn whole");
+        assert!(relight(&mut world).is_empty(), "nothing changed");
+
+        let materials = MaterialRegistry::builtin();
+        let fire = Atom { atom_type: AtomType::Fire, mass: materials.get(AtomType::Fire).mass, ..default() };
+        world.resource_mut::<AtomWorldResource>().0.set_atom(2, 2, fire);
+        let changed = relight(&mut world);
+
+        let rect = changed[&IVec2::ZERO];
+        assert!(rect.contains(IVec2::new(2, 2)));
+        assert_ne!(rect, DirtyRect::full(), "the whole chunk was redrawn");
+        assert!(!changed.contains_key(&IVec2::new(2, 0)), "a chunk out of reach was redrawn");
+        assert!(relight(&mut world).is_empty(), "the fire's light was worked out again");
+    }
+}
@@ -1,5 +1,14 @@
 use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
 use std::collections::HashMap;
+use crate::atoms::{Atom, AtomType, AtomWorld, AtomWorldResource, RayHit, SIMULATION_DT};
+use crate::explosions::Explosion;
+use crate::game::{Health, Player};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource};
+use crate::lighting::LightSource;
+use crate::physics::{RigidBodyObject, TerrainCollider};
+use crate::rendering::PixelCamera;
+use crate::world_space::WorldSpace;
 
 // Magic system based on "Spellcasting 3.0: Perks" and "Ability Subroutines"
 
@@ -104,6 +113,9 @@ pub struct Spell {
     pub cooldown_timer: f32,
     pub cast_position: Vec2,
     pub caster: Entity,
+    // Extra subroutines for conditions no perk stands for, run by the last
+    // projectile the spell fires
+    pub subroutines: Vec<SpellSubroutine>,
 }
 
 impl Spell {
@@ -116,44 +128,179 @@ impl Spell {
             cooldown_timer: 0.0,
             cast_position: Vec2::ZERO,
             caster: Entity::PLACEHOLDER,
+            subroutines: Vec::new(),
         }
     }
 
+    pub fn with_subroutine(mut self, condition: SubroutineCondition, perks: Vec<SpellPerk>) -> Self {
+        self.mana_cost += perks.iter().map(|p| p.cost()).sum::<u32>();
+        self.subroutines.push(SpellSubroutine { perks, condition });
+        self
+    }
+
     pub fn can_cast(&self, mana: u32) -> bool {
         self.cooldown_timer <= 0.0 && mana >= self.mana_cost
     }
 
-    pub fn cast(&mut self, position: Vec2, caster: Entity) -> SpellInstance {
+    // Colour of the light the spell gives off in flight
+    pub fn light_color(&self) -> Vec3 {
+        let color = self.perks.iter().find_map(|perk| match perk {
+            SpellPerk::DamageFire => Some(Vec3::new(1.0, 0.55, 0.2)),
+            SpellPerk::DamageIce => Some(Vec3::new(0.4, 0.7, 1.0)),
+            SpellPerk::DamagePoison => Some(Vec3::new(0.4, 1.0, 0.3)),
+            _ => None,
+        });
+        color.unwrap_or(Vec3::new(0.8, 0.3, 1.0))
+    }
+
+    // One instance per projectile the spell's program fires, not yet placed or moving
+    pub fn cast(&mut self, position: Vec2, caster: Entity) -> Vec<SpellInstance> {
         self.cast_position = position;
         self.caster = caster;
         self.cooldown_timer = self.cooldown;
 
-        SpellInstance::new(self.clone())
+        let mut programs = compile_perks(&self.perks);
+        if let Some(last) = programs.last_mut() {
+            last.subroutines.extend(self.subroutines.iter().cloned());
+        }
+        programs
+            .into_iter()
+            .map(|program| SpellInstance::from_program(self, program))
+            .collect()
     }
 }
 
+// Spells as small programs, the way Noita's wands work. Perks are read in order:
+// modifiers pile up and go to the next Projectile perk, and a trigger hands
+// everything after it to the projectile before it, as a subroutine that casts
+// once its condition is met. Modifiers with no projectile after them go to the
+// last one, so [Projectile, Damage] is still a damaging bolt, and a spell with no
+// Projectile perk at all fires a single one. A trigger with nothing after it sets
+// off the projectile itself instead, like a fuse.
+
+// Seconds a TriggerTimer projectile flies before its subroutine casts
+const TRIGGER_TIMER_DELAY: f32 = 1.0;
+
+// One projectile a spell's program fires
+#[derive(Debug, Clone, Default)]
+pub struct ProjectileProgram {
+    // Modifiers it carries, in the order they were read
+    pub perks: Vec<SpellPerk>,
+    pub subroutines: Vec<SpellSubroutine>,
+}
+
+pub fn compile_perks(perks: &[SpellPerk]) -> Vec<ProjectileProgram> {
+    let mut programs: Vec<ProjectileProgram> = Vec::new();
+    let mut pending = Vec::new();
+
+    for (i, &perk) in perks.iter().enumerate() {
+        let condition = match perk {
+            SpellPerk::Projectile => {
+                programs.push(ProjectileProgram {
+                    perks: std::mem::take(&mut pending),
+                    subroutines: Vec::new(),
+                });
+                continue;
+            }
+            SpellPerk::TriggerTimer => SubroutineCondition::Timer(TRIGGER_TIMER_DELAY),
+            SpellPerk::TriggerDeath => SubroutineCondition::OnDeath,
+            _ => {
+                pending.push(perk);
+                continue;
+            }
+        };
+
+        finish_programs(&mut programs, pending).subroutines.push(SpellSubroutine {
+            perks: perks[i + 1..].to_vec(),
+            condition,
+        });
+        return programs;
+    }
+
+    finish_programs(&mut programs, pending);
+    programs
+}
+
+// Give modifiers left at the end to the last projectile, making one if there's none
+fn finish_programs(programs: &mut Vec<ProjectileProgram>, pending: Vec<SpellPerk>) -> &mut ProjectileProgram {
+    if programs.is_empty() {
+        programs.push(ProjectileProgram::default());
+    }
+    let last = programs.last_mut().unwrap();
+    last.perks.extend(pending);
+    last
+}
+
 // Active spell instance during execution
 #[derive(Component, Debug, Clone)]
 pub struct SpellInstance {
     pub spell: Spell,
     pub position: Vec2,
+    // Where it was before the last update, for sweeping its path through the world
+    pub previous_position: Vec2,
     pub velocity: Vec2,
+    // Rebounds and pass-throughs left before it detonates on impact
+    pub bounces: u32,
+    pub pierces: u32,
     pub lifetime: f32,
     pub max_lifetime: f32,
+    // Cells flown so far
+    pub distance: f32,
+    // Set when it has detonated and is only waiting to be removed
+    pub spent: bool,
+    // Where it hit something this frame, if it did
+    pub impact: Option<SpellImpact>,
+    // What a homing projectile is locked on to, and where that was last seen
+    pub target: Option<(Entity, Vec2)>,
+    // Links of a chain reaction before this one
+    pub chain: u32,
+    pub subroutines: Vec<SpellSubroutine>,
     pub effects: Vec<SpellEffect>,
-    pub children: Vec<SpellInstance>,
+    // Projectile this one came from (by Multiply, Split, a subroutine or a chain
+    // reaction), if any. It may be gone by now.
+    pub parent: Option<Entity>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpellImpact {
+    // World position of the free cell in front of what was hit
+    pub position: Vec2,
+    // Surface normal, in world space
+    pub normal: Vec2,
 }
 
 impl SpellInstance {
+    // A projectile running one program of `spell`
+    pub fn from_program(spell: &Spell, program: ProjectileProgram) -> Self {
+        let mut instance = Self::new(Spell {
+            perks: program.perks,
+            subroutines: Vec::new(),
+            ..spell.clone()
+        });
+        instance.subroutines = program.subroutines;
+        instance
+    }
+
     pub fn new(spell: Spell) -> Self {
+        let bounces = if spell.perks.contains(&SpellPerk::ProjectileBounce) { MAX_BOUNCES } else { 0 };
+        let pierces = if spell.perks.contains(&SpellPerk::ProjectilePierce) { MAX_PIERCES } else { 0 };
         Self {
             spell,
             position: Vec2::ZERO,
+            previous_position: Vec2::ZERO,
             velocity: Vec2::ZERO,
+            bounces,
+            pierces,
             lifetime: 0.0,
             max_lifetime: 5.0, // Default lifetime
+            distance: 0.0,
+            spent: false,
+            impact: None,
+            target: None,
+            chain: 0,
+            subroutines: Vec::new(),
             effects: Vec::new(),
-            children: Vec::new(),
+            parent: None,
         }
     }
 
@@ -164,19 +311,63 @@ impl SpellInstance {
         self.apply_perks(dt);
 
         // Update position
+        self.previous_position = self.position;
         self.position += self.velocity * dt;
+        self.distance += (self.velocity * dt).length();
 
         // Update effects
         self.effects.retain_mut(|effect| {
             effect.update(dt);
             effect.is_alive()
         });
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.spent || self.lifetime >= self.max_lifetime
+    }
+
+    // Projectiles a subroutine casts from this one. They carry on the way it was
+    // going, or bounce back out of whatever it just hit, fanned out if there are
+    // several.
+    pub fn fork(&self, subroutine: &SpellSubroutine) -> Vec<SpellInstance> {
+        let spell = Spell {
+            perks: subroutine.perks.clone(),
+            subroutines: Vec::new(),
+            cast_position: self.position,
+            ..self.spell.clone()
+        };
+        let (position, velocity) = match self.impact {
+            Some(impact) => (impact.position, self.velocity - 2.0 * self.velocity.dot(impact.normal) * impact.normal),
+            None => (self.position, self.velocity),
+        };
+        let direction = velocity.try_normalize().unwrap_or(Vec2::Y);
+        let speed = velocity.length().max(MIN_FORK_SPEED);
 
-        // Update children
-        for child in &mut self.children {
-            child.update(dt);
+        let programs = compile_perks(&subroutine.perks);
+        let count = programs.len();
+        programs
+            .into_iter()
+            .enumerate()
+            .map(|(i, program)| {
+                let mut child = SpellInstance::from_program(&spell, program);
+                child.position = position;
+                child.previous_position = position;
+                child.velocity = Vec2::from_angle(spread_angle(i, count)).rotate(direction) * speed;
+                child.create_effects();
+                child
+            })
+            .collect()
+    }
+
+    // Copy of this projectile turned by `angle`, for the projectile `parent` to
+    // hand its modifiers on to
+    fn offspring(&self, parent: Entity, angle: f32) -> SpellInstance {
+        SpellInstance {
+            velocity: Vec2::from_angle(angle).rotate(self.velocity),
+            impact: None,
+            parent: Some(parent),
+            ..self.clone()
         }
-        self.children.retain(|child| child.lifetime < child.max_lifetime);
     }
 
     fn apply_perks(&mut self, dt: f32) {
@@ -185,11 +376,15 @@ impl SpellInstance {
                 SpellPerk::ProjectileGravity => {
                     self.velocity.y -= 30.0 * dt; // Gravity
                 }
-                SpellPerk::ProjectileBounce => {
-                    // Bounce off boundaries (would need collision detection)
-                }
                 SpellPerk::ProjectileHoming => {
-                    // Home towards nearest enemy (would need target finding)
+                    // Turn towards the target (picked by spell_homing), keeping speed
+                    if let Some((_, target)) = self.target {
+                        let turn = self.velocity.angle_between(target - self.position);
+                        let max_turn = HOMING_TURN_RATE * dt;
+                        if turn.is_finite() {
+                            self.velocity = Vec2::from_angle(turn.clamp(-max_turn, max_turn)).rotate(self.velocity);
+                        }
+                    }
                 }
                 SpellPerk::Accelerate => {
                     let dir = self.velocity.normalize_or_zero();
@@ -218,22 +413,12 @@ impl SpellInstance {
                 SpellPerk::DamagePoison => {
                     self.effects.push(SpellEffect::PoisonDamage { amount: 5.0, duration: 5.0 });
                 }
-                SpellPerk::Explosion => {
-                    self.effects.push(SpellEffect::Explosion { radius: 50.0, damage: 30.0 });
-                }
-                SpellPerk::Multiply => {
-                    // Create multiple projectiles
-                    for i in 1..3 {
-                        let angle = (i as f32 / 3.0) * std::f32::consts::TAU;
-                        let mut child = SpellInstance::new(self.spell.clone());
-                        child.velocity = Vec2::new(angle.cos(), angle.sin()) * 100.0;
-                        child.position = self.position;
-                        self.children.push(child);
-                    }
+                SpellPerk::DamageIce => {
+                    self.effects.push(SpellEffect::IceDamage { amount: 6.0, duration: 3.0 });
                 }
-                SpellPerk::Split => {
-                    // Split into smaller projectiles
-                    // Implementation similar to multiply but smaller
+                SpellPerk::Explosion => {
+                    // Radius in cells
+                    self.effects.push(SpellEffect::Explosion { radius: 10.0, damage: 30.0 });
                 }
                 _ => {}
             }
@@ -248,6 +433,7 @@ pub enum SpellEffect {
     AreaDamage { radius: f32, amount: f32 },
     FireDamage { amount: f32, duration: f32 },
     PoisonDamage { amount: f32, duration: f32 },
+    IceDamage { amount: f32, duration: f32 },
     Explosion { radius: f32, damage: f32 },
     Heal { amount: f32 },
     Buff { stat: String, amount: f32, duration: f32 },
@@ -259,6 +445,7 @@ impl SpellEffect {
         match self {
             SpellEffect::FireDamage { duration, .. } |
             SpellEffect::PoisonDamage { duration, .. } |
+            SpellEffect::IceDamage { duration, .. } |
             SpellEffect::Buff { duration, .. } |
             SpellEffect::Debuff { duration, .. } => {
                 *duration -= dt;
@@ -267,10 +454,32 @@ impl SpellEffect {
         }
     }
 
+    // Weaken (or strengthen) the effect by `factor`
+    pub fn scale(&mut self, factor: f32) {
+        match self {
+            SpellEffect::Damage { amount } |
+            SpellEffect::FireDamage { amount, .. } |
+            SpellEffect::PoisonDamage { amount, .. } |
+            SpellEffect::IceDamage { amount, .. } |
+            SpellEffect::Heal { amount } |
+            SpellEffect::Buff { amount, .. } |
+            SpellEffect::Debuff { amount, .. } => *amount *= factor,
+            SpellEffect::AreaDamage { radius, amount } => {
+                *radius *= factor;
+                *amount *= factor;
+            }
+            SpellEffect::Explosion { radius, damage } => {
+                *radius *= factor;
+                *damage *= factor;
+            }
+        }
+    }
+
     pub fn is_alive(&self) -> bool {
         match self {
             SpellEffect::FireDamage { duration, .. } |
             SpellEffect::PoisonDamage { duration, .. } |
+            SpellEffect::IceDamage { duration, .. } |
             SpellEffect::Buff { duration, .. } |
             SpellEffect::Debuff { duration, .. } => *duration > 0.0,
             _ => true, // Instant effects
@@ -319,6 +528,19 @@ pub enum SubroutineCondition {
     HealthPercent(f32),
 }
 
+impl SubroutineCondition {
+    // `caster_health` is the caster's health as a percentage, if they have any
+    pub fn is_met(&self, instance: &SpellInstance, caster_health: Option<f32>) -> bool {
+        match *self {
+            SubroutineCondition::OnHit => instance.impact.is_some(),
+            SubroutineCondition::OnDeath => instance.is_dead(),
+            SubroutineCondition::Timer(seconds) => instance.lifetime >= seconds,
+            SubroutineCondition::Distance(cells) => instance.distance >= cells,
+            SubroutineCondition::HealthPercent(percent) => caster_health.is_some_and(|health| health <= percent),
+        }
+    }
+}
+
 // Systems for magic
 pub fn update_magic_users(time: Res<Time>, mut query: Query<&mut MagicUser>) {
     let dt = time.delta_seconds();
@@ -337,17 +559,18 @@ pub fn update_magic_users(time: Res<Time>, mut query: Query<&mut MagicUser>) {
 
 pub fn cast_spell(
     mut commands: Commands,
-    mut magic_users: Query<(&mut MagicUser, &Transform)>,
+    mut magic_users: Query<(Entity, &mut MagicUser, &Transform)>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mouse_input: Res<ButtonInput<MouseButton>>,
     windows: Query<&Window>,
-    camera_query: Query<(&Camera, &GlobalTransform)>,
+    camera_query: Query<(&Camera, &GlobalTransform, &PixelCamera)>,
+    mut budget: ResMut<SpellBudget>,
 ) {
     if !mouse_input.just_pressed(MouseButton::Right) {
         return;
     }
 
-    for (mut magic_user, transform) in magic_users.iter_mut() {
+    for (caster, mut magic_user, transform) in magic_users.iter_mut() {
         if magic_user.spells.is_empty() {
             continue;
         }
@@ -365,30 +588,22 @@ pub fn cast_spell(
         }
 
         // Get mouse world position
-        if let Ok((camera, camera_transform)) = camera_query.get_single() {
+        if let Ok((camera, camera_transform, pixel_camera)) = camera_query.get_single() {
             if let Some(window) = windows.iter().next() {
                 if let Some(cursor_pos) = window.cursor_position() {
-                    if let Some(world_pos) = camera.viewport_to_world(camera_transform, cursor_pos) {
-                        let cast_pos = world_pos.origin.truncate();
-
+                    if let Some(cast_pos) = WorldSpace::screen_to_world(camera, camera_transform, pixel_camera, cursor_pos) {
                         // Cast the spell
-                        let mut instance = spell.cast(cast_pos, Entity::PLACEHOLDER);
-                        instance.position = transform.translation.truncate();
-                        instance.velocity = (cast_pos - instance.position).normalize() * 200.0;
-
-                        // Create spell entity
-                        commands.spawn((
-                            SpriteBundle {
-                                sprite: Sprite {
-                                    color: Color::rgb(0.8, 0.2, 1.0),
-                                    custom_size: Some(Vec2::new(8.0, 8.0)),
-                                    ..default()
-                                },
-                                transform: Transform::from_translation(instance.position.extend(0.0)),
-                                ..default()
-                            },
-                            instance,
-                        ));
+                        let origin = transform.translation.truncate();
+                        let direction = (cast_pos - origin).normalize_or_zero();
+                        let instances = spell.cast(cast_pos, caster);
+                        let count = instances.len();
+                        for (i, mut instance) in instances.into_iter().enumerate() {
+                            instance.position = origin;
+                            instance.previous_position = origin;
+                            instance.velocity = Vec2::from_angle(spread_angle(i, count)).rotate(direction) * SPELL_SPEED;
+                            instance.create_effects();
+                            spawn_spell(&mut commands, &mut budget, instance);
+                        }
 
                         magic_user.mana -= spell.mana_cost;
                     }
@@ -398,30 +613,680 @@ pub fn cast_spell(
     }
 }
 
-pub fn update_spell_instances(
-    time: Res<Time>,
+// Speed (world units per second) projectiles are cast at, and the slowest a
+// forked one sets off at
+const SPELL_SPEED: f32 = 200.0;
+const MIN_FORK_SPEED: f32 = 100.0;
+// Angle between projectiles cast together
+const SPELL_SPREAD: f32 = 0.15;
+
+// Turn for the `i`th of `count` projectiles cast together, fanned out around the aim
+fn spread_angle(i: usize, count: usize) -> f32 {
+    fan_angle(i, count, SPELL_SPREAD)
+}
+
+fn fan_angle(i: usize, count: usize, spread: f32) -> f32 {
+    (i as f32 - (count as f32 - 1.0) / 2.0) * spread
+}
+
+// Most projectiles that can be in flight at once, so runaway Multiply chains
+// can't bring the game to a halt. Spells cast past it fizzle.
+const MAX_LIVE_SPELLS: usize = 256;
+
+// Projectiles in flight, recounted every frame by count_live_spells and kept up
+// to date by spawn_spell in between
+#[derive(Resource, Default)]
+pub struct SpellBudget {
+    live: usize,
+}
+
+pub fn count_live_spells(mut budget: ResMut<SpellBudget>, spells: Query<(), With<SpellInstance>>) {
+    budget.live = spells.iter().count();
+}
+
+// Put a projectile into the world, if the budget has room for it
+pub fn spawn_spell(commands: &mut Commands, budget: &mut SpellBudget, instance: SpellInstance) -> bool {
+    if budget.live >= MAX_LIVE_SPELLS {
+        return false;
+    }
+    budget.live += 1;
+
+    let light = LightSource { color: instance.spell.light_color() };
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::srgb(0.8, 0.2, 1.0),
+                custom_size: Some(Vec2::new(3.0, 3.0)),
+                ..default()
+            },
+            transform: Transform::from_translation(instance.position.extend(0.0)),
+            ..default()
+        },
+        instance,
+        light,
+    ));
+    true
+}
+
+// Copies a projectile fans out into with Multiply, and pieces it breaks into with
+// Split, counting itself. Split pieces carry a share of its power.
+const MULTIPLY_COUNT: usize = 3;
+const MULTIPLY_SPREAD: f32 = 0.3;
+const SPLIT_COUNT: usize = 4;
+const SPLIT_SPREAD: f32 = 0.5;
+const SPLIT_POWER: f32 = 0.5;
+// Seconds a Split projectile flies whole
+const SPLIT_DELAY: f32 = 0.3;
+
+// Fan Multiply projectiles out as soon as they're cast and break Split ones up
+// once SPLIT_DELAY has passed. The children are projectiles of their own with
+// everything the original had but the perk that made them, so they don't go on
+// to multiply again.
+pub fn spawn_spell_children(
     mut commands: Commands,
-    mut query: Query<(Entity, &mut SpellInstance, &mut Transform)>,
+    mut budget: ResMut<SpellBudget>,
+    mut spells: Query<(Entity, &mut SpellInstance)>,
 ) {
-    let dt = time.delta_seconds();
+    for (entity, mut instance) in spells.iter_mut() {
+        if instance.is_dead() {
+            continue;
+        }
 
-    for (entity, mut instance, mut transform) in query.iter_mut() {
+        let (perk, count, spread) = if instance.spell.perks.contains(&SpellPerk::Multiply) {
+            (SpellPerk::Multiply, MULTIPLY_COUNT, MULTIPLY_SPREAD)
+        } else if instance.spell.perks.contains(&SpellPerk::Split) && instance.lifetime >= SPLIT_DELAY {
+            (SpellPerk::Split, SPLIT_COUNT, SPLIT_SPREAD)
+        } else {
+            continue;
+        };
+
+        instance.spell.perks.retain(|other| *other != perk);
+        if perk == SpellPerk::Split {
+            for effect in &mut instance.effects {
+                effect.scale(SPLIT_POWER);
+            }
+        }
+        for i in 1..count {
+            let child = instance.offspring(entity, fan_angle(i, count, spread));
+            spawn_spell(&mut commands, &mut budget, child);
+        }
+        let angle = fan_angle(0, count, spread);
+        instance.velocity = Vec2::from_angle(angle).rotate(instance.velocity);
+    }
+}
+
+// Projectiles move in step with the grid they carve and heat, a SIMULATION_DT per tick
+pub fn update_spell_instances(mut query: Query<(&mut SpellInstance, &mut Transform)>) {
+    let dt = SIMULATION_DT;
+
+    for (mut instance, mut transform) in query.iter_mut() {
         instance.update(dt);
         transform.translation = instance.position.extend(0.0);
+    }
+}
+
+// Cast the subroutines whose conditions have come true, and clear away spells
+// that are done. Each subroutine casts once, and one with no perks sets off the
+// projectile itself. A projectile that dies gets one last look for its OnDeath
+// ones first.
+pub fn run_spell_subroutines(
+    mut commands: Commands,
+    mut budget: ResMut<SpellBudget>,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut spells: Query<(Entity, &mut SpellInstance)>,
+    casters: Query<&Health>,
+) {
+    let world = &mut world.0;
+    let space = WorldSpace::of(world);
+
+    for (entity, mut instance) in spells.iter_mut() {
+        let caster_health = casters
+            .get(instance.spell.caster)
+            .ok()
+            .map(|health| health.current / health.max * 100.0);
+
+        let subroutines = std::mem::take(&mut instance.subroutines);
+        let (ready, waiting): (Vec<_>, Vec<_>) = subroutines
+            .into_iter()
+            .partition(|subroutine| subroutine.condition.is_met(&instance, caster_health));
+        for subroutine in &ready {
+            if subroutine.perks.is_empty() {
+                // A bare trigger: go off here, unless an impact already set it off
+                if instance.impact.is_none() {
+                    apply_impact(world, &materials.0, &instance.effects, space.world_to_grid(instance.position));
+                }
+                instance.spent = true;
+                continue;
+            }
+            for mut child in instance.fork(subroutine) {
+                child.parent = Some(entity);
+                spawn_spell(&mut commands, &mut budget, child);
+            }
+        }
+        instance.subroutines = waiting;
+        instance.impact = None;
 
-        // Remove dead spells
-        if instance.lifetime >= instance.max_lifetime {
+        if instance.is_dead() {
             commands.entity(entity).despawn();
         }
     }
 }
 
+// Radians per second a homing projectile can turn, and how far (world units) it
+// looks for something to lock on to
+const HOMING_TURN_RATE: f32 = 4.0;
+const HOMING_RANGE: f32 = 120.0;
+// Times a chain reaction can set itself off again
+const MAX_CHAIN_LINKS: u32 = 3;
+// How far (in cells) teleports and summons look for room around where they land
+const SAFE_SPOT_SEARCH_RADIUS: i32 = 12;
+// Shield wall size and distance in front of where it's cast (world units), and
+// seconds it stands
+const SHIELD_SIZE: Vec2 = Vec2::new(2.0, 12.0);
+const SHIELD_DISTANCE: f32 = 6.0;
+const SHIELD_DURATION: f32 = 4.0;
+const INVISIBILITY_DURATION: f32 = 5.0;
+// How see-through an invisible caster is drawn
+const INVISIBLE_ALPHA: f32 = 0.25;
+const SUMMON_SIZE: Vec2 = Vec2::new(1.0, 1.0);
+const SUMMON_HEALTH: f32 = 30.0;
+const SUMMON_DURATION: f32 = 10.0;
+
+// Something a spell brought into the world that goes away after a while
+#[derive(Component)]
+pub struct Conjured {
+    pub remaining: f32,
+}
+
+// A creature called up by a Summon spell
+#[derive(Component)]
+pub struct Summoned {
+    pub owner: Entity,
+}
+
+// On a caster hidden by an Invisibility spell. Homing projectiles can't see them.
+#[derive(Component)]
+pub struct Invisible {
+    pub remaining: f32,
+    // Sprite alpha to go back to, once it has been faded
+    alpha: Option<f32>,
+}
+
+// Perks that act as soon as their projectile is cast: a shield wall across its
+// path, or hiding the caster
+pub fn special_perks_on_cast(
+    mut commands: Commands,
+    spells: Query<&SpellInstance, Added<SpellInstance>>,
+    mut invisible: Query<&mut Invisible>,
+) {
+    // Only the cast itself; projectiles it forks into carry the same perks
+    for instance in spells.iter().filter(|instance| instance.parent.is_none()) {
+        for perk in &instance.spell.perks {
+            match perk {
+                SpellPerk::Shield => {
+                    let direction = instance.velocity.try_normalize().unwrap_or(Vec2::X);
+                    let position = instance.position + direction * SHIELD_DISTANCE;
+                    commands.spawn((
+                        SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::srgba(0.5, 0.8, 1.0, 0.6),
+                                custom_size: Some(SHIELD_SIZE),
+                                ..default()
+                            },
+                            transform: Transform::from_translation(position.extend(0.5))
+                                .with_rotation(Quat::from_rotation_z(direction.to_angle())),
+                            ..default()
+                        },
+                        RigidBody::Fixed,
+                        Collider::cuboid(SHIELD_SIZE.x / 2.0, SHIELD_SIZE.y / 2.0),
+                        Conjured { remaining: SHIELD_DURATION },
+                    ));
+                }
+                SpellPerk::Invisibility => match invisible.get_mut(instance.spell.caster) {
+                    Ok(mut invisible) => invisible.remaining = INVISIBILITY_DURATION,
+                    Err(_) => {
+                        if let Some(mut caster) = commands.get_entity(instance.spell.caster) {
+                            caster.insert(Invisible { remaining: INVISIBILITY_DURATION, alpha: None });
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+// Perks that act where their projectile ends up: chain reactions go off again
+// from each impact, teleports move the caster and summons call up a creature
+// where it dies
+pub fn special_perks_on_impact(
+    mut commands: Commands,
+    mut budget: ResMut<SpellBudget>,
+    world: Res<AtomWorldResource>,
+    spells: Query<(Entity, &SpellInstance)>,
+    mut casters: Query<(&mut Transform, Option<&mut Velocity>, Option<&Sprite>), Without<SpellInstance>>,
+) {
+    let world = &world.0;
+    let space = WorldSpace::of(world);
+
+    for (entity, instance) in spells.iter() {
+        let perks = &instance.spell.perks;
+        if instance.impact.is_some() && perks.contains(&SpellPerk::ChainReaction) && instance.chain < MAX_CHAIN_LINKS {
+            let again = SpellSubroutine {
+                perks: perks.clone(),
+                condition: SubroutineCondition::OnHit,
+            };
+            for mut child in instance.fork(&again) {
+                child.chain = instance.chain + 1;
+                child.parent = Some(entity);
+                spawn_spell(&mut commands, &mut budget, child);
+            }
+        }
+
+        if !instance.is_dead() {
+            continue;
+        }
+        let landing = space.world_to_grid(instance.impact.map_or(instance.position, |impact| impact.position));
+
+        if perks.contains(&SpellPerk::Teleport) {
+            if let Ok((mut transform, velocity, sprite)) = casters.get_mut(instance.spell.caster) {
+                let size = sprite.and_then(|sprite| sprite.custom_size).unwrap_or(Vec2::ONE);
+                if let Some(spot) = find_safe_spot(world, landing, size) {
+                    let position = space.grid_to_world(spot);
+                    transform.translation = position.extend(transform.translation.z);
+                    if let Some(mut velocity) = velocity {
+                        *velocity = Velocity::zero();
+                    }
+                }
+            }
+        }
+
+        if perks.contains(&SpellPerk::Summon) {
+            if let Some(spot) = find_safe_spot(world, landing, SUMMON_SIZE) {
+                commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::srgb(0.3, 0.9, 0.4),
+                            custom_size: Some(SUMMON_SIZE),
+                            ..default()
+                        },
+                        transform: Transform::from_translation(space.grid_to_world(spot).extend(1.0)),
+                        ..default()
+                    },
+                    RigidBody::Dynamic,
+                    Collider::cuboid(SUMMON_SIZE.x / 2.0, SUMMON_SIZE.y / 2.0),
+                    Velocity::zero(),
+                    LockedAxes::ROTATION_LOCKED,
+                    Health::new(SUMMON_HEALTH),
+                    Summoned { owner: instance.spell.caster },
+                    Conjured { remaining: SUMMON_DURATION },
+                ));
+            }
+        }
+    }
+}
+
+// Nearest cell to `cell` where a body `size` cells across fits in open air
+fn find_safe_spot(world: &AtomWorld, cell: IVec2, size: Vec2) -> Option<IVec2> {
+    let half = (size / 2.0).ceil().as_ivec2();
+    let fits = |center: IVec2| {
+        (-half.y..=half.y).all(|dy| {
+            (-half.x..=half.x).all(|dx| {
+                let cell = center + IVec2::new(dx, dy);
+                world.in_bounds(cell.x, cell.y) && world.is_empty(cell.x, cell.y)
+            })
+        })
+    };
+
+    for radius in 0..=SAFE_SPOT_SEARCH_RADIUS {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                if x.abs().max(y.abs()) != radius {
+                    continue;
+                }
+                let candidate = cell + IVec2::new(x, y);
+                if fits(candidate) {
+                    return Some(candidate);
+                }
+            }
+        }
+    }
+    None
+}
+
+// Lock homing projectiles on to the nearest thing with health they can see,
+// other than their caster and what the caster summoned, and keep track of it
+pub fn spell_homing(
+    mut spells: Query<&mut SpellInstance>,
+    targets: Query<(Entity, &GlobalTransform, Option<&Summoned>), (With<Health>, Without<Invisible>)>,
+) {
+    for mut instance in spells.iter_mut() {
+        if !instance.spell.perks.contains(&SpellPerk::ProjectileHoming) {
+            continue;
+        }
+        let caster = instance.spell.caster;
+        let locked = instance
+            .target
+            .and_then(|(target, _)| targets.get(target).ok())
+            .map(|(target, transform, _)| (target, transform.translation().truncate()));
+
+        instance.target = locked.or_else(|| {
+            targets
+                .iter()
+                .filter(|(target, _, summoned)| *target != caster && summoned.map_or(true, |summoned| summoned.owner != caster))
+                .map(|(target, transform, _)| (target, transform.translation().truncate()))
+                .filter(|(_, position)| position.distance(instance.position) <= HOMING_RANGE)
+                .min_by(|(_, a), (_, b)| {
+                    a.distance_squared(instance.position).total_cmp(&b.distance_squared(instance.position))
+                })
+        });
+    }
+}
+
+// Take away shields and summons whose time is up
+pub fn expire_conjured(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut conjured: Query<(Entity, &mut Conjured)>,
+) {
+    for (entity, mut conjured) in conjured.iter_mut() {
+        conjured.remaining -= time.delta_seconds();
+        if conjured.remaining <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Fade invisible casters out, and back in once it wears off
+pub fn update_invisibility(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut casters: Query<(Entity, &mut Invisible, Option<&mut Sprite>)>,
+) {
+    for (entity, mut invisible, sprite) in casters.iter_mut() {
+        invisible.remaining -= time.delta_seconds();
+        let Some(mut sprite) = sprite else {
+            if invisible.remaining <= 0.0 {
+                commands.entity(entity).remove::<Invisible>();
+            }
+            continue;
+        };
+
+        let alpha = *invisible.alpha.get_or_insert(sprite.color.alpha());
+        if invisible.remaining > 0.0 {
+            sprite.color.set_alpha(INVISIBLE_ALPHA);
+        } else {
+            sprite.color.set_alpha(alpha);
+            commands.entity(entity).remove::<Invisible>();
+        }
+    }
+}
+
+// Fire and ice spells heat or chill the atoms they fly past
+pub fn spell_heat_atoms(
+    mut world: ResMut<AtomWorldResource>,
+    spells: Query<&SpellInstance>,
+) {
+    let dt = SIMULATION_DT;
+    let space = WorldSpace::of(&world.0);
+
+    for instance in spells.iter() {
+        let heat: f32 = instance
+            .spell
+            .perks
+            .iter()
+            .map(|perk| match perk {
+                SpellPerk::DamageFire => 600.0,
+                SpellPerk::DamageIce => -300.0,
+                _ => 0.0,
+            })
+            .sum();
+        if heat == 0.0 {
+            continue;
+        }
+
+        let cell = space.world_to_grid(instance.position);
+        world.0.apply_heat(cell, 3, heat * dt);
+    }
+}
+
+// Times a bouncing projectile rebounds, and things a piercing one passes through,
+// before it detonates on the next impact
+const MAX_BOUNCES: u32 = 3;
+const MAX_PIERCES: u32 = 3;
+// Share of its speed a projectile keeps when it bounces
+const BOUNCE_RESTITUTION: f32 = 0.8;
+// How far (in cells) fire, ice and poison spread from an impact
+const IMPACT_RADIUS: i32 = 3;
+// Lifetime of the flames a fire impact leaves in the air
+const IMPACT_FLAME_LIFETIME: f32 = 1.0;
+// Temperature atoms are left at by an ice impact
+const FROST_TEMPERATURE: f32 = -30.0;
+
+// Projectiles fly through air and gases and hit everything else
+fn blocks_spells(materials: &MaterialRegistry, atom: &Atom) -> bool {
+    atom.atom_type != AtomType::Empty && !materials.get(atom.atom_type).is_gas()
+}
+
+// Sweep every projectile's last move through the atom world and past Rapier's
+// colliders, and take whichever it meets first. Terrain and rigid body colliders
+// are left out, since their atoms are already in the grid, which leaves what only
+// Rapier knows about, like shields and creatures. On impact it bounces if it can,
+// otherwise it applies its effects to what it hit and either carries on through
+// (piercing) or is used up.
 pub fn spell_collision_detection(
-    mut spell_query: Query<&mut SpellInstance>,
-    // Would need collision detection with enemies/objects
+    mut commands: Commands,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    rapier_context: Res<RapierContext>,
+    mut spells: Query<(&mut SpellInstance, &mut Transform)>,
+    mut creatures: Query<(&mut Health, Has<Player>)>,
+    colliders: Query<(Option<&Summoned>, Has<TerrainCollider>, Has<RigidBodyObject>)>,
 ) {
-    // Handle spell collisions and apply effects
-    // This would integrate with the physics system
+    let world = &mut world.0;
+    let materials = &materials.0;
+    let space = WorldSpace::of(world);
+    let blocks = |atom: &Atom| blocks_spells(materials, atom);
+
+    for (mut instance, mut transform) in spells.iter_mut() {
+        if instance.is_dead() {
+            continue;
+        }
+        let from = space.world_to_grid_point(instance.previous_position);
+        let to = space.world_to_grid_point(instance.position);
+        let atom_hit = world.raycast(from, to, blocks);
+
+        // The caster and what it summoned are never in the way of its own spells
+        let caster = instance.spell.caster;
+        let hittable = |entity: Entity| {
+            colliders.get(entity).is_ok_and(|(summoned, terrain, body)| {
+                !terrain && !body && summoned.map(|summoned| summoned.owner) != Some(caster)
+            })
+        };
+        let filter = QueryFilter::default()
+            .exclude_sensors()
+            .exclude_rigid_body(caster)
+            .predicate(&hittable);
+        let travel = instance.position - instance.previous_position;
+        let body_hit = rapier_context
+            .cast_ray(instance.previous_position, travel, 1.0, true, filter)
+            .filter(|&(_, toi)| !atom_hit.is_some_and(|hit| from.distance(hit.cell.as_vec2()) <= toi * travel.length()));
+
+        // Cell the effects go off in, the free spot in front of what was hit and
+        // which way that faces (world space)
+        let (cell, position, normal) = match (body_hit, atom_hit) {
+            (Some((_, toi)), _) => {
+                // Bodies aren't in the grid to read a surface off, so it comes back the way it came
+                let normal = -travel.normalize_or_zero();
+                let surface = instance.previous_position + travel * toi;
+                (space.world_to_grid(surface), surface + normal, normal)
+            }
+            (None, Some(hit)) => {
+                (hit.cell, space.grid_to_world(hit.free), WorldSpace::grid_to_world_vector(hit.normal.as_vec2()))
+            }
+            (None, None) => continue,
+        };
+
+        if instance.bounces > 0 {
+            instance.bounces -= 1;
+            let normal = match (body_hit, atom_hit) {
+                (None, Some(hit)) => bounce_normal(world, &hit, instance.velocity, blocks),
+                _ => normal,
+            };
+            instance.velocity = bounce(instance.velocity, normal);
+            instance.position = position;
+            transform.translation = instance.position.extend(transform.translation.z);
+            continue;
+        }
+
+        if let Some((body, _)) = body_hit {
+            if let Ok((mut health, is_player)) = creatures.get_mut(body) {
+                // A creature another projectile finished off this frame is already being despawned
+                if !health.is_dead() {
+                    health.damage(direct_damage(&instance.effects));
+                    if health.is_dead() && !is_player {
+                        commands.entity(body).despawn();
+                    }
+                }
+            }
+        }
+        apply_impact(world, materials, &instance.effects, cell);
+        instance.impact = Some(SpellImpact { position, normal });
+
+        if instance.pierces > 0 {
+            instance.pierces -= 1;
+        } else {
+            instance.spent = true;
+        }
+    }
+}
+
+// Normal (world space) a projectile flying at `velocity` bounces off where it ran
+// into the atoms: the slope of the surface, unless that's too ragged to face back
+// the way it came, in which case the side of the cell it hit
+fn bounce_normal(world: &AtomWorld, hit: &RayHit, velocity: Vec2, blocks: impl Fn(&Atom) -> bool) -> Vec2 {
+    let grid_velocity = WorldSpace::world_to_grid_vector(velocity);
+    let normal = world
+        .surface_normal(hit.cell, blocks)
+        .filter(|normal| normal.dot(grid_velocity) < 0.0)
+        .unwrap_or(hit.normal.as_vec2());
+    WorldSpace::grid_to_world_vector(normal)
+}
+
+// Reflect `velocity` off a surface facing `normal`, losing some speed
+fn bounce(velocity: Vec2, normal: Vec2) -> Vec2 {
+    (velocity - 2.0 * velocity.dot(normal) * normal) * BOUNCE_RESTITUTION
+}
+
+// Health a projectile takes from a creature it hits head on
+fn direct_damage(effects: &[SpellEffect]) -> f32 {
+    effects
+        .iter()
+        .map(|effect| match *effect {
+            SpellEffect::Damage { amount } => amount,
+            _ => 0.0,
+        })
+        .sum()
+}
+
+// What a projectile's effects do to the atoms where it hits. Direct damage is
+// for creatures and leaves the world alone.
+fn apply_impact(world: &mut AtomWorld, materials: &MaterialRegistry, effects: &[SpellEffect], cell: IVec2) {
+    for effect in effects {
+        match *effect {
+            SpellEffect::FireDamage { .. } => ignite_atoms(world, materials, cell, IMPACT_RADIUS),
+            SpellEffect::IceDamage { .. } => freeze_atoms(world, materials, cell, IMPACT_RADIUS),
+            SpellEffect::PoisonDamage { .. } => spill_atoms(world, materials, cell, IMPACT_RADIUS, AtomType::Poison),
+            SpellEffect::Explosion { radius, damage } => {
+                world.queue_explosion(Explosion::new(cell, radius).with_damage(damage))
+            }
+            _ => {}
+        }
+    }
+}
+
+fn cells_within(center: IVec2, radius: i32) -> impl Iterator<Item = IVec2> {
+    (-radius..=radius)
+        .flat_map(move |dy| (-radius..=radius).map(move |dx| IVec2::new(dx, dy)))
+        .filter(move |offset| offset.length_squared() <= radius * radius)
+        .map(move |offset| center + offset)
+}
+
+// Heat anything flammable past its ignition point and fill the air with flames
+fn ignite_atoms(world: &mut AtomWorld, materials: &MaterialRegistry, center: IVec2, radius: i32) {
+    let flame_temperature = materials.get(AtomType::Fire).heat_source.unwrap_or(800.0);
+    for cell in cells_within(center, radius) {
+        let Some(atom) = world.get_atom(cell.x, cell.y) else {
+            continue;
+        };
+        if atom.atom_type == AtomType::Empty {
+            world.set_atom(cell.x, cell.y, Atom {
+                atom_type: AtomType::Fire,
+                velocity: Vec2::ZERO,
+                mass: materials.get(AtomType::Fire).mass,
+                lifetime: Some(IMPACT_FLAME_LIFETIME),
+                temperature: flame_temperature,
+                ..default()
+            });
+            continue;
+        }
+        let ignition = materials
+            .get(atom.atom_type)
+            .combustion
+            .as_ref()
+            .map(|combustion| combustion.ignition);
+        if let (Some(ignition), Some(atom)) = (ignition, world.get_atom_mut(cell.x, cell.y)) {
+            atom.temperature = atom.temperature.max(ignition);
+        }
+    }
+}
+
+// Freeze whatever has a frozen form (water into ice), and chill and put out the rest
+fn freeze_atoms(world: &mut AtomWorld, materials: &MaterialRegistry, center: IVec2, radius: i32) {
+    for cell in cells_within(center, radius) {
+        let Some(atom) = world.get_atom(cell.x, cell.y) else {
+            continue;
+        };
+        if atom.atom_type == AtomType::Empty {
+            continue;
+        }
+        let frozen = materials
+            .get(atom.atom_type)
+            .freezing
+            .as_ref()
+            .map(|freezing| (freezing.target, freezing.lifetime));
+        match frozen {
+            Some((target, lifetime)) => world.set_atom(cell.x, cell.y, Atom {
+                atom_type: target,
+                velocity: Vec2::ZERO,
+                mass: materials.get(target).mass,
+                lifetime,
+                temperature: FROST_TEMPERATURE,
+                ..default()
+            }),
+            None => {
+                if let Some(atom) = world.get_atom_mut(cell.x, cell.y) {
+                    atom.temperature = atom.temperature.min(FROST_TEMPERATURE);
+                    atom.burning = false;
+                }
+            }
+        }
+    }
+}
+
+// Fill the empty cells around `center` with `atom_type`
+fn spill_atoms(world: &mut AtomWorld, materials: &MaterialRegistry, center: IVec2, radius: i32, atom_type: AtomType) {
+    for cell in cells_within(center, radius) {
+        if world.in_bounds(cell.x, cell.y) && world.is_empty(cell.x, cell.y) {
+            world.set_atom(cell.x, cell.y, Atom {
+                atom_type,
+                velocity: Vec2::ZERO,
+                mass: materials.get(atom_type).mass,
+                ..default()
+            });
+        }
+    }
 }
 
 // Spell crafting system
@@ -455,5 +1320,255 @@ pub fn get_example_spells() -> Vec<Spell> {
 
         // Teleport
         Spell::new(vec![SpellPerk::Teleport]),
+
+        // Fuse: a bolt that bursts into two fire bolts after a second
+        Spell::new(vec![
+            SpellPerk::Projectile,
+            SpellPerk::TriggerTimer,
+            SpellPerk::DamageFire,
+            SpellPerk::Projectile,
+            SpellPerk::DamageFire,
+            SpellPerk::Projectile,
+        ]),
+
+        // Cluster bomb: explodes where it lands and leaves an ice bolt behind, and
+        // drops a bolt once it has flown 150 cells
+        Spell::new(vec![SpellPerk::Projectile, SpellPerk::Explosion, SpellPerk::TriggerDeath, SpellPerk::Projectile, SpellPerk::DamageIce])
+            .with_subroutine(SubroutineCondition::Distance(150.0), vec![SpellPerk::Projectile, SpellPerk::Damage]),
+
+        // Last stand: splits in two on impact, and sheds a poison bolt while the
+        // caster is under a quarter of their health
+        Spell::new(vec![SpellPerk::Projectile, SpellPerk::Damage])
+            .with_subroutine(SubroutineCondition::HealthPercent(25.0), vec![SpellPerk::Projectile, SpellPerk::DamagePoison])
+            .with_subroutine(SubroutineCondition::OnHit, vec![SpellPerk::Projectile, SpellPerk::Projectile, SpellPerk::Damage]),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn instance() -> SpellInstance {
+        SpellInstance::new(Spell::new(vec![SpellPerk::Projectile]))
+    }
+
+    #[test]
+    fn on_hit_waits_for_an_impact() {
+        let mut instance = instance();
+        assert!(!SubroutineCondition::OnHit.is_met(&instance, None));
+
+        instance.impact = Some(SpellImpact {
+            position: Vec2::ZERO,
+            normal: Vec2::Y,
+        });
+        assert!(SubroutineCondition::OnHit.is_met(&instance, None));
+    }
+
+    #[test]
+    fn on_death_waits_for_the_projectile_to_die() {
+        let mut instance = instance();
+        assert!(!SubroutineCondition::OnDeath.is_met(&instance, None));
+
+        instance.lifetime = instance.max_lifetime;
+        assert!(SubroutineCondition::OnDeath.is_met(&instance, None));
+    }
+
+    #[test]
+    fn timer_waits_for_its_delay() {
+        let mut instance = instance();
+        instance.lifetime = 0.5;
+        assert!(!SubroutineCondition::Timer(1.0).is_met(&instance, None));
+
+        instance.lifetime = 1.0;
+        assert!(SubroutineCondition::Timer(1.0).is_met(&instance, None));
+    }
+
+    #[test]
+    fn distance_waits_for_the_projectile_to_fly_far_enough() {
+        let mut instance = instance();
+        instance.distance = 9.0;
+        assert!(!SubroutineCondition::Distance(10.0).is_met(&instance, None));
+
+        instance.distance = 10.0;
+        assert!(SubroutineCondition::Distance(10.0).is_met(&instance, None));
+    }
+
+    #[test]
+    fn health_percent_waits_for_the_caster_to_be_hurt() {
+        let instance = instance();
+        let condition = SubroutineCondition::HealthPercent(25.0);
+        assert!(!condition.is_met(&instance, Some(50.0)));
+        assert!(!condition.is_met(&instance, None));
+
+        assert!(condition.is_met(&instance, Some(20.0)));
+    }
+
+    #[test]
+    fn modifiers_go_to_the_next_projectile_only() {
+        let programs = compile_perks(&[
+            SpellPerk::Damage,
+            SpellPerk::Projectile,
+            SpellPerk::Accelerate,
+            SpellPerk::Projectile,
+            SpellPerk::Projectile,
+        ]);
+
+        assert_eq!(programs.len(), 3);
+        assert_eq!(programs[0].perks, vec![SpellPerk::Damage]);
+        assert_eq!(programs[1].perks, vec![SpellPerk::Accelerate]);
+        assert!(programs[2].perks.is_empty());
+    }
+
+    #[test]
+    fn triggers_fork_the_rest_of_the_spell_into_a_subroutine() {
+        let programs = compile_perks(&[
+            SpellPerk::Damage,
+            SpellPerk::Projectile,
+            SpellPerk::TriggerTimer,
+            SpellPerk::Projectile,
+            SpellPerk::DamageFire,
+        ]);
+
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].perks, vec![SpellPerk::Damage]);
+        let [subroutine] = programs[0].subroutines.as_slice() else {
+            panic!("expected one subroutine, got {:?}", programs[0].subroutines);
+        };
+        assert!(matches!(subroutine.condition, SubroutineCondition::Timer(delay) if delay == TRIGGER_TIMER_DELAY));
+        assert_eq!(subroutine.perks, vec![SpellPerk::Projectile, SpellPerk::DamageFire]);
+    }
+
+    // Spawn a projectile with `perks` and a timer subroutine, let it fly for
+    // `lifetime` and run spawn_spell_children over it
+    fn spawn_children(perks: Vec<SpellPerk>, lifetime: f32) -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<SpellBudget>();
+        let mut instance = SpellInstance::new(Spell::new(perks));
+        instance.velocity = Vec2::X * SPELL_SPEED;
+        instance.lifetime = lifetime;
+        instance.subroutines.push(SpellSubroutine {
+            perks: vec![SpellPerk::Projectile, SpellPerk::DamageFire],
+            condition: SubroutineCondition::Timer(TRIGGER_TIMER_DELAY),
+        });
+        instance.create_effects();
+        let original = world.spawn(instance).id();
+        world.run_system_once(spawn_spell_children);
+        (world, original)
+    }
+
+    fn children_of(world: &mut World, original: Entity) -> Vec<SpellInstance> {
+        world
+            .query::<&SpellInstance>()
+            .iter(world)
+            .filter(|instance| instance.parent == Some(original))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn multiply_fans_out_into_linked_copies() {
+        let (mut world, original) = spawn_children(vec![SpellPerk::Multiply, SpellPerk::Damage], 0.0);
+
+        let children = children_of(&mut world, original);
+        assert_eq!(children.len(), MULTIPLY_COUNT - 1);
+        for child in children.iter().chain([world.get::<SpellInstance>(original).unwrap()]) {
+            assert_eq!(child.spell.perks, vec![SpellPerk::Damage]);
+            assert!(matches!(child.effects.as_slice(), [SpellEffect::Damage { amount }] if *amount == 10.0));
+            let [subroutine] = child.subroutines.as_slice() else {
+                panic!("expected the timer subroutine, got {:?}", child.subroutines);
+            };
+            assert_eq!(subroutine.perks, vec![SpellPerk::Projectile, SpellPerk::DamageFire]);
+            assert!((child.velocity.length() - SPELL_SPEED).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn split_waits_then_breaks_into_weaker_pieces() {
+        let (mut world, original) = spawn_children(vec![SpellPerk::Split, SpellPerk::Damage], 0.0);
+        assert!(children_of(&mut world, original).is_empty());
+
+        let (mut world, original) = spawn_children(vec![SpellPerk::Split, SpellPerk::Damage], SPLIT_DELAY);
+        let children = children_of(&mut world, original);
+        assert_eq!(children.len(), SPLIT_COUNT - 1);
+        for child in &children {
+            assert_eq!(child.spell.perks, vec![SpellPerk::Damage]);
+            assert_eq!(child.subroutines.len(), 1);
+            let expected = 10.0 * SPLIT_POWER;
+            assert!(matches!(child.effects.as_slice(), [SpellEffect::Damage { amount }] if *amount == expected));
+        }
+    }
+
+    fn stone_world(solid: impl Fn(IVec2) -> bool) -> (AtomWorld, MaterialRegistry) {
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(64, 64);
+        for y in 0..64 {
+            for x in 0..64 {
+                if solid(IVec2::new(x, y)) {
+                    world.set_atom(x, y, Atom { atom_type: AtomType::Stone, mass: materials.get(AtomType::Stone).mass, ..default() });
+                }
+            }
+        }
+        (world, materials)
+    }
+
+    #[test]
+    fn safe_spots_are_the_nearest_room_big_enough() {
+        let (open, _) = stone_world(|_| false);
+        assert_eq!(find_safe_spot(&open, IVec2::new(30, 30), Vec2::ONE), Some(IVec2::new(30, 30)));
+        // Never hanging over the edge of the world
+        assert_eq!(find_safe_spot(&open, IVec2::ZERO, Vec2::ONE), Some(IVec2::ONE));
+
+        // Buried in rock next to a pocket 5 cells wide and 3 tall
+        let pocket = |cell: IVec2| {
+            let offset = (cell - IVec2::new(40, 30)).abs();
+            offset.x <= 2 && offset.y <= 1
+        };
+        let (buried, _) = stone_world(|cell| !pocket(cell));
+        assert_eq!(find_safe_spot(&buried, IVec2::new(34, 30), Vec2::ONE), Some(IVec2::new(39, 30)));
+        assert_eq!(find_safe_spot(&buried, IVec2::new(34, 30), Vec2::splat(6.0)), None);
+    }
+
+    // Where a projectile flying from `from` to `to` (grid) hits, and the normal it
+    // bounces off
+    fn bounce_off(world: &AtomWorld, materials: &MaterialRegistry, from: Vec2, to: Vec2) -> Vec2 {
+        let blocks = |atom: &Atom| blocks_spells(materials, atom);
+        let hit = world.raycast(from, to, blocks).expect("should hit the stone");
+        let velocity = WorldSpace::grid_to_world_vector(to - from);
+        bounce_normal(world, &hit, velocity, blocks)
+    }
+
+    #[test]
+    fn projectiles_bounce_off_the_slope_of_the_surface() {
+        // Flat floor: straight back up, keeping the sideways speed
+        let (floor, materials) = stone_world(|cell| cell.y >= 40);
+        let normal = bounce_off(&floor, &materials, Vec2::new(20.0, 30.0), Vec2::new(25.0, 50.0));
+        assert!(normal.distance(Vec2::Y) < 1e-3, "{normal:?}");
+        let velocity = bounce(Vec2::new(3.0, -4.0), normal);
+        assert!(velocity.distance(Vec2::new(3.0, 4.0) * BOUNCE_RESTITUTION) < 1e-3);
+
+        // Ground falling away to the right faces up and to the right, even for a
+        // projectile dropping straight down onto it
+        let (slope, materials) = stone_world(|cell| cell.y >= cell.x + 10);
+        let normal = bounce_off(&slope, &materials, Vec2::new(30.0, 10.0), Vec2::new(30.0, 60.0));
+        assert!(normal.x > 0.5 && normal.y > 0.5, "{normal:?}");
+        assert!(bounce(Vec2::NEG_Y, normal).x > 0.5);
+
+        // A wall one cell thick has no slope to go by, so it's the side that was hit
+        let (wall, materials) = stone_world(|cell| cell.x == 40);
+        let normal = bounce_off(&wall, &materials, Vec2::new(20.0, 30.0), Vec2::new(50.0, 30.0));
+        assert_eq!(normal, Vec2::NEG_X);
+    }
+
+    #[test]
+    fn cast_perks_only_fire_for_the_cast_itself() {
+        let mut world = World::new();
+        let cast = world.spawn(SpellInstance::new(Spell::new(vec![SpellPerk::Shield]))).id();
+        let mut child = SpellInstance::new(Spell::new(vec![SpellPerk::Shield]));
+        child.parent = Some(cast);
+        world.spawn(child);
+
+        world.run_system_once(special_perks_on_cast);
+        assert_eq!(world.query::<&Conjured>().iter(&world).count(), 1);
+    }
+}
@@ -1,5 +1,11 @@
 mod atoms;
+mod materials;
+mod random;
+mod world_space;
 mod physics;
+mod particles;
+mod explosions;
+mod lighting;
 mod rendering;
 mod game;
 mod magic;
@@ -21,11 +27,13 @@ fn main() {
             }),
             ..default()
         }))
-        // 32 pixels = 1 physics meter for Rapier (2D)
+        // 32 pixels = 1 physics meter for Rapier (2D). It steps in FixedUpdate, along
+        // with the systems that push bodies around and write them back to the grid.
         .add_plugins(
             bevy_rapier2d::prelude::RapierPhysicsPlugin::<bevy_rapier2d::prelude::NoUserData>::pixels_per_meter(
-                32.0,
-            ),
+                world_space::PIXELS_PER_METER,
+            )
+            .in_fixed_schedule(),
         )
         .add_plugins(bevy_rapier2d::prelude::RapierDebugRenderPlugin::default())
         .add_plugins(game::GamePlugin)
@@ -0,0 +1,586 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::atoms::AtomType;
+
+// Data-driven material and reaction definitions. The built-in tables are
+// embedded from assets/materials.json, and the same file on disk is loaded over
+// them at startup so designers can tweak or add materials without recompiling.
+
+pub const MATERIALS_PATH: &str = "assets/materials.json";
+
+const BUILTIN_MATERIALS: &str = include_str!("../assets/materials.json");
+
+// Phase of matter, used for broad rules like what counts as fluid or gas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Empty,
+    Solid,
+    Powder,
+    Liquid,
+    Gas,
+}
+
+// Update rule run for an atom every simulation step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Behaviour {
+    Static,
+    Powder,
+    Liquid,
+    Acid,
+    Poison,
+    Gas,
+    Fire,
+}
+
+// Conversion into another material once a temperature threshold is crossed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transition {
+    pub temperature: f32,
+    pub into: String,
+    // Lifetime given to the converted atom, e.g. for fire
+    #[serde(default)]
+    pub lifetime: Option<f32>,
+    // `into` resolved against the registry
+    #[serde(skip)]
+    pub target: AtomType,
+}
+
+// How a corrosive material like acid eats through its neighbours
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Corrosion {
+    // Chance per tick of dissolving a neighbour with no resistance
+    pub strength: f32,
+    // Chance the corrosive atom is used up by each thing it dissolves
+    pub consumption: f32,
+    // Gas left behind where something was dissolved
+    pub byproduct: String,
+    #[serde(default)]
+    pub byproduct_lifetime: Option<f32>,
+    // Health per second taken from bodies in contact
+    #[serde(default)]
+    pub damage: f32,
+    #[serde(skip)]
+    pub byproduct_type: AtomType,
+}
+
+// How a flammable material catches fire and what it leaves behind
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Combustion {
+    // Temperature the atom has to reach before it can catch fire
+    pub ignition: f32,
+    // Chance per tick of catching once hot enough
+    pub flammability: f32,
+    // Seconds the atom burns for
+    pub fuel: f32,
+    // Temperature a burning atom is held at, which is what spreads the fire
+    #[serde(default = "default_burn_temperature")]
+    pub burn_temperature: f32,
+    // Material left once the fuel runs out
+    pub residue: String,
+    #[serde(default)]
+    pub residue_lifetime: Option<f32>,
+    #[serde(skip)]
+    pub residue_type: AtomType,
+}
+
+fn default_burn_temperature() -> f32 {
+    600.0
+}
+
+fn default_color_noise() -> f32 {
+    0.08
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Material {
+    pub name: String,
+    pub phase: Phase,
+    pub behaviour: Behaviour,
+    pub color: [f32; 4],
+    // Colours atoms of this material pick from when they're spawned. Without a
+    // palette every atom gets `color` with its brightness nudged by up to `color_noise`.
+    #[serde(default)]
+    pub palette: Vec<[f32; 4]>,
+    #[serde(default = "default_color_noise")]
+    pub color_noise: f32,
+    // Light given off, as an RGB colour whose brightness is its strength
+    #[serde(default)]
+    pub emission: Option<[f32; 3]>,
+    pub mass: f32,
+    pub density: f32,
+    pub friction: f32,
+    pub heat_capacity: f32,
+    // 0.0 flows freely, 1.0 never spreads sideways
+    #[serde(default)]
+    pub viscosity: f32,
+    // Atoms of this material never drop below this temperature (fire, lava)
+    #[serde(default)]
+    pub heat_source: Option<f32>,
+    // Triggered when the atom heats above the threshold
+    #[serde(default)]
+    pub melting: Option<Transition>,
+    #[serde(default)]
+    pub boiling: Option<Transition>,
+    // Triggered when the atom cools below the threshold
+    #[serde(default)]
+    pub freezing: Option<Transition>,
+    #[serde(default)]
+    pub condensation: Option<Transition>,
+    // 0.0 dissolves as fast as the acid allows, 1.0 never does; None is immune
+    #[serde(default)]
+    pub corrosion_resistance: Option<f32>,
+    #[serde(default)]
+    pub corrosion: Option<Corrosion>,
+    #[serde(default)]
+    pub combustion: Option<Combustion>,
+    // 0.0 is blown away by any explosion that reaches it, 1.0 shrugs off all of them
+    #[serde(default)]
+    pub hardness: f32,
+}
+
+impl Material {
+    pub fn color(&self) -> Color {
+        let [r, g, b, a] = self.color;
+        Color::srgba(r, g, b, a)
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        matches!(self.phase, Phase::Liquid | Phase::Gas)
+    }
+
+    pub fn is_gas(&self) -> bool {
+        self.phase == Phase::Gas
+    }
+
+    pub fn can_burn(&self) -> bool {
+        self.combustion.is_some()
+    }
+
+    // Colour of one of the material's variants, see `Atom::color_variant`
+    pub fn variant_color(&self, variant: u8) -> Color {
+        if !self.palette.is_empty() {
+            let [r, g, b, a] = self.palette[variant as usize % self.palette.len()];
+            return Color::srgba(r, g, b, a);
+        }
+        let shade = 1.0 + self.color_noise * (variant as f32 / 255.0 * 2.0 - 1.0);
+        let [r, g, b, a] = self.color;
+        Color::srgba(r * shade, g * shade, b * shade, a)
+    }
+
+    // Material (and lifetime) an atom at `temperature` should turn into, if any
+    pub fn transition_at(&self, temperature: f32) -> Option<(AtomType, Option<f32>)> {
+        let heating = [&self.boiling, &self.melting];
+        let cooling = [&self.freezing, &self.condensation];
+
+        heating
+            .into_iter()
+            .flatten()
+            .find(|transition| temperature > transition.temperature)
+            .or_else(|| {
+                cooling
+                    .into_iter()
+                    .flatten()
+                    .find(|transition| temperature < transition.temperature)
+            })
+            .map(|transition| (transition.target, transition.lifetime))
+    }
+
+    fn transitions_mut(&mut self) -> impl Iterator<Item = &mut Transition> {
+        [
+            &mut self.melting,
+            &mut self.boiling,
+            &mut self.freezing,
+            &mut self.condensation,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+// Where a reaction product ends up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    ReplaceA,
+    ReplaceB,
+    // First empty cell around the reactants, dropped if there is none
+    SpawnNearby,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductDef {
+    pub material: String,
+    pub placement: Placement,
+    #[serde(default)]
+    pub lifetime: Option<f32>,
+}
+
+// A reaction as written in the data file, with materials referenced by name.
+// Reactants that no product replaces are left untouched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionDef {
+    pub a: String,
+    pub b: String,
+    // Chance per tick that a touching pair reacts
+    pub probability: f32,
+    // Only react once either reactant is at least this hot
+    #[serde(default)]
+    pub min_temperature: Option<f32>,
+    // Heat added to the reaction site (negative absorbs heat)
+    #[serde(default)]
+    pub energy: f32,
+    // Radius (in cells) of the explosion the reaction sets off, if it's explosive
+    #[serde(default)]
+    pub explosion: Option<f32>,
+    pub products: Vec<ProductDef>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Product {
+    pub atom_type: AtomType,
+    pub placement: Placement,
+    pub lifetime: Option<f32>,
+}
+
+// A reaction resolved against the material table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reaction {
+    pub a: AtomType,
+    pub b: AtomType,
+    pub probability: f32,
+    pub min_temperature: Option<f32>,
+    pub energy: f32,
+    pub explosion: Option<f32>,
+    pub products: Vec<Product>,
+}
+
+impl Reaction {
+    pub fn can_occur(&self, temperature_a: f32, temperature_b: f32) -> bool {
+        self.min_temperature
+            .map_or(true, |threshold| temperature_a.max(temperature_b) >= threshold)
+    }
+
+    // The same reaction seen from B's side
+    fn mirrored(&self) -> Self {
+        let products = self
+            .products
+            .iter()
+            .map(|product| Product {
+                placement: match product.placement {
+                    Placement::ReplaceA => Placement::ReplaceB,
+                    Placement::ReplaceB => Placement::ReplaceA,
+                    Placement::SpawnNearby => Placement::SpawnNearby,
+                },
+                ..*product
+            })
+            .collect();
+
+        Self {
+            a: self.b,
+            b: self.a,
+            products,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MaterialFile {
+    materials: Vec<Material>,
+    #[serde(default)]
+    reactions: Vec<ReactionDef>,
+}
+
+// All known materials, indexed by AtomType id, and the reactions between them
+#[derive(Debug, Clone)]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+    by_name: HashMap<String, AtomType>,
+    // Keyed on (a, b); every rule is stored from both sides
+    reactions: HashMap<(AtomType, AtomType), Reaction>,
+}
+
+impl MaterialRegistry {
+    pub fn builtin() -> Self {
+        let file: MaterialFile = serde_json::from_str(BUILTIN_MATERIALS)
+            .expect("embedded materials.json is valid");
+        let mut registry = Self {
+            materials: Vec::new(),
+            by_name: HashMap::default(),
+            reactions: HashMap::default(),
+        };
+        registry.extend(file.materials);
+        registry
+            .resolve_references()
+            .expect("embedded materials reference known materials");
+        registry
+            .add_reactions(file.reactions)
+            .expect("embedded reactions reference known materials");
+
+        // AtomType's associated constants index straight into the table
+        for (id, name) in AtomType::BUILTIN_NAMES.iter().enumerate() {
+            assert_eq!(
+                registry.by_name.get(*name),
+                Some(&AtomType(id as u16)),
+                "built-in material `{name}` must be entry {id} of materials.json",
+            );
+        }
+        registry
+    }
+
+    // Built-in materials with the given JSON applied on top: entries with a known
+    // name replace that material, new names are appended as new atom types.
+    // Reactions replace any built-in rule for the same pair.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let file: MaterialFile = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let mut registry = Self::builtin();
+        registry.extend(file.materials);
+        registry.resolve_references()?;
+        registry.add_reactions(file.reactions)?;
+        Ok(registry)
+    }
+
+    // Load the materials file, falling back to the built-in table if it's missing or broken
+    pub fn load_or_builtin(path: &str) -> Self {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) => {
+                println!("Could not read {}: {}, using built-in materials", path, err);
+                return Self::builtin();
+            }
+        };
+
+        match Self::from_json(&json) {
+            Ok(registry) => registry,
+            Err(err) => {
+                println!("Invalid material file {}: {}, using built-in materials", path, err);
+                Self::builtin()
+            }
+        }
+    }
+
+    fn extend(&mut self, materials: Vec<Material>) {
+        for material in materials {
+            match self.by_name.get(&material.name) {
+                Some(atom_type) => self.materials[atom_type.0 as usize] = material,
+                None => {
+                    let atom_type = AtomType(self.materials.len() as u16);
+                    self.by_name.insert(material.name.clone(), atom_type);
+                    self.materials.push(material);
+                }
+            }
+        }
+    }
+
+    // Resolve material names used inside material definitions into atom types
+    fn resolve_references(&mut self) -> Result<(), String> {
+        let by_name = &self.by_name;
+        for material in &mut self.materials {
+            let name = material.name.clone();
+            let lookup = |other: &str| {
+                by_name
+                    .get(other)
+                    .copied()
+                    .ok_or_else(|| format!("material `{}` references unknown material `{}`", name, other))
+            };
+
+            for transition in material.transitions_mut() {
+                transition.target = lookup(&transition.into)?;
+            }
+            if let Some(corrosion) = material.corrosion.as_mut() {
+                corrosion.byproduct_type = lookup(&corrosion.byproduct)?;
+            }
+            if let Some(combustion) = material.combustion.as_mut() {
+                combustion.residue_type = lookup(&combustion.residue)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_reactions(&mut self, reactions: Vec<ReactionDef>) -> Result<(), String> {
+        for def in reactions {
+            let lookup = |name: &str| {
+                self.by_name(name)
+                    .ok_or_else(|| format!("reaction {} + {} uses unknown material `{}`", def.a, def.b, name))
+            };
+
+            let products = def
+                .products
+                .iter()
+                .map(|product| {
+                    Ok(Product {
+                        atom_type: lookup(&product.material)?,
+                        placement: product.placement,
+                        lifetime: product.lifetime,
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let reaction = Reaction {
+                a: lookup(&def.a)?,
+                b: lookup(&def.b)?,
+                probability: def.probability.clamp(0.0, 1.0),
+                min_temperature: def.min_temperature,
+                energy: def.energy,
+                explosion: def.explosion,
+                products,
+            };
+
+            let mirrored = reaction.mirrored();
+            self.reactions.insert((mirrored.a, mirrored.b), mirrored);
+            self.reactions.insert((reaction.a, reaction.b), reaction);
+        }
+        Ok(())
+    }
+
+    // Rule for `a` touching `b`, with placements relative to that order
+    pub fn reaction(&self, a: AtomType, b: AtomType) -> Option<&Reaction> {
+        self.reactions.get(&(a, b))
+    }
+
+    pub fn get(&self, atom_type: AtomType) -> &Material {
+        // Unknown ids behave like empty space
+        self.materials.get(atom_type.0 as usize).unwrap_or(&self.materials[0])
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<AtomType> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (AtomType, &Material)> {
+        self.materials
+            .iter()
+            .enumerate()
+            .map(|(id, material)| (AtomType(id as u16), material))
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+// The registry the game runs with. Shared, so the simulation can hand it to
+// chunk updates on other threads without copying it.
+#[derive(Resource, Clone, Default)]
+pub struct MaterialRegistryResource(pub Arc<MaterialRegistry>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atoms::AMBIENT_TEMPERATURE;
+
+    const SLIME: &str = r#"{
+        "materials": [
+            {
+                "name": "water", "phase": "liquid", "behaviour": "liquid", "color": [0.2, 0.4, 0.8, 0.8],
+                "mass": 1.0, "density": 1.5, "friction": 0.1, "heat_capacity": 4.18
+            },
+            {
+                "name": "slime", "phase": "liquid", "behaviour": "liquid", "color": [0.3, 0.9, 0.3, 1.0],
+                "mass": 1.2, "density": 1.2, "friction": 0.5, "heat_capacity": 2.0, "viscosity": 0.9,
+                "boiling": { "temperature": 150.0, "into": "smoke", "lifetime": 2.0 }
+            }
+        ],
+        "reactions": [
+            {
+                "a": "slime", "b": "fire", "probability": 0.25,
+                "products": [{ "material": "ash", "placement": "replace_a" }]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn json_overlays_builtin_materials_and_appends_new_ones() {
+        let builtin = MaterialRegistry::builtin();
+        let registry = MaterialRegistry::from_json(SLIME).unwrap();
+
+        assert_eq!(registry.by_name("water"), Some(AtomType::Water));
+        assert_eq!(registry.get(AtomType::Water).density, 1.5);
+        // Replacing a material replaces all of it, transitions included
+        assert!(registry.get(AtomType::Water).boiling.is_none());
+        assert_eq!(registry.get(AtomType::Stone).density, builtin.get(AtomType::Stone).density);
+
+        let slime = AtomType(builtin.len() as u16);
+        assert_eq!(registry.len(), builtin.len() + 1);
+        assert_eq!(registry.by_name("slime"), Some(slime));
+        let boiling = registry.get(slime).boiling.as_ref().unwrap();
+        assert_eq!(boiling.target, AtomType::Smoke);
+    }
+
+    #[test]
+    fn unknown_references_are_rejected() {
+        let json = SLIME.replace(r#""into": "smoke""#, r#""into": "nothing""#);
+        assert!(MaterialRegistry::from_json(&json).is_err());
+
+        let json = SLIME.replace(r#""material": "ash""#, r#""material": "nothing""#);
+        assert!(MaterialRegistry::from_json(&json).is_err());
+
+        assert!(MaterialRegistry::from_json("{ not json").is_err());
+    }
+
+    #[test]
+    fn missing_file_falls_back_to_builtin() {
+        let registry = MaterialRegistry::load_or_builtin("does/not/exist.json");
+        assert_eq!(registry.len(), MaterialRegistry::builtin().len());
+    }
+
+    #[test]
+    fn reactions_are_mirrored_for_the_other_reactant() {
+        let registry = MaterialRegistry::builtin();
+        let steam = registry.by_name("steam").unwrap();
+
+        let forward = registry.reaction(AtomType::Fire, AtomType::Water).unwrap();
+        let mirrored = registry.reaction(AtomType::Water, AtomType::Fire).unwrap();
+        assert_eq!((mirrored.a, mirrored.b), (AtomType::Water, AtomType::Fire));
+        assert_eq!(mirrored.probability, forward.probability);
+        assert_eq!(mirrored.energy, forward.energy);
+
+        let placement_of = |reaction: &Reaction, atom_type| {
+            reaction.products.iter().find(|product| product.atom_type == atom_type).unwrap().placement
+        };
+        assert_eq!(placement_of(forward, AtomType::Empty), Placement::ReplaceA);
+        assert_eq!(placement_of(forward, steam), Placement::ReplaceB);
+        assert_eq!(placement_of(mirrored, AtomType::Empty), Placement::ReplaceB);
+        assert_eq!(placement_of(mirrored, steam), Placement::ReplaceA);
+
+        assert!(registry.reaction(AtomType::Sand, AtomType::Stone).is_none());
+    }
+
+    #[test]
+    fn json_reactions_are_added_from_both_sides() {
+        let registry = MaterialRegistry::from_json(SLIME).unwrap();
+        let slime = registry.by_name("slime").unwrap();
+        let ash = registry.by_name("ash").unwrap();
+
+        let reaction = registry.reaction(AtomType::Fire, slime).unwrap();
+        assert_eq!(reaction.probability, 0.25);
+        assert_eq!(reaction.products[0].atom_type, ash);
+        assert_eq!(reaction.products[0].placement, Placement::ReplaceB);
+    }
+
+    #[test]
+    fn transitions_happen_past_their_thresholds() {
+        let registry = MaterialRegistry::builtin();
+        let water = registry.get(AtomType::Water);
+        let ice = registry.by_name("ice").unwrap();
+
+        assert_eq!(water.transition_at(AMBIENT_TEMPERATURE), None);
+        assert_eq!(water.transition_at(100.0), None);
+        assert_eq!(water.transition_at(101.0), Some((AtomType::Steam, None)));
+        assert_eq!(water.transition_at(-5.0), Some((ice, None)));
+
+        assert_eq!(registry.get(AtomType::Steam).transition_at(80.0), Some((AtomType::Water, None)));
+        assert_eq!(registry.get(AtomType::Acid).transition_at(130.0), Some((AtomType::Smoke, Some(2.0))));
+        assert_eq!(registry.get(AtomType::Stone).transition_at(5000.0), None);
+    }
+}
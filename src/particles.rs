@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::atoms::{Atom, AtomType, AtomWorld, AtomWorldResource, SIMULATION_DT};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource};
+use crate::world_space::WorldSpace;
+
+// Free-flying atoms as described in "Particles, for real this time". An atom that
+// gets knocked out of the grid (shoved aside by a moving body, thrown by a splash)
+// becomes a particle: it flies under gravity, ignoring the grid's rules, until it
+// hits something and settles back into the grid. Nothing is lost on the way.
+
+pub struct Particle {
+    pub atom: Atom,
+    // Grid position in cells, not snapped to a cell while flying
+    pub position: Vec2,
+    // Grid cells per second
+    pub velocity: Vec2,
+}
+
+#[derive(Resource, Default)]
+pub struct Particles {
+    particles: Vec<Particle>,
+}
+
+impl Particles {
+    pub fn spawn(&mut self, atom: Atom, position: Vec2, velocity: Vec2) {
+        self.particles.push(Particle {
+            atom,
+            position,
+            velocity,
+        });
+    }
+
+    // Knock the atom at `cell` out of the grid and send it flying at `velocity`
+    // (grid cells per second)
+    pub fn eject(&mut self, world: &mut AtomWorld, cell: IVec2, velocity: Vec2) -> bool {
+        let Some(atom) = world.get_atom(cell.x, cell.y).cloned() else {
+            return false;
+        };
+        if atom.atom_type == AtomType::Empty {
+            return false;
+        }
+        world.set_atom(cell.x, cell.y, Atom::default());
+        self.spawn(atom, cell.as_vec2(), velocity);
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Particle> {
+        self.particles.iter()
+    }
+
+    // Fly every particle for `dt` under `gravity` (grid cells per second squared),
+    // landing the ones that hit something. A particle with nowhere to land stops
+    // where it is and drops from there, so its atom is never lost.
+    pub fn step(&mut self, world: &mut AtomWorld, materials: &MaterialRegistry, gravity: Vec2, dt: f32) {
+        self.particles.retain_mut(|particle| {
+            particle.velocity += gravity * dt;
+
+            // March a cell at a time so fast particles can't skip through thin walls
+            let start = particle.position;
+            let end = start + particle.velocity * dt;
+            let steps = (end - start).abs().max_element().ceil().max(1.0) as usize;
+            let mut last_free = start;
+            for i in 1..=steps {
+                let point = start.lerp(end, i as f32 / steps as f32);
+                let cell = point.round().as_ivec2();
+                if !can_fly_through(world, materials, cell) {
+                    if land(world, &particle.atom, last_free.round().as_ivec2()) {
+                        return false;
+                    }
+                    // Nowhere to go yet; drop from here and try again next time
+                    particle.position = last_free;
+                    particle.velocity = Vec2::ZERO;
+                    return true;
+                }
+                last_free = point;
+            }
+
+            particle.position = end;
+            true
+        });
+    }
+}
+
+// How far (in cells) a landing particle looks for room when the cell it stopped
+// in has been taken
+const LANDING_SEARCH_RADIUS: i32 = 3;
+
+// System to fly particles and land them back in the grid. They fall like the
+// rigid bodies do, so splashes and the bodies causing them agree on gravity.
+// They step with the grid, a fixed SIMULATION_DT per tick, so where they land
+// doesn't depend on the frame rate.
+pub fn update_particles(
+    rapier_config: Res<RapierConfiguration>,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut particles: ResMut<Particles>,
+) {
+    let gravity = WorldSpace::world_to_grid_vector(rapier_config.gravity);
+    particles.step(&mut world.0, &materials.0, gravity, SIMULATION_DT);
+}
+
+// Particles pass through empty cells and gases; anything else (including the
+// edge of the world) stops them
+fn can_fly_through(world: &AtomWorld, materials: &MaterialRegistry, cell: IVec2) -> bool {
+    world.in_bounds(cell.x, cell.y)
+        && world
+            .get_atom(cell.x, cell.y)
+            .map_or(true, |atom| atom.atom_type == AtomType::Empty || materials.get(atom.atom_type).is_gas())
+}
+
+// Put a particle's atom back into the grid at `cell`, or the nearest empty cell
+// around it
+fn land(world: &mut AtomWorld, atom: &Atom, cell: IVec2) -> bool {
+    let Some(target) = world.nearest_empty(cell, LANDING_SEARCH_RADIUS) else {
+        return false;
+    };
+    world.set_atom(target.x, target.y, Atom {
+        velocity: Vec2::ZERO,
+        ..atom.clone()
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atoms::CHUNK_SIZE;
+
+    fn atom(materials: &MaterialRegistry, atom_type: AtomType) -> Atom {
+        Atom { atom_type, mass: materials.get(atom_type).mass, ..default() }
+    }
+
+    fn small_world() -> AtomWorld {
+        AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize)
+    }
+
+    fn type_at(world: &AtomWorld, x: i32, y: i32) -> AtomType {
+        world.get_atom(x, y).map_or(AtomType::Empty, |atom| atom.atom_type)
+    }
+
+    #[test]
+    fn fast_particles_stop_at_thin_walls() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = small_world();
+        for y in 0..CHUNK_SIZE {
+            world.set_atom(20, y, atom(&materials, AtomType::Stone));
+        }
+        let mut particles = Particles::default();
+        // 20 cells in one step, which would carry it well past the wall
+        particles.spawn(atom(&materials, AtomType::Sand), Vec2::new(10.0, 10.0), Vec2::new(20.0 / SIMULATION_DT, 0.0));
+
+        particles.step(&mut world, &materials, Vec2::ZERO, SIMULATION_DT);
+
+        assert_eq!(particles.iter().count(), 0);
+        assert_eq!(type_at(&world, 19, 10), AtomType::Sand);
+        assert!((21..CHUNK_SIZE).all(|x| type_at(&world, x, 10) == AtomType::Empty));
+    }
+
+    #[test]
+    fn particles_land_in_the_last_free_cell_before_they_hit() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = small_world();
+        for x in 0..CHUNK_SIZE {
+            world.set_atom(x, 12, atom(&materials, AtomType::Stone));
+        }
+        let mut particles = Particles::default();
+        particles.spawn(atom(&materials, AtomType::Water), Vec2::new(10.3, 4.0), Vec2::new(0.0, 5.0));
+
+        let gravity = Vec2::new(0.0, 300.0);
+        let steps = (1..=60).find(|_| {
+            particles.step(&mut world, &materials, gravity, SIMULATION_DT);
+            particles.iter().count() == 0
+        });
+
+        assert!(steps.is_some(), "never landed");
+        assert_eq!(type_at(&world, 10, 11), AtomType::Water);
+        assert_eq!(world.get_atom(10, 11).unwrap().velocity, Vec2::ZERO);
+    }
+
+    #[test]
+    fn particles_with_nowhere_to_land_wait_instead_of_losing_their_atom() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = small_world();
+        // A room full of smoke over a stone floor: it flies through the smoke, but
+        // there's no empty cell to land in
+        for y in 0..20 {
+            for x in 0..20 {
+                world.set_atom(x, y, atom(&materials, AtomType::Smoke));
+            }
+        }
+        for y in 20..25 {
+            for x in 0..20 {
+                world.set_atom(x, y, atom(&materials, AtomType::Stone));
+            }
+        }
+        let mut particles = Particles::default();
+        particles.spawn(atom(&materials, AtomType::Sand), Vec2::new(10.0, 14.0), Vec2::new(0.0, 10.0 / SIMULATION_DT));
+
+        let gravity = Vec2::new(0.0, 300.0);
+        particles.step(&mut world, &materials, gravity, SIMULATION_DT);
+
+        let waiting: Vec<&Particle> = particles.iter().collect();
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting[0].position.round().as_ivec2(), IVec2::new(10, 19));
+        assert_eq!(waiting[0].velocity, Vec2::ZERO);
+
+        // Once the smoke clears a little it lands
+        world.set_atom(11, 19, Atom::default());
+        let landed = (1..=60).find(|_| {
+            particles.step(&mut world, &materials, gravity, SIMULATION_DT);
+            particles.iter().count() == 0
+        });
+        assert!(landed.is_some(), "never landed");
+        assert_eq!(type_at(&world, 11, 19), AtomType::Sand);
+    }
+}
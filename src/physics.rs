@@ -1,244 +1,1425 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
-use crate::atoms::{AtomWorldResource, AtomType};
+use bevy::utils::{HashMap, HashSet};
+use crate::atoms::{Atom, AtomWorld, AtomWorldResource, AtomType, Chunk, CHUNK_SIZE, SIMULATION_DT};
+use crate::game::{Health, Player};
+use crate::materials::{MaterialRegistry, MaterialRegistryResource, Phase};
+use crate::particles::Particles;
+use crate::random::SimulationRng;
+use crate::world_space::WorldSpace;
+use rand::Rng;
 
-// Physics bridge as described in "Bridging Physics Worlds" blog post
+// Physics bridge as described in "Bridging Physics Worlds" blog post.
+// A piece of terrain knocked loose from the grid, simulated by Rapier until it
+// comes to rest. It carries its atoms as a bitmap laid out like the grid (y
+// down); see `local_position` for where they sit in the body's local space.
 #[derive(Component)]
 pub struct RigidBodyObject {
+    // Most common material in the body
     pub atom_type: AtomType,
-    pub collider_points: Vec<Vec2>,
+    pub size: IVec2,
+    pub atoms: Vec<Atom>,
+    // Grid cells the atoms were written to for this step, with their bitmap index
+    stamped: Vec<(IVec2, usize)>,
+    // How long the body has been (nearly) still
+    resting: f32,
+    // Set when the body's atoms have changed and its texture needs redrawing
+    pub redraw: bool,
 }
 
-// System to create colliders from atom terrain
-pub fn create_terrain_colliders(
-    mut commands: Commands,
-    world: Res<AtomWorldResource>,
-) {
-    // Group solid atoms into collision shapes
-    let mut solid_regions = find_solid_regions(&world.0);
+impl RigidBodyObject {
+    fn new(size: IVec2, atoms: Vec<Atom>) -> Self {
+        let mut counts: HashMap<AtomType, usize> = HashMap::default();
+        for atom in atoms.iter().filter(|atom| atom.atom_type != AtomType::Empty) {
+            *counts.entry(atom.atom_type).or_default() += 1;
+        }
+        let atom_type = counts
+            .into_iter()
+            .max_by_key(|&(atom_type, count)| (count, atom_type.0))
+            .map_or(AtomType::Empty, |(atom_type, _)| atom_type);
 
-    for region in solid_regions {
-        if region.len() < 3 { continue; }
+        Self {
+            atom_type,
+            size,
+            atoms,
+            stamped: Vec::new(),
+            resting: 0.0,
+            redraw: true,
+        }
+    }
 
-        // Create simplified collider from region points
-        let collider_shape = create_convex_hull(&region);
+    // Where the atom at `local` in the bitmap sits in the body's local space,
+    // which is y up like the rest of world space
+    pub fn local_position(local: IVec2) -> Vec2 {
+        Vec2::new(local.x as f32, -local.y as f32)
+    }
 
-        if Collider::convex_hull(&collider_shape).is_some() {
-            commands.spawn((
-                RigidBody::Fixed,
-                Collider::convex_hull(&collider_shape).unwrap(),
-                Transform::default(),
-            ));
-        }
+    // Every atom left in the body with its position in the bitmap
+    pub fn iter_atoms(&self) -> impl Iterator<Item = (IVec2, &Atom)> + '_ {
+        self.atoms.iter().enumerate().filter_map(move |(i, atom)| {
+            let local = IVec2::new(i as i32 % self.size.x, i as i32 / self.size.x);
+            (atom.atom_type != AtomType::Empty).then_some((local, atom))
+        })
+    }
 
+    // Collider around the body's atoms, holes included
+    fn collider(&self) -> Option<Collider> {
+        let solid: Vec<bool> = self.atoms.iter().map(|atom| atom.atom_type != AtomType::Empty).collect();
+        terrain_collider(&trace_solid_outlines(&solid, self.size))
     }
 }
 
-// Find contiguous regions of solid atoms
-fn find_solid_regions(world: &crate::atoms::AtomWorld) -> Vec<Vec<Vec2>> {
-    let mut visited = vec![false; world.width * world.height];
-    let mut regions = Vec::new();
+// Fixed collider for the solid cells of one chunk, positioned at the chunk origin
+#[derive(Component)]
+pub struct TerrainCollider {
+    pub chunk: IVec2,
+}
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            let idx = y * world.width + x;
-            if visited[idx] { continue; }
+struct ChunkColliderState {
+    entity: Entity,
+    // Solid cells the current collider was built from
+    solid: Vec<bool>,
+}
 
-            if let Some(atom) = world.get_atom(x as i32, y as i32) {
-                if is_solid(atom.atom_type) {
-                    let mut region = Vec::new();
-                    flood_fill(world, x, y, &mut visited, &mut region);
-                    if region.len() >= 3 {
-                        regions.push(region);
-                    }
-                }
+// Keeps one terrain collider entity per chunk with solid cells. A chunk's collider
+// is only rebuilt when its solid cells change, and despawned once it has none.
+// Only cells in a chunk's terrain rect are looked at, so untouched chunks cost nothing.
+#[derive(Resource, Default)]
+pub struct TerrainColliders {
+    chunks: HashMap<IVec2, ChunkColliderState>,
+}
+
+impl TerrainColliders {
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+impl TerrainConnectivity {
+    // Cells that were lifted out of the grid aren't solid any more
+    fn forget(&mut self, cells: &[IVec2]) {
+        for &cell in cells {
+            let (chunk, local) = AtomWorld::chunk_coords(cell.x, cell.y);
+            if let Some(solid) = self.chunks.get_mut(&chunk) {
+                solid[Chunk::local_index(local)] = false;
             }
         }
     }
-
-    regions
 }
 
-fn is_solid(atom_type: AtomType) -> bool {
-    matches!(atom_type, AtomType::Stone | AtomType::Sand)
+// Static solid cells per chunk as of the last check. Pieces can only get cut off
+// from the terrain where solid cells have disappeared since then, which is always
+// inside the chunk's terrain rect.
+#[derive(Resource, Default)]
+pub struct TerrainConnectivity {
+    chunks: HashMap<IVec2, Vec<bool>>,
+    // World step of the last check, to notice when the world was replaced
+    step: u64,
 }
 
-fn flood_fill(
-    world: &crate::atoms::AtomWorld,
-    start_x: usize,
-    start_y: usize,
-    visited: &mut Vec<bool>,
-    region: &mut Vec<Vec2>,
+// Max distance (in cells) an outline may be moved from the pixel boundary when simplifying
+const OUTLINE_SIMPLIFY_EPSILON: f32 = 0.4;
+
+// Pieces of terrain up to this many cells come loose once cut off; anything
+// bigger (or touching the world's edge) is anchored terrain
+const MAX_BODY_CELLS: usize = 2048;
+// Smaller pieces stay where they are
+const MIN_BODY_CELLS: usize = 3;
+
+// A body slower than this (cells/s and rad/s) for REST_TIME is written back to the grid
+const REST_SPEED: f32 = 2.0;
+const REST_ANGULAR_SPEED: f32 = 0.2;
+const REST_TIME: f32 = 0.5;
+
+// System to keep the terrain colliders in sync with the atom grid. Runs last of
+// the terrain systems and takes the terrain rects, so they start over next tick.
+pub fn update_terrain_colliders(
+    mut commands: Commands,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut colliders: ResMut<TerrainColliders>,
 ) {
-    let mut stack = vec![(start_x, start_y)];
-
-    while let Some((x, y)) = stack.pop() {
-        let idx = y * world.width + x;
-        if visited[idx] { continue; }
-
-        visited[idx] = true;
-
-        if let Some(atom) = world.get_atom(x as i32, y as i32) {
-            if is_solid(atom.atom_type) {
-                region.push(Vec2::new(x as f32, y as f32));
-
-                // Check neighbors
-                for dx in -1..=1 {
-                    for dy in -1..=1 {
-                        let nx = x as i32 + dx;
-                        let ny = y as i32 + dy;
-                        if nx >= 0 && nx < world.width as i32 && ny >= 0 && ny < world.height as i32 {
-                            let nidx = ny as usize * world.width + nx as usize;
-                            if !visited[nidx] {
-                                stack.push((nx as usize, ny as usize));
-                            }
-                        }
-                    }
+    let world = &mut world.0;
+    // Powder only counts once it has settled, so a sliding sand pile doesn't
+    // rebuild its chunk's collider every tick
+    let is_solid = |atom: &Atom, awake: u8| match materials.0.get(atom.atom_type).phase {
+        Phase::Solid => true,
+        Phase::Powder => awake == 0,
+        _ => false,
+    };
+    let space = WorldSpace::of(world);
+
+    // Colliders of chunks that no longer exist (e.g. after loading a new level)
+    colliders.chunks.retain(|position, state| {
+        let keep = world.chunks.contains_key(position);
+        if !keep {
+            commands.entity(state.entity).despawn();
+        }
+        keep
+    });
+
+    for (&position, chunk) in world.chunks.iter_mut() {
+        let Some(rect) = chunk.take_terrain_rect() else {
+            continue;
+        };
+        let solid_at = |local: IVec2| {
+            let index = Chunk::local_index(local);
+            is_solid(&chunk.atoms[index], chunk.awake[index])
+        };
+        let unchanged = colliders
+            .chunks
+            .get(&position)
+            .is_some_and(|state| rect.cells().all(|local| state.solid[Chunk::local_index(local)] == solid_at(local)));
+        if unchanged {
+            continue;
+        }
+
+        let solid: Vec<bool> = chunk
+            .atoms
+            .iter()
+            .zip(&chunk.awake)
+            .map(|(atom, &awake)| is_solid(atom, awake))
+            .collect();
+
+        let outlines = trace_solid_outlines(&solid, IVec2::splat(CHUNK_SIZE));
+        let Some(collider) = terrain_collider(&outlines) else {
+            if let Some(state) = colliders.chunks.remove(&position) {
+                commands.entity(state.entity).despawn();
+            }
+            continue;
+        };
+
+        match colliders.chunks.get_mut(&position) {
+            Some(state) => {
+                commands.entity(state.entity).insert(collider);
+                state.solid = solid;
+            }
+            None => {
+                let origin = space.grid_to_world(chunk.origin());
+                let entity = commands
+                    .spawn((
+                        TerrainCollider { chunk: position },
+                        RigidBody::Fixed,
+                        collider,
+                        TransformBundle::from_transform(Transform::from_translation(origin.extend(0.0))),
+                    ))
+                    .id();
+                colliders.chunks.insert(position, ChunkColliderState { entity, solid });
+            }
+        }
+    }
+}
+
+// Closed outlines around the solid cells of a row-major grid (a chunk or a body's
+// bitmap), in local cell units with y up: cell (x, y) is centred on (x, -y).
+// Cells outside the grid count as empty, so every outline is closed within it.
+fn trace_solid_outlines(solid: &[bool], size: IVec2) -> Vec<Vec<Vec2>> {
+    let is_solid = |x: i32, y: i32| {
+        (0..size.x).contains(&x) && (0..size.y).contains(&y) && solid[(y * size.x + x) as usize]
+    };
+
+    // Marching squares over cell centres. Points are stored doubled so edge
+    // midpoints stay on integers, and each segment keeps solid on its left.
+    let mut segments: HashMap<IVec2, IVec2> = HashMap::default();
+    for y in -1..size.y {
+        for x in -1..size.x {
+            // Corners counter-clockwise from bottom left; edge i runs from corner i to i + 1
+            let corners = [
+                is_solid(x, y),
+                is_solid(x + 1, y),
+                is_solid(x + 1, y + 1),
+                is_solid(x, y + 1),
+            ];
+            let midpoints = [
+                IVec2::new(2 * x + 1, 2 * y),
+                IVec2::new(2 * x + 2, 2 * y + 1),
+                IVec2::new(2 * x + 1, 2 * y + 2),
+                IVec2::new(2 * x, 2 * y + 1),
+            ];
+
+            // One segment per run of solid corners, from the edge leaving the run
+            // back to the edge entering it. Diagonal saddles stay separate.
+            for last in 0..4 {
+                if !corners[last] || corners[(last + 1) % 4] {
+                    continue;
+                }
+                let mut first = last;
+                while corners[(first + 3) % 4] && (first + 3) % 4 != last {
+                    first = (first + 3) % 4;
                 }
+                segments.insert(midpoints[last], midpoints[(first + 3) % 4]);
             }
         }
     }
+
+    let mut outlines = Vec::new();
+    while let Some(&start) = segments.keys().next() {
+        let mut outline = Vec::new();
+        let mut point = start;
+        while let Some(next) = segments.remove(&point) {
+            outline.push(Vec2::new(point.x as f32, -point.y as f32) / 2.0);
+            point = next;
+        }
+        // Flipping y mirrors the outline, so reverse it to keep solids counter-clockwise
+        outline.reverse();
+        let outline = simplify_closed_outline(&outline, OUTLINE_SIMPLIFY_EPSILON);
+        if outline.len() >= 3 {
+            outlines.push(outline);
+        }
+    }
+    outlines
 }
 
-// Create convex hull using gift wrapping algorithm
-fn create_convex_hull(points: &[Vec2]) -> Vec<Vec2> {
+// Ramer-Douglas-Peucker for a closed loop: split at the point farthest from an
+// extreme point (which is always a corner) and simplify both halves
+fn simplify_closed_outline(points: &[Vec2], epsilon: f32) -> Vec<Vec2> {
     if points.len() <= 3 {
         return points.to_vec();
     }
 
-    // Find leftmost point
-    let mut leftmost = 0;
-    for i in 1..points.len() {
-        if points[i].x < points[leftmost].x {
-            leftmost = i;
+    let lowest = (0..points.len())
+        .min_by(|&a, &b| (points[a].x, points[a].y).partial_cmp(&(points[b].x, points[b].y)).unwrap())
+        .unwrap_or(0);
+    let mut points = points.to_vec();
+    points.rotate_left(lowest);
+
+    let far = (1..points.len())
+        .max_by(|&a, &b| {
+            points[0]
+                .distance_squared(points[a])
+                .total_cmp(&points[0].distance_squared(points[b]))
+        })
+        .unwrap_or(1);
+
+    let mut first_half = points[..=far].to_vec();
+    let mut second_half = points[far..].to_vec();
+    second_half.push(points[0]);
+
+    first_half = simplify_polyline(&first_half, epsilon);
+    second_half = simplify_polyline(&second_half, epsilon);
+
+    // Both halves share their end points
+    first_half.pop();
+    second_half.pop();
+    first_half.extend(second_half);
+    first_half
+}
+
+fn simplify_polyline(points: &[Vec2], epsilon: f32) -> Vec<Vec2> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+
+    let (start, end) = (points[0], points[points.len() - 1]);
+    let (index, distance) = points[1..points.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(i, &point)| (i + 1, distance_to_segment(point, start, end)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+
+    if distance <= epsilon {
+        return vec![start, end];
+    }
+
+    let mut simplified = simplify_polyline(&points[..=index], epsilon);
+    simplified.pop();
+    simplified.extend(simplify_polyline(&points[index..], epsilon));
+    simplified
+}
+
+fn distance_to_segment(point: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let segment = end - start;
+    let length_squared = segment.length_squared();
+    if length_squared == 0.0 {
+        return point.distance(start);
+    }
+    let t = ((point - start).dot(segment) / length_squared).clamp(0.0, 1.0);
+    point.distance(start + segment * t)
+}
+
+// Solid collider for a chunk: its outlines are triangulated (holes included) into
+// a compound of triangles. Falls back to the bare outlines as a polyline if some
+// outline can't be triangulated.
+fn terrain_collider(outlines: &[Vec<Vec2>]) -> Option<Collider> {
+    if outlines.is_empty() {
+        return None;
+    }
+
+    match triangulate_outlines(outlines) {
+        Some(triangles) if !triangles.is_empty() => Some(Collider::compound(
+            triangles
+                .into_iter()
+                .map(|[a, b, c]| (Vec2::ZERO, 0.0, Collider::triangle(a, b, c)))
+                .collect(),
+        )),
+        _ => Some(outline_collider(outlines)),
+    }
+}
+
+// A single polyline collider made of all of a chunk's closed outlines
+fn outline_collider(outlines: &[Vec<Vec2>]) -> Collider {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for outline in outlines {
+        let base = vertices.len() as u32;
+        let count = outline.len() as u32;
+        vertices.extend_from_slice(outline);
+        indices.extend((0..count).map(|i| [base + i, base + (i + 1) % count]));
+    }
+    Collider::polyline(vertices, Some(indices))
+}
+
+// Triangles covering the solid side of the outlines. Counter-clockwise outlines
+// are solid areas, clockwise ones are holes in the smallest outline around them.
+fn triangulate_outlines(outlines: &[Vec<Vec2>]) -> Option<Vec<[Vec2; 3]>> {
+    let (solids, holes): (Vec<&Vec<Vec2>>, Vec<&Vec<Vec2>>) =
+        outlines.iter().partition(|outline| is_counter_clockwise(outline));
+
+    let mut holes_of: Vec<Vec<&Vec<Vec2>>> = vec![Vec::new(); solids.len()];
+    for hole in holes {
+        let container = (0..solids.len())
+            .filter(|&i| point_in_polygon(hole[0], solids[i]))
+            .min_by(|&a, &b| signed_area(solids[a]).total_cmp(&signed_area(solids[b])));
+        if let Some(container) = container {
+            holes_of[container].push(hole);
+        }
+    }
+
+    let mut triangles = Vec::new();
+    for (solid, holes) in solids.into_iter().zip(holes_of) {
+        let polygon = bridge_holes(solid, holes)?;
+        triangles.extend(ear_clipping_triangulation(&polygon)?);
+    }
+    Some(triangles)
+}
+
+// Merge holes into the outline around them by cutting a zero-width bridge from
+// each hole's rightmost point to the closest outline point it can see, leaving a
+// single polygon to triangulate
+fn bridge_holes(outline: &[Vec2], mut holes: Vec<&Vec<Vec2>>) -> Option<Vec<Vec2>> {
+    let mut polygon = outline.to_vec();
+    let rightmost = |points: &[Vec2]| {
+        (0..points.len())
+            .max_by(|&a, &b| points[a].x.total_cmp(&points[b].x))
+            .unwrap_or(0)
+    };
+    holes.sort_by(|a, b| b[rightmost(b)].x.total_cmp(&a[rightmost(a)].x));
+
+    for (index, hole) in holes.iter().enumerate() {
+        let start = rightmost(hole);
+        let from = hole[start];
+
+        let mut candidates: Vec<usize> = (0..polygon.len()).collect();
+        candidates.sort_by(|&a, &b| {
+            from.distance_squared(polygon[a])
+                .total_cmp(&from.distance_squared(polygon[b]))
+        });
+
+        // The bridge can't cross or even touch the polygon so far or any hole,
+        // this one included
+        let visible = |to: Vec2| {
+            let loops = std::iter::once(&polygon).chain(holes[index..].iter().copied());
+            loops.into_iter().all(|points| {
+                (0..points.len()).all(|i| {
+                    let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                    !segments_cross(from, to, a, b) && !point_on_segment(a, from, to)
+                })
+            })
+        };
+        let target = candidates.into_iter().find(|&i| visible(polygon[i]))?;
+
+        let mut bridged = polygon[..=target].to_vec();
+        bridged.extend(hole[start..].iter().chain(&hole[..=start]));
+        bridged.extend_from_slice(&polygon[target..]);
+        polygon = bridged;
+    }
+    Some(polygon)
+}
+
+// Ear clipping triangulation, ported from the optimizing_physics_bridge example.
+// Coincident points left by hole bridges are allowed. Returns None if the polygon
+// runs out of ears before it is fully clipped.
+fn ear_clipping_triangulation(points: &[Vec2]) -> Option<Vec<[Vec2; 3]>> {
+    if points.len() < 3 {
+        return Some(Vec::new());
+    }
+
+    let mut remaining_points = points.to_vec();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+
+    // Ensure counter-clockwise winding
+    if !is_counter_clockwise(&remaining_points) {
+        remaining_points.reverse();
+    }
+
+    while remaining_points.len() >= 3 {
+        let len = remaining_points.len();
+        let corner = |i: usize| {
+            let prev = if i == 0 { len - 1 } else { i - 1 };
+            (remaining_points[prev], remaining_points[i], remaining_points[(i + 1) % len])
+        };
+
+        let ear = (0..len).find(|&i| is_ear(&remaining_points, i)).or_else(|| {
+            // Flat corners (e.g. along a bridge) don't add area, so they can just go
+            (0..len).find(|&i| {
+                let (a, b, c) = corner(i);
+                cross(a, b, c).abs() < 1e-6
+            })
+        })?;
+
+        let (a, b, c) = corner(ear);
+        if is_convex(a, b, c) {
+            triangles.push([a, b, c]);
         }
+        remaining_points.remove(ear);
+    }
+
+    Some(triangles)
+}
+
+fn signed_area(points: &[Vec2]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..points.len() {
+        let (p1, p2) = (points[i], points[(i + 1) % points.len()]);
+        sum += p1.perp_dot(p2);
     }
+    sum / 2.0
+}
+
+fn is_counter_clockwise(points: &[Vec2]) -> bool {
+    signed_area(points) > 0.0
+}
+
+fn is_ear(points: &[Vec2], index: usize) -> bool {
+    let len = points.len();
+    let prev = if index == 0 { len - 1 } else { index - 1 };
+    let next = (index + 1) % len;
 
-    let mut hull = Vec::new();
-    let mut p = leftmost;
-    let mut q;
+    let (p1, p2, p3) = (points[prev], points[index], points[next]);
 
-    loop {
-        hull.push(points[p]);
+    // Check if triangle is convex
+    if !is_convex(p1, p2, p3) {
+        return false;
+    }
+
+    // Check if no other points are inside the triangle. Copies of its own corners
+    // (from hole bridges) don't count, so also make sure no edge crosses the cut.
+    points.iter().enumerate().all(|(i, &point)| {
+        i == prev
+            || i == index
+            || i == next
+            || point == p1
+            || point == p2
+            || point == p3
+            || !point_in_triangle(point, p1, p2, p3)
+    }) && (0..len).all(|i| {
+        let (a, b) = (points[i], points[(i + 1) % len]);
+        !segments_cross(p1, p3, a, b)
+    })
+}
 
-        q = (p + 1) % points.len();
+fn cross(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    (b - a).perp_dot(c - a)
+}
+
+fn is_convex(p1: Vec2, p2: Vec2, p3: Vec2) -> bool {
+    cross(p1, p2, p3) > 1e-6
+}
+
+// Inside counter-clockwise triangle abc, or on its edge ca. That edge is the
+// diagonal an ear cuts off, while ab and bc are already polygon edges.
+fn point_in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    cross(a, b, p) > 0.0 && cross(b, c, p) > 0.0 && cross(c, a, p) >= 0.0
+}
+
+fn point_in_polygon(point: Vec2, polygon: &[Vec2]) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
 
-        for i in 0..points.len() {
-            if orientation(points[p], points[i], points[q]) < 0.0 {
-                q = i;
+// Whether p lies on segment ab, end points excluded
+fn point_on_segment(p: Vec2, a: Vec2, b: Vec2) -> bool {
+    p != a && p != b && cross(a, b, p).abs() < 1e-6 && (p - a).dot(p - b) < 0.0
+}
+
+// Whether segments ab and cd cross at a point inside both of them
+fn segments_cross(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
+    let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+    let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+// Lift pieces of terrain that have been cut off (by acid, fire, explosions, ...)
+// out of the grid and into dynamic bodies
+pub fn detach_rigid_bodies(
+    mut commands: Commands,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut connectivity: ResMut<TerrainConnectivity>,
+) {
+    let world = &mut world.0;
+    let materials = &materials.0;
+    let is_static = |atom: &Atom| materials.get(atom.atom_type).phase == Phase::Solid;
+
+    // A new world (e.g. a freshly loaded level) has nothing to compare against
+    if world.step < connectivity.step {
+        connectivity.chunks.clear();
+    }
+    connectivity.step = world.step;
+    connectivity.chunks.retain(|position, _| world.chunks.contains_key(position));
+
+    // Cells that were solid last time but aren't any more. The terrain rects are
+    // left for update_terrain_colliders to take.
+    let mut removed = Vec::new();
+    for (&position, chunk) in &world.chunks {
+        let Some(rect) = chunk.terrain_rect else {
+            continue;
+        };
+        let Some(previous) = connectivity.chunks.get_mut(&position) else {
+            connectivity.chunks.insert(position, chunk.atoms.iter().map(is_static).collect());
+            continue;
+        };
+        for local in rect.cells() {
+            let index = Chunk::local_index(local);
+            let is = is_static(&chunk.atoms[index]);
+            if previous[index] && !is {
+                removed.push(chunk.origin() + local);
             }
+            previous[index] = is;
         }
+    }
 
-        p = q;
+    // Flood out from the solid neighbours of every removed cell. Terrain found to
+    // be anchored is remembered so it isn't flooded again from the next cell.
+    let bounds = world.cell_bounds();
+    let mut anchored: HashSet<IVec2> = HashSet::default();
+    for cell in removed {
+        for start in [cell + IVec2::X, cell - IVec2::X, cell + IVec2::Y, cell - IVec2::Y] {
+            if anchored.contains(&start) || !world.get_atom(start.x, start.y).is_some_and(is_static) {
+                continue;
+            }
+            match loose_piece(world, start, bounds, &is_static, &anchored) {
+                Ok(piece) if piece.len() >= MIN_BODY_CELLS => {
+                    lift_piece(&mut commands, world, materials, &piece);
+                    connectivity.forget(&piece);
+                }
+                Ok(piece) | Err(piece) => anchored.extend(piece),
+            }
+        }
+    }
+}
 
-        if p == leftmost {
-            break;
+// The static cells connected to `start`, or Err with the cells visited so far if
+// they turn out to be anchored: too many of them, or reaching the world's edge
+fn loose_piece(
+    world: &AtomWorld,
+    start: IVec2,
+    bounds: IRect,
+    is_static: &impl Fn(&Atom) -> bool,
+    anchored: &HashSet<IVec2>,
+) -> Result<Vec<IVec2>, Vec<IVec2>> {
+    let mut piece = vec![start];
+    let mut visited: HashSet<IVec2> = HashSet::default();
+    visited.insert(start);
+
+    let mut next = 0;
+    while let Some(&cell) = piece.get(next) {
+        next += 1;
+        let on_edge = cell.x <= bounds.min.x
+            || cell.y <= bounds.min.y
+            || cell.x >= bounds.max.x - 1
+            || cell.y >= bounds.max.y - 1;
+        if on_edge || anchored.contains(&cell) || piece.len() > MAX_BODY_CELLS {
+            return Err(piece);
+        }
+
+        for neighbour in [cell + IVec2::X, cell - IVec2::X, cell + IVec2::Y, cell - IVec2::Y] {
+            if !visited.contains(&neighbour) && world.get_atom(neighbour.x, neighbour.y).is_some_and(is_static) {
+                visited.insert(neighbour);
+                piece.push(neighbour);
+            }
         }
     }
+    Ok(piece)
+}
+
+// Move a piece's atoms out of the grid into a dynamic body in the same place
+fn lift_piece(commands: &mut Commands, world: &mut AtomWorld, materials: &MaterialRegistry, cells: &[IVec2]) {
+    let min = cells.iter().copied().fold(IVec2::MAX, IVec2::min);
+    let max = cells.iter().copied().fold(IVec2::MIN, IVec2::max);
+    let size = max - min + 1;
+
+    let mut atoms = vec![Atom::default(); (size.x * size.y) as usize];
+    for &cell in cells {
+        let local = cell - min;
+        if let Some(atom) = world.get_atom(cell.x, cell.y) {
+            atoms[(local.y * size.x + local.x) as usize] = atom.clone();
+        }
+        world.set_atom(cell.x, cell.y, Atom::default());
+    }
+
+    let body = RigidBodyObject::new(size, atoms);
+    let Some(collider) = body.collider() else {
+        return;
+    };
+    let origin = WorldSpace::of(world).grid_to_world(min);
+    let material = materials.get(body.atom_type);
+    commands.spawn((
+        RigidBody::Dynamic,
+        collider,
+        ColliderMassProperties::Density(material.density),
+        Friction::coefficient(material.friction),
+        Velocity::zero(),
+        TransformBundle::from_transform(Transform::from_translation(origin.extend(0.0))),
+        body,
+    ));
+}
 
-    hull
+// Grid cell under a body's atom at `local`
+fn body_cell(space: &WorldSpace, transform: &Transform, local: IVec2) -> IVec2 {
+    let position = transform.transform_point(RigidBodyObject::local_position(local).extend(0.0));
+    space.world_to_grid(position.truncate())
 }
 
-fn orientation(p: Vec2, q: Vec2, r: Vec2) -> f32 {
-    (q.y - p.y) * (r.x - q.x) - (q.x - p.x) * (r.y - q.y)
+// Write the bodies' atoms into the empty cells they cover for the coming step, so
+// the simulation sees them: fluids pile up against them, acid and fire eat them
+pub fn stamp_rigid_bodies(
+    mut world: ResMut<AtomWorldResource>,
+    mut bodies: Query<(&mut RigidBodyObject, &Transform)>,
+) {
+    let world = &mut world.0;
+    let space = WorldSpace::of(world);
+
+    for (mut body, transform) in bodies.iter_mut() {
+        let body = &mut *body;
+        body.stamped.clear();
+        for (i, atom) in body.atoms.iter().enumerate() {
+            if atom.atom_type == AtomType::Empty {
+                continue;
+            }
+            let local = IVec2::new(i as i32 % body.size.x, i as i32 / body.size.x);
+            let cell = body_cell(&space, transform, local);
+            if world.in_bounds(cell.x, cell.y) && world.is_empty(cell.x, cell.y) {
+                world.set_atom(cell.x, cell.y, atom.clone());
+                body.stamped.push((cell, i));
+            }
+        }
+    }
 }
 
-// Optimize colliders using ear clipping as mentioned in "Optimizing the Physics Bridge"
-pub fn optimize_colliders_with_ear_clipping(
+// Take the stamped atoms back out after the step. Atoms the step destroyed or
+// turned into something else leave holes, and the body's collider is rebuilt.
+pub fn unstamp_rigid_bodies(
     mut commands: Commands,
-    world: Res<AtomWorldResource>,
+    mut world: ResMut<AtomWorldResource>,
+    mut bodies: Query<(Entity, &mut RigidBodyObject)>,
 ) {
-    // This would implement the ear clipping triangulation
-    // For performance as described in the blog post
-    // Simplified version for now
-    create_terrain_colliders(commands, world);
+    let world = &mut world.0;
+
+    for (entity, mut body) in bodies.iter_mut() {
+        let body = &mut *body;
+        let mut damaged = false;
+        for (cell, i) in body.stamped.drain(..) {
+            match world.get_atom(cell.x, cell.y) {
+                Some(atom) if atom.atom_type == body.atoms[i].atom_type => {
+                    // Keep whatever else happened to it, like heating up or catching fire
+                    body.atoms[i] = atom.clone();
+                    world.set_atom(cell.x, cell.y, Atom::default());
+                }
+                _ => {
+                    body.atoms[i] = Atom::default();
+                    damaged = true;
+                }
+            }
+        }
+
+        if damaged {
+            body.redraw = true;
+            match body.collider() {
+                Some(collider) => {
+                    commands.entity(entity).insert(collider);
+                }
+                None => commands.entity(entity).despawn(),
+            }
+        }
+    }
+}
+
+// How far (in cells) a settling body looks for room for the gas it pushes out of
+// its cells, and for its own atoms that land on something
+const SETTLE_SEARCH_RADIUS: i32 = 3;
+
+// Bodies that have come to rest are written back into the grid as terrain
+pub fn settle_rigid_bodies(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut particles: ResMut<Particles>,
+    mut bodies: Query<(Entity, &mut RigidBodyObject, &Transform, &Velocity)>,
+) {
+    let world = &mut world.0;
+    let materials = &materials.0;
+    let space = WorldSpace::of(world);
+
+    for (entity, mut body, transform, velocity) in bodies.iter_mut() {
+        if velocity.linvel.length() < REST_SPEED && velocity.angvel.abs() < REST_ANGULAR_SPEED {
+            body.resting += time.delta_seconds();
+        } else {
+            body.resting = 0.0;
+        }
+        if body.resting < REST_TIME {
+            continue;
+        }
+
+        // The body takes its cells, pushing any gas out of them. Its atoms that land
+        // on anything else, and the gas it pushed out, go to the nearest empty
+        // cell, or fly off as particles if there is none close by.
+        let mut displaced = Vec::new();
+        for (local, atom) in body.iter_atoms() {
+            let cell = body_cell(&space, transform, local);
+            let occupant = world.get_atom(cell.x, cell.y).cloned();
+            match occupant {
+                _ if !world.in_bounds(cell.x, cell.y) => displaced.push((cell, atom.clone())),
+                Some(occupant) if occupant.atom_type != AtomType::Empty && !materials.get(occupant.atom_type).is_gas() => {
+                    displaced.push((cell, atom.clone()));
+                }
+                occupant => {
+                    world.set_atom(cell.x, cell.y, atom.clone());
+                    if let Some(gas) = occupant.filter(|occupant| occupant.atom_type != AtomType::Empty) {
+                        displaced.push((cell, gas));
+                    }
+                }
+            }
+        }
+        for (cell, atom) in displaced {
+            match world.nearest_empty(cell, SETTLE_SEARCH_RADIUS) {
+                Some(target) => world.set_atom(target.x, target.y, atom),
+                None => particles.spawn(atom, cell.as_vec2(), Vec2::ZERO),
+            }
+        }
+        commands.entity(entity).despawn();
+    }
 }
 
-// Atoms interacting with rigid bodies
+// Drag on a fully submerged body, per second: some from the fluid's density, and
+// much more from its viscosity
+const FLUID_DRAG: f32 = 1.0;
+const VISCOUS_DRAG: f32 = 6.0;
+
+// Buoyancy and drag for dynamic bodies submerged in fluid atoms. Each body is
+// sampled at one point per cell of its collider; a sample sitting in a fluid cell
+// displaces that cell's worth of fluid, which pushes up at that point against
+// gravity in proportion to the fluid's density. Runs in FixedUpdate right before
+// Rapier steps, so it works with the same timestep as gravity does.
 pub fn atoms_push_rigid_bodies(
-    mut rigid_bodies: Query<(&mut Velocity, &Transform), With<RigidBody>>,
+    mut rigid_bodies: Query<(
+        &RigidBody,
+        &mut Velocity,
+        &Transform,
+        &Collider,
+        Option<&RigidBodyObject>,
+        Option<&ColliderMassProperties>,
+        Option<&LockedAxes>,
+    )>,
     world: Res<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    rapier_config: Res<RapierConfiguration>,
     time: Res<Time>,
 ) {
     let dt = time.delta_seconds();
+    let world = &world.0;
+    let materials = &materials.0;
+    let space = WorldSpace::of(world);
+
+    for (rigid_body, mut velocity, transform, collider, body, mass_properties, locked_axes) in rigid_bodies.iter_mut() {
+        if *rigid_body != RigidBody::Dynamic {
+            continue;
+        }
+
+        let points = body_points(transform, collider, body);
+        if points.is_empty() {
+            continue;
+        }
+        let footprint: HashSet<IVec2> = points
+            .iter()
+            .map(|&point| space.world_to_grid(point))
+            .collect();
 
-    for (mut velocity, transform) in rigid_bodies.iter_mut() {
-        let pos = transform.translation.truncate();
-        let size = Vec2::new(1.0, 1.0); // Assuming 1x1 objects for now
+        // Everything below is per unit of body mass, so it can go straight into
+        // the velocity. Bodies have uniform density, like their Rapier colliders.
+        let density = match mass_properties {
+            Some(ColliderMassProperties::Density(density)) => *density,
+            _ => 1.0,
+        };
+        let cell_mass = density * points.len() as f32;
+        let center = points.iter().sum::<Vec2>() / points.len() as f32;
+        let inertia = points.iter().map(|point| point.distance_squared(center)).sum::<f32>()
+            / points.len() as f32
+            + 1.0 / 6.0;
 
-        // Calculate force from surrounding atoms
-        let force = calculate_atom_force(&world.0, pos, size);
-        velocity.linvel += force * dt;
+        let mut lift = Vec2::ZERO;
+        let mut torque = 0.0;
+        let mut drag = 0.0;
+        for &point in &points {
+            let cell = space.world_to_grid(point);
+            let Some(fluid) = submerging_fluid(world, materials, cell, &footprint) else {
+                continue;
+            };
+
+            let fluid = materials.get(fluid);
+            let fluid_density = fluid.density;
+            let force = -rapier_config.gravity * fluid_density / cell_mass;
+            lift += force;
+            torque += (point - center).perp_dot(force);
+            drag += (FLUID_DRAG * fluid_density + VISCOUS_DRAG * fluid.viscosity) / cell_mass;
+        }
+
+        let damping = (1.0 - drag * dt).max(0.0);
+        velocity.linvel = (velocity.linvel + lift * dt) * damping;
+        if !locked_axes.is_some_and(|axes| axes.contains(LockedAxes::ROTATION_LOCKED)) {
+            velocity.angvel = (velocity.angvel + torque / inertia * dt) * damping;
+        }
     }
 }
 
-fn calculate_atom_force(world: &crate::atoms::AtomWorld, pos: Vec2, size: Vec2) -> Vec2 {
-    let mut force = Vec2::ZERO;
-    let half_size = size / 2.0;
+// The fluid a cell of a body is submerged in: whatever fluid is in the cell, or
+// else the fluid beside the body at the same height. Moving bodies push fluid out
+// of the cells they cover, but are still under the surface next to them.
+fn submerging_fluid(
+    world: &AtomWorld,
+    materials: &MaterialRegistry,
+    cell: IVec2,
+    footprint: &HashSet<IVec2>,
+) -> Option<AtomType> {
+    let fluid_at = |cell: IVec2| {
+        world
+            .get_atom(cell.x, cell.y)
+            .map(|atom| atom.atom_type)
+            .filter(|&atom_type| materials.get(atom_type).is_fluid())
+    };
 
-    // Sample atoms around the object
-    let samples = 8;
-    for i in 0..samples {
-        let angle = (i as f32 / samples as f32) * std::f32::consts::TAU;
-        let sample_pos = pos + Vec2::new(angle.cos(), angle.sin()) * half_size.x;
+    fluid_at(cell).or_else(|| {
+        [IVec2::NEG_X, IVec2::X].into_iter().find_map(|step| {
+            let mut side = cell + step;
+            while footprint.contains(&side) {
+                side += step;
+            }
+            fluid_at(side)
+        })
+    })
+}
 
-        let x = sample_pos.x.round() as i32;
-        let y = sample_pos.y.round() as i32;
+// World positions of one point per cell of a body: the atoms of a body made of
+// atoms, otherwise samples of its collider
+fn body_points(transform: &Transform, collider: &Collider, body: Option<&RigidBodyObject>) -> Vec<Vec2> {
+    let samples: Vec<Vec2> = match body {
+        Some(body) => body.iter_atoms().map(|(local, _)| RigidBodyObject::local_position(local)).collect(),
+        None => collider_samples(collider),
+    };
+    samples
+        .iter()
+        .map(|sample| transform.transform_point(sample.extend(0.0)).truncate())
+        .collect()
+}
 
-        if let Some(atom) = world.get_atom(x, y) {
-            if atom.atom_type != AtomType::Empty {
-                // Push away from atoms
-                let atom_pos = Vec2::new(x as f32, y as f32);
-                let dir = (pos - atom_pos).normalize();
-                let strength = atom.atom_type.density();
-                force += dir * strength * 0.1;
+// One point per cell covered by a box or ball collider, in the collider's space
+fn collider_samples(collider: &Collider) -> Vec<Vec2> {
+    let (half_size, radius) = if let Some(cuboid) = collider.as_cuboid() {
+        (cuboid.half_extents(), None)
+    } else if let Some(ball) = collider.as_ball() {
+        (Vec2::splat(ball.radius()), Some(ball.radius()))
+    } else {
+        return Vec::new();
+    };
+
+    let cells = (half_size * 2.0).round().max(Vec2::ONE).as_ivec2();
+    let mut samples = Vec::new();
+    for y in 0..cells.y {
+        for x in 0..cells.x {
+            let sample = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - cells.as_vec2() / 2.0;
+            if radius.map_or(true, |radius| sample.length() <= radius) {
+                samples.push(sample);
             }
         }
     }
-
-    force
+    samples
 }
 
-// Moving bodies displace atoms
+// Speed (cells/s) displaced atoms are thrown clear of a body with, on top of the
+// body's own speed where they were
+const EJECT_SPEED: f32 = 8.0;
+
+// Moving bodies knock the loose atoms in their way out of the grid as particles,
+// which land again elsewhere, so material is never crushed or deleted
 pub fn rigid_bodies_displace_atoms(
-    rigid_bodies: Query<(&Transform, &Velocity), With<RigidBody>>,
+    rigid_bodies: Query<(&RigidBody, &Transform, &Velocity, &Collider, Option<&RigidBodyObject>)>,
     mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut particles: ResMut<Particles>,
 ) {
-    for (transform, velocity) in rigid_bodies.iter() {
-        let pos = transform.translation.truncate();
-        let vel = velocity.linvel;
+    let world = &mut world.0;
+    let space = WorldSpace::of(world);
+
+    for (rigid_body, transform, velocity, collider, body) in rigid_bodies.iter() {
+        if *rigid_body != RigidBody::Dynamic || velocity.linvel.length_squared() <= 0.1 {
+            continue;
+        }
 
-        if vel.length_squared() > 0.1 {
-            // Object is moving, displace atoms
-            displace_atoms_around_point(&mut world.0, pos, vel);
+        let points = body_points(transform, collider, body);
+        if points.is_empty() {
+            continue;
+        }
+        let center = points.iter().sum::<Vec2>() / points.len() as f32;
+
+        for point in points {
+            let cell = space.world_to_grid(point);
+            // Terrain is the colliders' job, and gases just let bodies through
+            let loose = world.get_atom(cell.x, cell.y).is_some_and(|atom| {
+                let phase = materials.0.get(atom.atom_type).phase;
+                atom.atom_type != AtomType::Empty && phase != Phase::Gas && phase != Phase::Solid
+            });
+            if !loose {
+                continue;
+            }
+
+            let offset = point - center;
+            let point_velocity = velocity.linvel + offset.perp() * velocity.angvel;
+            let throw = point_velocity + offset.normalize_or_zero() * EJECT_SPEED;
+            particles.eject(world, cell, WorldSpace::world_to_grid_vector(throw));
         }
     }
 }
 
-fn displace_atoms_around_point(world: &mut crate::atoms::AtomWorld, pos: Vec2, force: Vec2) {
-    let radius = 2.0;
-    let force_strength = force.length();
+// Corrosive atoms touching a body eat away at its health and get used up doing it.
+// Like the grid, this rolls SimulationRng once per tick, so it steps by SIMULATION_DT.
+pub fn corrosive_atoms_damage_bodies(
+    mut commands: Commands,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    mut rng: ResMut<SimulationRng>,
+    mut bodies: Query<(Entity, &Transform, &mut Health, Option<&Sprite>, Has<Player>)>,
+) {
+    let dt = SIMULATION_DT;
+    let world = &mut world.0;
+    let materials = &materials.0;
+    let space = WorldSpace::of(world);
 
-    for dx in -(radius as i32)..=(radius as i32) {
-        for dy in -(radius as i32)..=(radius as i32) {
-            let dist = Vec2::new(dx as f32, dy as f32).length();
-            if dist <= radius {
-                let x = (pos.x + dx as f32) as i32;
-                let y = (pos.y + dy as f32) as i32;
+    for (entity, transform, mut health, sprite, is_player) in bodies.iter_mut() {
+        let center = space.world_to_grid_point(transform.translation.truncate());
+        let half_size = sprite.and_then(|sprite| sprite.custom_size).unwrap_or(Vec2::ONE) / 2.0;
+        // Sample one cell past the body's outline so touching atoms count
+        let min = (center - half_size).floor().as_ivec2() - 1;
+        let max = (center + half_size).ceil().as_ivec2() + 1;
 
-                if let Some(atom) = world.get_atom_mut(x, y) {
-                    if atom.atom_type != AtomType::Empty && !atom.atom_type.is_gas() {
-                        // Move atom in direction of force
-                        let push_dir = Vec2::new(dx as f32, dy as f32).normalize();
-                        let push_x = x + (push_dir.x * force_strength * 0.5) as i32;
-                        let push_y = y + (push_dir.y * force_strength * 0.5) as i32;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let Some(atom) = world.get_atom(x, y) else {
+                    continue;
+                };
+                let Some((damage, consumption, byproduct, lifetime)) = materials
+                    .get(atom.atom_type)
+                    .corrosion
+                    .as_ref()
+                    .map(|c| (c.damage, c.consumption, c.byproduct_type, c.byproduct_lifetime))
+                else {
+                    continue;
+                };
 
-                        if world.is_empty(push_x, push_y) {
-                            world.swap_atoms(x, y, push_x, push_y);
-                        }
-                    }
+                let temperature = atom.temperature;
+                health.damage(damage * dt);
+
+                if rng.rng().gen::<f32>() < consumption * dt {
+                    world.set_atom(x, y, Atom {
+                        atom_type: byproduct,
+                        velocity: Vec2::ZERO,
+                        mass: materials.get(byproduct).mass,
+                        lifetime,
+                        temperature,
+                        ..default()
+                    });
                 }
             }
         }
+
+        if health.is_dead() && !is_player {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn area(triangles: &[[Vec2; 3]]) -> f32 {
+        triangles.iter().map(|&[a, b, c]| cross(a, b, c).abs() / 2.0).sum()
+    }
+
+    fn covers(triangles: &[[Vec2; 3]], point: Vec2) -> bool {
+        triangles.iter().any(|&[a, b, c]| point_in_triangle(point, a, b, c))
+    }
+
+    fn square(min: Vec2, max: Vec2) -> Vec<Vec2> {
+        vec![min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)]
+    }
+
+    #[test]
+    fn holes_are_left_out_of_the_triangulation() {
+        let outer = square(Vec2::ZERO, Vec2::splat(10.0));
+        let mut hole = square(Vec2::splat(3.0), Vec2::splat(6.0));
+        hole.reverse();
+
+        let triangles = triangulate_outlines(&[outer, hole]).unwrap();
+
+        assert!((area(&triangles) - (100.0 - 9.0)).abs() < 1e-3);
+        let inside_hole = [Vec2::splat(3.5), Vec2::splat(4.5), Vec2::splat(5.5), Vec2::new(3.5, 5.5), Vec2::new(5.5, 3.5)];
+        for point in inside_hole {
+            assert!(!covers(&triangles, point), "{point} is in the hole");
+        }
+        assert!(covers(&triangles, Vec2::splat(1.0)));
+    }
+
+    #[test]
+    fn concave_outlines_keep_their_notch() {
+        // A "U" 6 wide and 6 tall with a 2 wide, 4 deep notch cut down from the top
+        let outline = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(6.0, 0.0),
+            Vec2::new(6.0, 6.0),
+            Vec2::new(4.0, 6.0),
+            Vec2::new(4.0, 2.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(2.0, 6.0),
+            Vec2::new(0.0, 6.0),
+        ];
+
+        let triangles = triangulate_outlines(&[outline]).unwrap();
+
+        assert!((area(&triangles) - (36.0 - 8.0)).abs() < 1e-3);
+        for point in [Vec2::new(3.0, 2.5), Vec2::new(3.0, 4.0), Vec2::new(3.0, 5.5)] {
+            assert!(!covers(&triangles, point), "{point} is in the notch");
+        }
+        assert!(covers(&triangles, Vec2::new(1.0, 4.0)));
+        assert!(covers(&triangles, Vec2::new(5.0, 4.0)));
+    }
+
+    // A `size` grid with the cells from `min` to `max` (inclusive) solid
+    fn solid_block(size: IVec2, min: IVec2, max: IVec2) -> Vec<bool> {
+        (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| IVec2::new(x, y)))
+            .map(|cell| cell.cmpge(min).all() && cell.cmple(max).all())
+            .collect()
+    }
+
+    // Cell centres in outline space
+    fn centre(cell: IVec2) -> Vec2 {
+        Vec2::new(cell.x as f32, -cell.y as f32)
+    }
+
+    #[test]
+    fn a_block_traces_one_counter_clockwise_outline() {
+        let size = IVec2::splat(8);
+        let solid = solid_block(size, IVec2::splat(2), IVec2::splat(5));
+
+        let outlines = trace_solid_outlines(&solid, size);
+
+        assert_eq!(outlines.len(), 1);
+        let outline = &outlines[0];
+        // Straight runs simplify away, leaving the corners (cut by marching squares)
+        assert!(outline.len() <= 8, "{outline:?}");
+        let area = signed_area(outline);
+        assert!(area > 0.0, "solids should wind counter-clockwise");
+        assert!((15.0..=16.0).contains(&area), "area {area}");
+        for point in outline {
+            assert!((1.5..=5.5).contains(&point.x) && (-5.5..=-1.5).contains(&point.y), "{point} is off the block");
+        }
+        assert!(point_in_polygon(centre(IVec2::new(3, 4)), outline));
+    }
+
+    #[test]
+    fn a_block_with_a_hole_traces_the_hole_the_other_way_round() {
+        let size = IVec2::splat(10);
+        let mut solid = solid_block(size, IVec2::ONE, IVec2::splat(8));
+        for cell in [IVec2::new(4, 4), IVec2::new(5, 4), IVec2::new(4, 5), IVec2::new(5, 5)] {
+            solid[(cell.y * size.x + cell.x) as usize] = false;
+        }
+
+        let outlines = trace_solid_outlines(&solid, size);
+
+        assert_eq!(outlines.len(), 2);
+        let (outer, hole): (Vec<_>, Vec<_>) = outlines.iter().partition(|outline| signed_area(outline) > 0.0);
+        let (outer, hole) = (outer[0], hole[0]);
+        assert!(signed_area(outer) > 60.0);
+        assert!((2.0..=4.5).contains(&-signed_area(hole)), "hole area {}", -signed_area(hole));
+        let in_hole = (centre(IVec2::new(4, 4)) + centre(IVec2::new(5, 5))) / 2.0;
+        assert!(point_in_polygon(in_hole, hole));
+        assert!(!point_in_polygon(centre(IVec2::new(2, 2)), hole));
+
+        let triangles = triangulate_outlines(&outlines).unwrap();
+        assert!(!covers(&triangles, in_hole));
+        assert!(covers(&triangles, centre(IVec2::new(2, 2))));
+    }
+
+    #[test]
+    fn outlines_close_past_the_edge_of_the_grid() {
+        let size = IVec2::splat(CHUNK_SIZE);
+        // A lone corner cell, and a row along the whole bottom edge
+        let mut solid = solid_block(size, IVec2::new(0, CHUNK_SIZE - 1), IVec2::splat(CHUNK_SIZE - 1));
+        solid[0] = true;
+
+        let outlines = trace_solid_outlines(&solid, size);
+
+        assert_eq!(outlines.len(), 2);
+        let corner = outlines.iter().find(|outline| point_in_polygon(centre(IVec2::ZERO), outline)).unwrap();
+        assert!(corner.iter().any(|point| point.x < 0.0) && corner.iter().any(|point| point.y > 0.0));
+        assert!((signed_area(corner) - 0.5).abs() < 1e-3);
+
+        let row = outlines.iter().find(|outline| !std::ptr::eq(*outline, corner)).unwrap();
+        for x in [0, CHUNK_SIZE / 2, CHUNK_SIZE - 1] {
+            assert!(point_in_polygon(centre(IVec2::new(x, CHUNK_SIZE - 1)), row), "cell {x} is outside the row");
+        }
+        let extent = row.iter().fold((f32::MAX, f32::MIN), |(min, max), point| (min.min(point.x), max.max(point.x)));
+        assert_eq!(extent, (-0.5, CHUNK_SIZE as f32 - 0.5));
+    }
+
+    #[test]
+    fn terrain_rects_cover_just_what_changed() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        world.set_atom(5, 5, Atom { atom_type: AtomType::Stone, mass: materials.get(AtomType::Stone).mass, ..default() });
+        let chunk = world.chunks.get_mut(&IVec2::ZERO).unwrap();
+        assert!(chunk.take_terrain_rect().is_some_and(|rect| rect.contains(IVec2::ZERO)), "new chunks start out whole");
+        assert_eq!(chunk.take_terrain_rect(), None);
+
+        world.set_atom(20, 30, Atom::default());
+        world.set_atom(22, 31, Atom { atom_type: AtomType::Stone, ..default() });
+        let rect = world.chunks.get_mut(&IVec2::ZERO).unwrap().take_terrain_rect().unwrap();
+        // Writes wake their neighbours, and woken powder stops counting as terrain
+        assert_eq!((rect.min, rect.max), (IVec2::new(19, 29), IVec2::new(23, 32)));
+
+        // Writing to cells that are already awake only touches the cell itself
+        world.set_atom(20, 30, Atom::default());
+        let rect = world.chunks.get_mut(&IVec2::ZERO).unwrap().take_terrain_rect().unwrap();
+        assert_eq!((rect.min, rect.max), (IVec2::new(20, 30), IVec2::new(20, 30)));
+    }
+
+    fn stone(materials: &MaterialRegistry) -> Atom {
+        Atom { atom_type: AtomType::Stone, mass: materials.get(AtomType::Stone).mass, ..default() }
+    }
+
+    // A bevy world holding a one-chunk atom grid with the cells from `min` to `max`
+    // (inclusive) filled with stone
+    fn world_with_stone(min: IVec2, max: IVec2) -> World {
+        let materials = MaterialRegistry::builtin();
+        let mut grid = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                grid.set_atom(x, y, stone(&materials));
+            }
+        }
+        let mut world = World::new();
+        world.insert_resource(AtomWorldResource(grid));
+        world.insert_resource(MaterialRegistryResource(std::sync::Arc::new(materials)));
+        world.insert_resource(Particles::default());
+        world.insert_resource(Time::<()>::default());
+        world
+    }
+
+    // Lift the cells from `min` to `max` out of the grid into a body, like
+    // detach_rigid_bodies does with a loose piece
+    fn lift(world: &mut World, min: IVec2, max: IVec2) -> Entity {
+        let cells: Vec<IVec2> = (min.y..=max.y).flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y))).collect();
+        world.run_system_once(
+            move |mut commands: Commands, mut grid: ResMut<AtomWorldResource>, materials: Res<MaterialRegistryResource>| {
+                lift_piece(&mut commands, &mut grid.0, &materials.0, &cells);
+            },
+        );
+        let mut bodies = world.query_filtered::<Entity, With<RigidBodyObject>>();
+        bodies.single(world)
+    }
+
+    fn grid(world: &World) -> &AtomWorld {
+        &world.resource::<AtomWorldResource>().0
+    }
+
+    fn is_stone(world: &World, cell: IVec2) -> bool {
+        grid(world).get_atom(cell.x, cell.y).is_some_and(|atom| atom.atom_type == AtomType::Stone)
+    }
+
+    #[test]
+    fn loose_pieces_are_found_and_anchored_ones_are_not() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        // A floating 3x3 block, and a pillar standing on the world's bottom edge
+        for y in 10..13 {
+            for x in 10..13 {
+                world.set_atom(x, y, stone(&materials));
+            }
+        }
+        for y in 40..CHUNK_SIZE {
+            world.set_atom(30, y, stone(&materials));
+        }
+        let bounds = world.cell_bounds();
+        let is_static = |atom: &Atom| atom.atom_type == AtomType::Stone;
+        let no_anchors = HashSet::default();
+
+        let piece = loose_piece(&world, IVec2::new(11, 11), bounds, &is_static, &no_anchors).unwrap();
+        assert_eq!(piece.len(), 9);
+        assert!(loose_piece(&world, IVec2::new(30, 40), bounds, &is_static, &no_anchors).is_err());
+
+        // Terrain already known to be anchored anchors whatever touches it
+        let anchors: HashSet<IVec2> = [IVec2::new(12, 12)].into_iter().collect();
+        assert!(loose_piece(&world, IVec2::new(10, 10), bounds, &is_static, &anchors).is_err());
+    }
+
+    #[test]
+    fn lifted_pieces_leave_the_grid_for_a_body_in_the_same_place() {
+        let (min, max) = (IVec2::new(10, 10), IVec2::new(13, 11));
+        let mut world = world_with_stone(min, max);
+
+        let entity = lift(&mut world, min, max);
+
+        assert!(!(10..=13).any(|x| is_stone(&world, IVec2::new(x, 10)) || is_stone(&world, IVec2::new(x, 11))));
+        let body = world.get::<RigidBodyObject>(entity).unwrap();
+        assert_eq!(body.size, IVec2::new(4, 2));
+        assert_eq!(body.atom_type, AtomType::Stone);
+        assert_eq!(body.iter_atoms().count(), 8);
+        let transform = world.get::<Transform>(entity).unwrap();
+        let space = WorldSpace::of(grid(&world));
+        for (local, _) in body.iter_atoms() {
+            assert_eq!(body_cell(&space, transform, local), min + local);
+        }
+    }
+
+    #[test]
+    fn stamping_and_unstamping_carries_holes_back_to_the_body() {
+        let (min, max) = (IVec2::new(10, 10), IVec2::new(12, 12));
+        let mut world = world_with_stone(min, max);
+        let entity = lift(&mut world, min, max);
+        world.get_mut::<RigidBodyObject>(entity).unwrap().redraw = false;
+
+        world.run_system_once(stamp_rigid_bodies);
+        assert!((10..=12).all(|y| (10..=12).all(|x| is_stone(&world, IVec2::new(x, y)))));
+
+        // Something carves out the middle while the body is stamped
+        world.resource_mut::<AtomWorldResource>().0.set_atom(11, 11, Atom::default());
+        world.run_system_once(unstamp_rigid_bodies);
+
+        assert!((10..=12).all(|y| (10..=12).all(|x| !is_stone(&world, IVec2::new(x, y)))), "atoms left behind");
+        let body = world.get::<RigidBodyObject>(entity).unwrap();
+        assert_eq!(body.iter_atoms().count(), 8);
+        assert_eq!(body.atoms[4].atom_type, AtomType::Empty);
+        assert!(body.redraw);
+    }
+
+    #[test]
+    fn resting_bodies_settle_back_into_the_grid() {
+        let (min, max) = (IVec2::new(10, 10), IVec2::new(12, 12));
+        let mut world = world_with_stone(min, max);
+        let entity = lift(&mut world, min, max);
+        let materials = MaterialRegistry::builtin();
+        {
+            let grid = &mut world.resource_mut::<AtomWorldResource>().0;
+            // Gas in one of its cells gets pushed out, water in another keeps its cell
+            grid.set_atom(10, 10, Atom { atom_type: AtomType::Smoke, mass: materials.get(AtomType::Smoke).mass, ..default() });
+            grid.set_atom(12, 12, Atom { atom_type: AtomType::Water, mass: materials.get(AtomType::Water).mass, ..default() });
+        }
+
+        // Still moving, so it stays a body
+        world.entity_mut(entity).insert(Velocity::linear(Vec2::X * REST_SPEED * 2.0));
+        world.get_mut::<RigidBodyObject>(entity).unwrap().resting = REST_TIME;
+        world.run_system_once(settle_rigid_bodies);
+        assert!(world.get_entity(entity).is_some());
+
+        world.entity_mut(entity).insert(Velocity::zero());
+        world.get_mut::<RigidBodyObject>(entity).unwrap().resting = REST_TIME;
+        world.run_system_once(settle_rigid_bodies);
+
+        assert!(world.get_entity(entity).is_none());
+        assert!(is_stone(&world, IVec2::new(10, 10)), "the gas kept the body out");
+        let stone_cells = grid(&world).iter_atoms().filter(|(_, atom)| atom.atom_type == AtomType::Stone).count();
+        assert_eq!(stone_cells, 9, "the stone that landed on water goes to the nearest empty cell");
+        let kept = |atom_type| grid(&world).iter_atoms().filter(|(_, atom)| atom.atom_type == atom_type).count();
+        assert_eq!((kept(AtomType::Smoke), kept(AtomType::Water)), (1, 1));
+        assert_eq!(grid(&world).get_atom(12, 12).unwrap().atom_type, AtomType::Water);
+    }
+
+    // Velocity a still body picks up from the water in one fixed step. The body's
+    // bitmap is `rows` (a '#' per atom) with its top left cell at `min`, in a
+    // world whose water surface is at y = 20.
+    fn fluid_push(rows: &[&str], density: f32, min: IVec2) -> Vec2 {
+        let materials = MaterialRegistry::builtin();
+        let water = Atom { atom_type: AtomType::Water, mass: materials.get(AtomType::Water).mass, ..default() };
+        let mut grid = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        for y in 20..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                grid.set_atom(x, y, water.clone());
+            }
+        }
+        let size = IVec2::new(rows[0].len() as i32, rows.len() as i32);
+        let atoms = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|c| if c == '#' { stone(&materials) } else { Atom::default() })
+            .collect();
+        let origin = WorldSpace::of(&grid).grid_to_world(min);
+
+        let mut world = World::new();
+        world.insert_resource(AtomWorldResource(grid));
+        world.insert_resource(MaterialRegistryResource(std::sync::Arc::new(materials)));
+        world.insert_resource(RapierConfiguration::new(1.0));
+        let mut time = Time::<()>::default();
+        time.advance_by(std::time::Duration::from_secs_f32(SIMULATION_DT));
+        world.insert_resource(time);
+        let entity = world
+            .spawn((
+                RigidBody::Dynamic,
+                Collider::cuboid(size.x as f32 / 2.0, size.y as f32 / 2.0),
+                ColliderMassProperties::Density(density),
+                Velocity::zero(),
+                Transform::from_translation(origin.extend(0.0)),
+                RigidBodyObject::new(size, atoms),
+            ))
+            .id();
+
+        world.run_system_once(atoms_push_rigid_bodies);
+        world.get::<Velocity>(entity).unwrap().linvel
+    }
+
+    const BLOCK: [&str; 4] = ["####", "####", "####", "####"];
+
+    #[test]
+    fn denser_bodies_sink_and_lighter_ones_float() {
+        let gravity = RapierConfiguration::new(1.0).gravity * SIMULATION_DT;
+        let deep = IVec2::new(10, 30);
+
+        // Rapier adds gravity on top of what the water does
+        let stone = fluid_push(&BLOCK, 2.5, deep) + gravity;
+        let wood = fluid_push(&BLOCK, 0.5, deep) + gravity;
+
+        assert!(stone.y < 0.0, "stone floats: {stone}");
+        assert!(wood.y > 0.0, "wood sinks: {wood}");
+        assert!(stone.x.abs() < 1e-4 && wood.x.abs() < 1e-4);
+    }
+
+    #[test]
+    fn only_the_atoms_under_the_surface_displace_water() {
+        // Half under the surface, all of it under, and a body whose bitmap is half
+        // empty, with just its atoms under
+        let half = fluid_push(&BLOCK, 1.0, IVec2::new(10, 18));
+        let full = fluid_push(&BLOCK, 1.0, IVec2::new(10, 30));
+        let hollow = fluid_push(&["....", "....", "####", "####"], 1.0, IVec2::new(10, 18));
+
+        assert!(half.y > 0.0 && half.y < full.y, "half {half}, full {full}");
+        assert!((hollow.y - full.y).abs() < 1e-4, "hollow {hollow}, full {full}");
     }
 }
@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+// Deterministic randomness for the simulation, modelled on the DeterministicRandom
+// from the "Nondeterminism And You" example. Everything the simulation rolls goes
+// through here, so the same seed and inputs always give the same grid. The whole
+// state is a single u64, which makes it cheap to snapshot for replays or to send
+// to lockstep peers.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeterministicRandom {
+    state: u64,
+}
+
+impl DeterministicRandom {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // SplitMix64 rather than the example's LCG, so every output bit is usable
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn next_bool(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+
+    // Uniform in [min, max]
+    pub fn range_i32(&mut self, min: i32, max: i32) -> i32 {
+        let range = (max as i64 - min as i64 + 1) as u64;
+        (min as i64 + (self.next_u64() % range) as i64) as i32
+    }
+
+    // Independent generator for `stream`, derived without advancing this one
+    pub fn fork(&self, stream: u64) -> Self {
+        let mut forked = Self::new(self.state ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03));
+        forked.next_u64();
+        forked
+    }
+}
+
+// Stateless value in [0, 1) for a cell, the same every time for the same cell and
+// seed. For looks (colour variants, flicker) that mustn't draw from, and so
+// disturb, the simulation's generator.
+pub fn cell_noise(cell: IVec2, seed: u64) -> f32 {
+    DeterministicRandom::new(seed)
+        .fork(((cell.x as u32 as u64) << 32) | cell.y as u32 as u64)
+        .next_f32()
+}
+
+// Lets the generator stand in anywhere `rand::Rng` is used (`gen`, `gen_bool`, ...)
+impl RngCore for DeterministicRandom {
+    fn next_u32(&mut self) -> u32 {
+        DeterministicRandom::next_u32(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        DeterministicRandom::next_u64(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// Saved generator state, see `SimulationRng::snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RngSnapshot {
+    pub seed: u64,
+    pub rng: DeterministicRandom,
+}
+
+// The simulation's source of randomness. The atom world draws one seed from it
+// per step and derives every chunk's generator from that, so results don't depend
+// on how chunks are scheduled across threads.
+#[derive(Resource, Debug, Clone)]
+pub struct SimulationRng {
+    seed: u64,
+    rng: DeterministicRandom,
+}
+
+impl SimulationRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: DeterministicRandom::new(seed),
+        }
+    }
+
+    // Random seed for normal play. Log `seed()` to reproduce a run later.
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        *self = Self::new(seed);
+    }
+
+    pub fn rng(&mut self) -> &mut DeterministicRandom {
+        &mut self.rng
+    }
+
+    pub fn snapshot(&self) -> RngSnapshot {
+        RngSnapshot {
+            seed: self.seed,
+            rng: self.rng,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &RngSnapshot) {
+        self.seed = snapshot.seed;
+        self.rng = snapshot.rng;
+    }
+}
+
+impl Default for SimulationRng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
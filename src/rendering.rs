@@ -1,94 +1,589 @@
 use bevy::prelude::*;
-use crate::atoms::{AtomWorldResource, AtomType};
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::texture::ImageSampler;
+use bevy::render::view::RenderLayers;
+use bevy::sprite::Anchor;
+use bevy::utils::HashMap;
+use crate::atoms::{Atom, AtomWorld, AtomWorldResource, AtomType, Chunk, DirtyRect, CHUNK_SIZE, GLOW_FULL_TEMPERATURE, GLOW_TEMPERATURE};
+use crate::lighting::LightMap;
+use crate::materials::{Behaviour, MaterialRegistry, MaterialRegistryResource, Phase};
+use crate::random::cell_noise;
+use crate::particles::{Particle, Particles};
+use crate::physics::RigidBodyObject;
+use crate::world_space::WorldSpace;
 
-// Pixel-perfect camera setup as described in "Pixel Perfect Rendering" blog post
-pub fn setup_pixel_camera(mut commands: Commands) {
-    commands.spawn(Camera2dBundle {
-        transform: Transform::from_xyz(0.0, 0.0, 1000.0),
-        ..default()
-    });
+// The grid is drawn as one texture per chunk, each on a single quad. Textures are
+// kept between frames and only the cells that changed since the last frame are
+// rewritten, so a quiet world costs next to nothing to draw.
+#[derive(Component)]
+pub struct ChunkTexture {
+    pub position: IVec2,
+}
+
+// How much of its colour a soaked powder or solid loses
+const WET_DARKENING: f32 = 0.35;
+
+// How an atom looks: its material's colour variant, lit up by heat, darkened when
+// wet, and flickering between flame shades while it burns (flames always do).
+// `flicker` is a random value in [0, 1) that changes from step to step.
+pub fn atom_color(materials: &MaterialRegistry, atom: &Atom, wet: bool, flicker: f32) -> Color {
+    let variant = atom.color_variant.unwrap_or(0);
+    let material = materials.get(atom.atom_type);
+    let mut color = material.variant_color(variant).to_srgba();
+
+    if wet {
+        color = color.mix(&Srgba::new(0.0, 0.0, 0.0, color.alpha), WET_DARKENING);
+    }
+
+    // Dull red through orange to pale yellow, like hot metal
+    let heat = (atom.temperature - GLOW_TEMPERATURE) / (GLOW_FULL_TEMPERATURE - GLOW_TEMPERATURE);
+    if heat > 0.0 {
+        let heat = heat.min(1.0);
+        let glow = if heat < 0.5 {
+            Srgba::new(0.6, 0.05, 0.0, 1.0).mix(&Srgba::new(1.0, 0.45, 0.05, 1.0), heat * 2.0)
+        } else {
+            Srgba::new(1.0, 0.45, 0.05, 1.0).mix(&Srgba::new(1.0, 0.95, 0.6, 1.0), heat * 2.0 - 1.0)
+        };
+        color = color.mix(&glow.with_alpha(color.alpha.max(heat)), heat * 0.8);
+    }
+
+    if atom.burning || material.behaviour == Behaviour::Fire {
+        let flame = materials.get(AtomType::Fire).variant_color((flicker * 256.0) as u8).to_srgba();
+        let brightness = 0.75 + flicker * 0.5;
+        color = Srgba::new(
+            flame.red * brightness,
+            flame.green * brightness,
+            flame.blue * brightness,
+            1.0,
+        );
+    }
+
+    color.into()
+}
+
+// Whether a powder or solid at `cell` is soaking in a liquid. Hot liquids like
+// lava burn things rather than wet them.
+fn is_wet(world: &AtomWorld, materials: &MaterialRegistry, cell: IVec2, atom: &Atom) -> bool {
+    if materials.get(atom.atom_type).is_fluid() {
+        return false;
+    }
+    [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y].into_iter().any(|offset| {
+        let neighbour = cell + offset;
+        world.get_atom(neighbour.x, neighbour.y).map_or(false, |neighbour| {
+            let material = materials.get(neighbour.atom_type);
+            material.phase == Phase::Liquid && material.heat_source.is_none()
+        })
+    })
+}
+
+// Colour under `light`. Light above 1 brightens past the atom's own colour.
+pub fn lit_color(color: Color, light: Vec3) -> Color {
+    let color = color.to_srgba();
+    Srgba::new(
+        (color.red * light.x).min(1.0),
+        (color.green * light.y).min(1.0),
+        (color.blue * light.z).min(1.0),
+        color.alpha,
+    )
+    .into()
+}
+
+// Pixel for the atom at `cell`. Empty cells show the background through a veil
+// of darkness, as thick as the light there is thin.
+pub fn atom_pixel(world: &AtomWorld, materials: &MaterialRegistry, light_map: &LightMap, cell: IVec2, atom: &Atom) -> [u8; 4] {
+    let light = light_map.light_at(cell);
+    if atom.atom_type == AtomType::Empty {
+        let darkness = 1.0 - light.max_element().min(1.0);
+        return [0, 0, 0, (darkness * 255.0) as u8];
+    }
+    let wet = is_wet(world, materials, cell, atom);
+    let color = atom_color(materials, atom, wet, cell_noise(cell, world.step));
+    lit_color(color, light).to_srgba().to_u8_array()
+}
+
+// A blank CHUNK_SIZE x CHUNK_SIZE texture, sampled nearest-neighbour so atoms
+// stay crisp squares however far the camera zooms in
+pub fn chunk_image() -> Image {
+    atom_image(UVec2::splat(CHUNK_SIZE as u32))
+}
+
+// A blank texture with one transparent pixel per atom
+fn atom_image(size: UVec2) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+// Rewrite the pixels of `rect` (local, inclusive) from the chunk's atoms. Image
+// rows run top to bottom like grid rows do, so local cells map straight to pixels.
+pub fn write_chunk_pixels(
+    world: &AtomWorld,
+    materials: &MaterialRegistry,
+    light_map: &LightMap,
+    chunk: &Chunk,
+    rect: DirtyRect,
+    data: &mut [u8],
+) {
+    for y in rect.min.y..=rect.max.y {
+        for x in rect.min.x..=rect.max.x {
+            let local = IVec2::new(x, y);
+            let index = Chunk::local_index(local);
+            let pixel = atom_pixel(world, materials, light_map, chunk.origin() + local, &chunk.atoms[index]);
+            data[index * 4..index * 4 + 4].copy_from_slice(&pixel);
+        }
+    }
+}
+
+// World position of a chunk quad's centre
+fn chunk_translation(space: &WorldSpace, chunk: &Chunk) -> Vec3 {
+    // Cells are centred on their grid position, so the chunk spans half a cell
+    // either side of its first and last cell
+    let center = chunk.origin().as_vec2() + Vec2::splat(CHUNK_SIZE as f32 / 2.0 - 0.5);
+    space.grid_point_to_world(center).extend(0.0)
 }
 
-// System to render atoms as sprites
+// System to upload changed cells into the chunk textures
 pub fn render_atoms(
     mut commands: Commands,
-    mut atom_entities: Local<Vec<Entity>>,
-    world: Res<AtomWorldResource>,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut textures: Local<HashMap<IVec2, (Entity, Handle<Image>)>>,
+    mut world: ResMut<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    light_map: Res<LightMap>,
+    mut images: ResMut<Assets<Image>>,
 ) {
-    // Clear previous atom entities
-    for entity in atom_entities.drain(..) {
-        commands.entity(entity).despawn();
-    }
-
-    // Spawn new sprites for each atom
-    for y in 0..world.0.height {
-        for x in 0..world.0.width {
-            if let Some(atom) = world.0.get_atom(x as i32, y as i32) {
-                if atom.atom_type != AtomType::Empty {
-                    let entity = commands.spawn(SpriteBundle {
-                        sprite: Sprite {
-                            color: atom.atom_type.color(),
-                            custom_size: Some(Vec2::new(1.0, 1.0)),
-                            ..default()
-                        },
-                        transform: Transform::from_xyz(
-                            x as f32 - (world.0.width as f32 / 2.0),
-                            y as f32 - (world.0.height as f32 / 2.0),
-                            0.0,
-                        ),
+    let world = &mut world.0;
+    let space = WorldSpace::of(world);
+
+    // Chunks can go away when a new level replaces the world
+    textures.retain(|position, (entity, _)| {
+        let keep = world.chunks.contains_key(position);
+        if !keep {
+            commands.entity(*entity).despawn();
+        }
+        keep
+    });
+
+    // Looks depend on neighbouring cells, so collect the work first and draw from
+    // the finished grid
+    let changed: Vec<(IVec2, DirtyRect)> = world
+        .chunks
+        .iter_mut()
+        .filter_map(|(position, chunk)| chunk.take_changed_rect().map(|rect| (*position, rect)))
+        .collect();
+
+    for (position, rect) in changed {
+        let chunk = &world.chunks[&position];
+
+        let (entity, handle) = textures.entry(position).or_insert_with(|| {
+            let handle = images.add(chunk_image());
+            let entity = commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::splat(CHUNK_SIZE as f32)),
                         ..default()
-                    }).id();
-                    atom_entities.push(entity);
-                }
+                    },
+                    texture: handle.clone(),
+                    ..default()
+                },
+                ChunkTexture { position },
+            )).id();
+            (entity, handle)
+        });
+
+        if let Some(image) = images.get_mut(handle) {
+            write_chunk_pixels(world, &materials.0, &light_map, chunk, rect, &mut image.data);
+        }
+        // A replacement world may be a different size, which moves every chunk
+        commands.entity(*entity).insert(Transform::from_translation(chunk_translation(&space, chunk)));
+    }
+}
+
+// Rewrite a body's texture from its bitmap. Rows run top to bottom like the
+// bitmap's do; empty cells are left see-through.
+fn write_body_pixels(materials: &MaterialRegistry, body: &RigidBodyObject, data: &mut [u8]) {
+    for (index, atom) in body.atoms.iter().enumerate() {
+        let local = IVec2::new(index as i32 % body.size.x, index as i32 / body.size.x);
+        let pixel = match atom.atom_type {
+            AtomType::Empty => [0; 4],
+            _ => atom_color(materials, atom, false, cell_noise(local, 0)).to_srgba().to_u8_array(),
+        };
+        data[index * 4..index * 4 + 4].copy_from_slice(&pixel);
+    }
+}
+
+// Rigid bodies are drawn like chunks, as one sprite showing their bitmap that
+// moves and turns with the body. The texture is only redrawn when the body loses
+// atoms; light is a tint taken from wherever its middle is.
+pub fn render_rigid_bodies(
+    mut commands: Commands,
+    world: Res<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    light_map: Res<LightMap>,
+    mut images: ResMut<Assets<Image>>,
+    mut bodies: Query<(Entity, &mut RigidBodyObject, &Transform)>,
+    mut sprites: Query<(&Handle<Image>, &mut Sprite)>,
+) {
+    let space = WorldSpace::of(&world.0);
+    for (entity, mut body, transform) in bodies.iter_mut() {
+        let center = transform.transform_point(RigidBodyObject::local_position(body.size / 2).extend(0.0));
+        let color = lit_color(Color::WHITE, light_map.light_at(space.world_to_grid(center.truncate())));
+
+        let Ok((texture, mut sprite)) = sprites.get_mut(entity) else {
+            let mut image = atom_image(body.size.as_uvec2());
+            write_body_pixels(&materials.0, &body, &mut image.data);
+            body.redraw = false;
+
+            // The body's origin is the centre of its bitmap's first cell
+            let size = body.size.as_vec2();
+            let anchor = Vec2::new(-0.5 + 0.5 / size.x, 0.5 - 0.5 / size.y);
+            commands.entity(entity).insert((
+                Sprite {
+                    color,
+                    custom_size: Some(size),
+                    anchor: Anchor::Custom(anchor),
+                    ..default()
+                },
+                images.add(image),
+                VisibilityBundle::default(),
+            ));
+            continue;
+        };
+
+        sprite.color = color;
+        if body.redraw {
+            if let Some(image) = images.get_mut(texture) {
+                write_body_pixels(&materials.0, &body, &mut image.data);
             }
+            body.redraw = false;
         }
     }
 }
 
-// Pixel-perfect camera controller
+// Marks the sprites render_particles draws particles with
+#[derive(Component)]
+pub struct ParticleSprite;
+
+// Particles draw at their exact position, between grid cells while flying. The
+// sprites are kept from frame to frame; spare ones are hidden until needed again.
+pub fn render_particles(
+    mut commands: Commands,
+    world: Res<AtomWorldResource>,
+    materials: Res<MaterialRegistryResource>,
+    light_map: Res<LightMap>,
+    particles: Res<Particles>,
+    mut sprites: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<ParticleSprite>>,
+) {
+    let space = WorldSpace::of(&world.0);
+    let color = |particle: &Particle| {
+        lit_color(
+            atom_color(&materials.0, &particle.atom, false, 0.5),
+            light_map.light_at(particle.position.round().as_ivec2()),
+        )
+    };
+    let translation = |particle: &Particle| space.grid_point_to_world(particle.position).extend(0.0);
+
+    let mut particles = particles.iter();
+    for (mut sprite, mut transform, mut visibility) in sprites.iter_mut() {
+        match particles.next() {
+            Some(particle) => {
+                sprite.color = color(particle);
+                transform.translation = translation(particle);
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+
+    // More particles in flight than ever before
+    for particle in particles {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: color(particle),
+                    custom_size: Some(Vec2::new(1.0, 1.0)),
+                    ..default()
+                },
+                transform: Transform::from_translation(translation(particle)),
+                ..default()
+            },
+            ParticleSprite,
+        ));
+    }
+}
+
+// Pixel-perfect camera as described in the "Pixel Perfect Rendering" blog post.
+// The world is drawn into a small canvas texture at a fixed internal resolution,
+// one pixel per atom, and that canvas is shown in the window at a whole-number
+// scale with black bars around it. Pixels never get squashed or smeared.
+//
+// The game camera only ever moves in whole canvas pixels, otherwise atoms would
+// shimmer as it slides. What's left over is made up for by sliding the canvas
+// itself in screen pixels, which are much finer, so following the player still
+// looks smooth.
+
+// Render layer of things drawn straight to the window rather than into the canvas
+pub const SCREEN_LAYER: usize = 1;
+
+// Spare canvas pixels around the visible area, room for the subpixel slide
+const CANVAS_MARGIN: u32 = 1;
+
+#[derive(Resource, Clone)]
+pub struct PixelCameraSettings {
+    // Internal resolution in canvas pixels
+    pub resolution: UVec2,
+    // Fixed upscale, or None for the largest whole number that fits the window
+    pub scale: Option<u32>,
+}
+
+impl Default for PixelCameraSettings {
+    fn default() -> Self {
+        Self {
+            resolution: UVec2::new(200, 150),
+            scale: None,
+        }
+    }
+}
+
+// The game camera, drawing the world into the canvas
 #[derive(Component)]
 pub struct PixelCamera {
     pub pixels_per_unit: f32,
+    // Where the camera really is. Its transform is this snapped to whole pixels.
+    pub position: Vec2,
+    // Nudge on top of `position` for the current frame, like camera shake
+    pub offset: Vec2,
+    // Window layout worked out by `pixel_perfect_camera`, for converting between
+    // window and canvas positions
+    pub scale: u32,
+    pub window_size: Vec2,
+    resolution: Vec2,
+    // Canvas pixel shown at the top left of the visible area
+    view_min: Vec2,
 }
 
-pub fn pixel_perfect_camera(
-    mut query: Query<(&mut Transform, &PixelCamera)>,
-    windows: Query<&Window>,
-) {
-    let window = windows.single();
-    let (mut transform, camera) = query.single_mut();
+impl PixelCamera {
+    pub fn new(pixels_per_unit: f32, resolution: UVec2) -> Self {
+        Self {
+            pixels_per_unit,
+            position: Vec2::ZERO,
+            offset: Vec2::ZERO,
+            scale: 1,
+            window_size: resolution.as_vec2(),
+            resolution: resolution.as_vec2(),
+            view_min: Vec2::splat(CANVAS_MARGIN as f32),
+        }
+    }
+
+    // Window position (logical pixels, y down) to canvas position
+    pub fn screen_to_canvas(&self, screen: Vec2) -> Vec2 {
+        (screen - self.window_size / 2.0) / self.scale as f32 + self.resolution / 2.0 + self.view_min
+    }
 
-    // Ensure camera position is pixel-aligned
-    let pixel_size = 1.0 / camera.pixels_per_unit;
-    transform.translation.x = (transform.translation.x / pixel_size).round() * pixel_size;
-    transform.translation.y = (transform.translation.y / pixel_size).round() * pixel_size;
+    pub fn canvas_to_screen(&self, canvas: Vec2) -> Vec2 {
+        (canvas - self.view_min - self.resolution / 2.0) * self.scale as f32 + self.window_size / 2.0
+    }
 
-    // Adjust projection to be pixel-perfect
-    // This ensures sprites align to pixel boundaries
+    // Largest whole-number scale at which the canvas fits the window
+    pub fn fit_scale(resolution: UVec2, window_size: Vec2) -> u32 {
+        let fit = (window_size / resolution.as_vec2()).floor();
+        (fit.x.min(fit.y) as u32).max(1)
+    }
 }
 
-// Smoothing filter for pixel art as mentioned in the blog
-pub fn apply_pixel_art_filter(
-    mut materials: ResMut<Assets<ColorMaterial>>,
+// The sprite showing the canvas in the window
+#[derive(Component)]
+pub struct PixelCanvas;
+
+// Camera that draws the canvas and screen overlays to the window
+#[derive(Component)]
+pub struct ScreenCamera;
+
+pub fn setup_pixel_camera(
+    mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
+    settings: Res<PixelCameraSettings>,
 ) {
-    // This would implement the pixel art smoothing filter
-    // For now, we'll keep it simple
+    let canvas_size = settings.resolution + UVec2::splat(CANVAS_MARGIN * 2);
+    let size = Extent3d {
+        width: canvas_size.x,
+        height: canvas_size.y,
+        depth_or_array_layers: 1,
+    };
+    let mut canvas = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("pixel_canvas"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        sampler: ImageSampler::nearest(),
+        ..default()
+    };
+    canvas.resize(size);
+    let canvas = images.add(canvas);
+
+    // Atoms are one canvas pixel each
+    let pixels_per_unit = 1.0;
+    let mut game_camera = Camera2dBundle::default();
+    game_camera.camera.order = -1;
+    game_camera.camera.target = RenderTarget::Image(canvas.clone());
+    game_camera.projection.scale = 1.0 / pixels_per_unit;
+    commands.spawn((game_camera, PixelCamera::new(pixels_per_unit, settings.resolution)));
+
+    commands.spawn((
+        SpriteBundle {
+            texture: canvas,
+            ..default()
+        },
+        PixelCanvas,
+        RenderLayers::layer(SCREEN_LAYER),
+    ));
+
+    let mut screen_camera = Camera2dBundle::default();
+    screen_camera.camera.clear_color = ClearColorConfig::Custom(Color::BLACK);
+    commands.spawn((screen_camera, ScreenCamera, RenderLayers::layer(SCREEN_LAYER)));
+}
+
+// System to snap the game camera to whole canvas pixels, and scale and slide the
+// canvas in the window to match
+pub fn pixel_perfect_camera(
+    settings: Res<PixelCameraSettings>,
+    windows: Query<&Window>,
+    mut cameras: Query<(&mut Transform, &mut PixelCamera, &mut OrthographicProjection)>,
+    mut canvases: Query<&mut Sprite, With<PixelCanvas>>,
+) {
+    let Some(window) = windows.iter().next() else {
+        return;
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let Ok((mut transform, mut camera, mut projection)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    let scale = settings
+        .scale
+        .unwrap_or_else(|| PixelCamera::fit_scale(settings.resolution, window_size));
+    let pixels = (camera.position + camera.offset) * camera.pixels_per_unit;
+    let snapped = pixels.round();
+    transform.translation = (snapped / camera.pixels_per_unit).extend(transform.translation.z);
+    projection.scale = 1.0 / camera.pixels_per_unit;
+
+    // The camera is short of where it should be by less than a canvas pixel; show
+    // the canvas from that much further along instead (canvas y runs down)
+    let remainder = pixels - snapped;
+    let view_min = Vec2::splat(CANVAS_MARGIN as f32) + Vec2::new(remainder.x, -remainder.y);
+    camera.scale = scale;
+    camera.window_size = window_size;
+    camera.resolution = settings.resolution.as_vec2();
+    camera.view_min = view_min;
+
+    for mut sprite in canvases.iter_mut() {
+        sprite.custom_size = Some(settings.resolution.as_vec2() * scale as f32);
+        sprite.rect = Some(Rect::from_corners(view_min, view_min + settings.resolution.as_vec2()));
+    }
 }
 
 // Camera controller for following player
 pub fn camera_follow_player(
-    mut camera_query: Query<&mut Transform, With<Camera>>,
-    player_query: Query<&Transform, (With<crate::game::Player>, Without<Camera>)>,
+    mut camera_query: Query<&mut PixelCamera>,
+    player_query: Query<&Transform, With<crate::game::Player>>,
 ) {
-    if let (Ok(mut camera_transform), Ok(player_transform)) = (camera_query.get_single_mut(), player_query.get_single()) {
-        let target = player_transform.translation;
-        let current = camera_transform.translation;
+    if let (Ok(mut camera), Ok(player_transform)) = (camera_query.get_single_mut(), player_query.get_single()) {
+        let target = player_transform.translation.truncate();
+        let current = camera.position;
 
         // Smooth camera follow
         let lerp_factor = 0.1;
-        camera_transform.translation = current.lerp(target, lerp_factor);
+        camera.position = current.lerp(target, lerp_factor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_light() -> LightMap {
+        let mut light_map = LightMap::default();
+        light_map.ambient = Vec3::ONE;
+        light_map
+    }
+
+    fn stone(materials: &MaterialRegistry, variant: u8) -> Atom {
+        Atom {
+            atom_type: AtomType::Stone,
+            mass: materials.get(AtomType::Stone).mass,
+            temperature: 20.0,
+            color_variant: Some(variant),
+            ..default()
+        }
+    }
+
+    fn texel(data: &[u8], local: IVec2) -> [u8; 4] {
+        let index = Chunk::local_index(local) * 4;
+        data[index..index + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn atoms_are_written_to_their_texel() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        let cell = IVec2::new(5, 9);
+        world.set_atom(cell.x, cell.y, stone(&materials, 3));
+
+        let mut image = chunk_image();
+        assert_eq!(image.data.len(), (CHUNK_SIZE * CHUNK_SIZE * 4) as usize);
+        assert!(image.data.iter().all(|&byte| byte == 0));
+
+        let chunk = &world.chunks[&IVec2::ZERO];
+        write_chunk_pixels(&world, &materials, &full_light(), chunk, DirtyRect::new(cell), &mut image.data);
+
+        let expected = materials.get(AtomType::Stone).variant_color(3).to_srgba().to_u8_array();
+        assert_eq!(texel(&image.data, cell), expected);
+        assert_eq!(texel(&image.data, cell + IVec2::X), [0; 4]);
+    }
+
+    #[test]
+    fn rewrites_stay_inside_their_rect() {
+        let materials = MaterialRegistry::builtin();
+        let mut world = AtomWorld::new(CHUNK_SIZE as usize, CHUNK_SIZE as usize);
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                world.set_atom(x, y, stone(&materials, 0));
+            }
+        }
+
+        let mut image = chunk_image();
+        let untouched = [1, 2, 3, 4];
+        for pixel in image.data.chunks_mut(4) {
+            pixel.copy_from_slice(&untouched);
+        }
+        let rect = DirtyRect {
+            min: IVec2::new(2, 2),
+            max: IVec2::new(4, 6),
+        };
+        write_chunk_pixels(&world, &materials, &full_light(), &world.chunks[&IVec2::ZERO], rect, &mut image.data);
+
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let local = IVec2::new(x, y);
+                assert_eq!(texel(&image.data, local) == untouched, !rect.contains(local), "texel {local}");
+            }
+        }
     }
 }
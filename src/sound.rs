@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use bevy::prelude::*;
 use crate::atoms::{AtomType, AtomWorldResource};
+use crate::explosions::ExplosionEvent;
+use crate::world_space::WorldSpace;
 
 // Simple sound system inspired by "Effecting Sound" and "Affecting Sound" blog posts
 // Using a simplified FMOD-like approach for atomic reactions
@@ -135,6 +137,7 @@ impl Default for SoundManager {
 pub fn monitor_atomic_reactions(
     world: Res<AtomWorldResource>,
     mut sound_manager: ResMut<SoundManager>,
+    mut explosions: EventReader<ExplosionEvent>,
     time: Res<Time>,
 ) {
     if !sound_manager.sounds_enabled {
@@ -153,7 +156,7 @@ pub fn monitor_atomic_reactions(
     check_water_reactions(&world.0, &mut sound_manager, current_time);
 
     // Check for explosions
-    check_explosion_reactions(&world.0, &mut sound_manager, current_time);
+    check_explosion_reactions(explosions.read(), WorldSpace::of(&world.0), &mut sound_manager, current_time);
 
     // Update ambient sounds
     update_ambient_sounds(&world.0, &mut sound_manager);
@@ -163,15 +166,11 @@ fn check_fire_reactions(world: &crate::atoms::AtomWorld, sound_manager: &mut Sou
     let mut fire_positions = Vec::new();
     let mut fire_count = 0;
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            if let Some(atom) = world.get_atom(x as i32, y as i32) {
-                if atom.atom_type == AtomType::Fire {
-                    fire_count += 1;
-                    if fire_count % 50 == 0 { // Sample every 50 fire atoms
-                        fire_positions.push(Vec2::new(x as f32, y as f32));
-                    }
-                }
+    for (pos, atom) in world.iter_atoms() {
+        if atom.atom_type == AtomType::Fire {
+            fire_count += 1;
+            if fire_count % 50 == 0 { // Sample every 50 fire atoms
+                fire_positions.push(pos.as_vec2());
             }
         }
     }
@@ -189,15 +188,11 @@ fn check_acid_reactions(world: &crate::atoms::AtomWorld, sound_manager: &mut Sou
     let mut acid_positions = Vec::new();
     let mut acid_count = 0;
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            if let Some(atom) = world.get_atom(x as i32, y as i32) {
-                if atom.atom_type == AtomType::Acid {
-                    acid_count += 1;
-                    if acid_count % 30 == 0 {
-                        acid_positions.push(Vec2::new(x as f32, y as f32));
-                    }
-                }
+    for (pos, atom) in world.iter_atoms() {
+        if atom.atom_type == AtomType::Acid {
+            acid_count += 1;
+            if acid_count % 30 == 0 {
+                acid_positions.push(pos.as_vec2());
             }
         }
     }
@@ -214,15 +209,11 @@ fn check_water_reactions(world: &crate::atoms::AtomWorld, sound_manager: &mut So
     let mut water_positions = Vec::new();
     let mut moving_water_count = 0;
 
-    for y in 0..world.height {
-        for x in 0..world.width {
-            if let Some(atom) = world.get_atom(x as i32, y as i32) {
-                if atom.atom_type == AtomType::Water && atom.velocity.length_squared() > 1.0 {
-                    moving_water_count += 1;
-                    if moving_water_count % 20 == 0 {
-                        water_positions.push(Vec2::new(x as f32, y as f32));
-                    }
-                }
+    for (pos, atom) in world.iter_atoms() {
+        if atom.atom_type == AtomType::Water && atom.velocity.length_squared() > 1.0 {
+            moving_water_count += 1;
+            if moving_water_count % 20 == 0 {
+                water_positions.push(pos.as_vec2());
             }
         }
     }
@@ -235,9 +226,17 @@ fn check_water_reactions(world: &crate::atoms::AtomWorld, sound_manager: &mut So
     }
 }
 
-fn check_explosion_reactions(world: &crate::atoms::AtomWorld, sound_manager: &mut SoundManager, current_time: f64) {
-    // Check for rapid expansion of gas/fire atoms (explosion-like behavior)
-    // This is a simplified detection
+fn check_explosion_reactions<'a>(
+    explosions: impl Iterator<Item = &'a ExplosionEvent>,
+    space: WorldSpace,
+    sound_manager: &mut SoundManager,
+    current_time: f64,
+) {
+    // Explosion events are in world space; the other reaction sounds are placed on the grid
+    for explosion in explosions {
+        let position = space.world_to_grid_point(explosion.position);
+        trigger_reaction_sound(sound_manager, ReactionType::Explosion, position, current_time);
+    }
 }
 
 fn update_ambient_sounds(world: &crate::atoms::AtomWorld, sound_manager: &mut SoundManager) {
@@ -245,15 +244,16 @@ fn update_ambient_sounds(world: &crate::atoms::AtomWorld, sound_manager: &mut So
 
     // Scan for areas with concentrated atom types
     let scan_step = 20; // Sample every 20 pixels
+    let bounds = world.cell_bounds();
 
-    for base_y in (0..world.height).step_by(scan_step) {
-        for base_x in (0..world.width).step_by(scan_step) {
+    for base_y in (bounds.min.y..bounds.max.y).step_by(scan_step as usize) {
+        for base_x in (bounds.min.x..bounds.max.x).step_by(scan_step as usize) {
             let mut atom_counts = std::collections::HashMap::new();
 
             // Count atoms in this region
-            for y in base_y..(base_y + scan_step).min(world.height) {
-                for x in base_x..(base_x + scan_step).min(world.width) {
-                    if let Some(atom) = world.get_atom(x as i32, y as i32) {
+            for y in base_y..(base_y + scan_step).min(bounds.max.y) {
+                for x in base_x..(base_x + scan_step).min(bounds.max.x) {
+                    if let Some(atom) = world.get_atom(x, y) {
                         *atom_counts.entry(atom.atom_type).or_insert(0) += 1;
                     }
                 }
@@ -1,5 +1,8 @@
 use bevy::input::touch::{Touch, TouchPhase};
 use bevy::prelude::*;
+use bevy::render::view::RenderLayers;
+use crate::rendering::{ScreenCamera, SCREEN_LAYER};
+use crate::world_space::WorldSpace;
 
 // Touchscreen and mobile support as mentioned in "Touching Screens" blog post
 // Virtual controls and touch-based interaction
@@ -86,6 +89,16 @@ impl Default for TouchControls {
     }
 }
 
+impl TouchControls {
+    // Screen position the touch brush is painting at, while a finger is down on it
+    pub fn brush_position(&self) -> Option<Vec2> {
+        self.brush_area
+            .as_ref()
+            .filter(|area| self.enabled && area.active)
+            .map(|area| area.brush_position)
+    }
+}
+
 impl Default for TouchGestureRecognizer {
     fn default() -> Self {
         Self {
@@ -498,35 +511,55 @@ pub fn toggle_touchscreen(
 pub fn render_touch_controls(
     touch_controls: Res<TouchControls>,
     mut commands: Commands,
+    mut control_entities: Local<Vec<Entity>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<ScreenCamera>>,
 ) {
+    for entity in control_entities.drain(..) {
+        commands.entity(entity).despawn();
+    }
+
     if !touch_controls.enabled {
         return;
     }
+    let Ok((camera, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+    // Controls are laid out in screen pixels and drawn over the game at full
+    // window resolution, not into the pixel canvas
+    let to_overlay = |screen: Vec2, z: f32| {
+        WorldSpace::screen_to_overlay(camera, camera_transform, screen).map(|position| position.extend(z))
+    };
 
     // Render virtual joystick
     if let Some(ref joystick) = touch_controls.virtual_joystick {
+        let (Some(base_pos), Some(handle_pos)) = (
+            to_overlay(joystick.center, 5.0),
+            to_overlay(joystick.center + joystick.direction * joystick.max_distance * 0.5, 6.0),
+        ) else {
+            return;
+        };
+
         // Render joystick base
-        commands.spawn(SpriteBundle {
+        control_entities.push(commands.spawn((SpriteBundle {
             sprite: Sprite {
                 color: Color::rgba(0.5, 0.5, 0.5, 0.3),
                 custom_size: Some(Vec2::new(joystick.max_distance * 2.0, joystick.max_distance * 2.0)),
                 ..default()
             },
-            transform: Transform::from_translation(joystick.center.extend(5.0)),
+            transform: Transform::from_translation(base_pos),
             ..default()
-        });
+        }, RenderLayers::layer(SCREEN_LAYER))).id());
 
         // Render joystick handle
-        let handle_pos = joystick.center + joystick.direction * joystick.max_distance * 0.5;
-        commands.spawn(SpriteBundle {
+        control_entities.push(commands.spawn((SpriteBundle {
             sprite: Sprite {
                 color: Color::rgba(1.0, 1.0, 1.0, 0.7),
                 custom_size: Some(Vec2::new(20.0, 20.0)),
                 ..default()
             },
-            transform: Transform::from_translation(handle_pos.extend(6.0)),
+            transform: Transform::from_translation(handle_pos),
             ..default()
-        });
+        }, RenderLayers::layer(SCREEN_LAYER))).id());
     }
 
     // Render virtual buttons
@@ -537,15 +570,18 @@ pub fn render_touch_controls(
             Color::rgba(0.5, 0.5, 1.0, 0.6)
         };
 
-        commands.spawn(SpriteBundle {
+        let Some(button_pos) = to_overlay(button.position, 5.0) else {
+            continue;
+        };
+        control_entities.push(commands.spawn((SpriteBundle {
             sprite: Sprite {
                 color,
                 custom_size: Some(button.size),
                 ..default()
             },
-            transform: Transform::from_translation(button.position.extend(5.0)),
+            transform: Transform::from_translation(button_pos),
             ..default()
-        });
+        }, RenderLayers::layer(SCREEN_LAYER))).id());
     }
 }
 
@@ -0,0 +1,157 @@
+use bevy::prelude::*;
+use crate::atoms::AtomWorld;
+use crate::rendering::PixelCamera;
+
+// Coordinate spaces and the conversions between them. Everything that maps a
+// position from one space to another goes through here:
+// - grid: AtomWorld cells. Integer, y grows downward (row 0 is the top of the
+//   world), which is the direction atoms fall.
+// - world: Bevy's 2D space that sprites and Rapier bodies live in. One unit per
+//   cell, y up, with the grid centred on the origin. Cell centres sit on integer
+//   coordinates.
+// - physics: Rapier's internal space, in meters.
+// - canvas: pixels of the low-res texture the game camera draws the world into
+//   (see rendering::PixelCamera).
+// - screen: window pixels (y down, origin top left), through the canvas and the
+//   game camera.
+// - overlay: what the screen camera draws straight to the window, like touch
+//   controls. One unit per window pixel, y up, origin in the middle.
+
+// World units (cells) per Rapier meter
+pub const PIXELS_PER_METER: f32 = 32.0;
+
+// The way atoms fall in the grid
+pub const GRID_DOWN: IVec2 = IVec2::Y;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldSpace {
+    half_extent: Vec2,
+}
+
+impl WorldSpace {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            half_extent: Vec2::new(width as f32, height as f32) / 2.0,
+        }
+    }
+
+    pub fn of(world: &AtomWorld) -> Self {
+        Self::new(world.width, world.height)
+    }
+
+    // Centre of a cell
+    pub fn grid_to_world(&self, cell: IVec2) -> Vec2 {
+        self.grid_point_to_world(cell.as_vec2())
+    }
+
+    // A position between cells, like a flying particle's
+    pub fn grid_point_to_world(&self, point: Vec2) -> Vec2 {
+        Vec2::new(point.x - self.half_extent.x, self.half_extent.y - point.y)
+    }
+
+    // The cell a world position falls in
+    pub fn world_to_grid(&self, position: Vec2) -> IVec2 {
+        self.world_to_grid_point(position).round().as_ivec2()
+    }
+
+    pub fn world_to_grid_point(&self, position: Vec2) -> Vec2 {
+        Vec2::new(position.x + self.half_extent.x, self.half_extent.y - position.y)
+    }
+
+    // Directions and velocities only flip y; they don't move with the origin
+    pub fn grid_to_world_vector(vector: Vec2) -> Vec2 {
+        Vec2::new(vector.x, -vector.y)
+    }
+
+    pub fn world_to_grid_vector(vector: Vec2) -> Vec2 {
+        Vec2::new(vector.x, -vector.y)
+    }
+
+    pub fn world_to_physics(position: Vec2) -> Vec2 {
+        position / PIXELS_PER_METER
+    }
+
+    pub fn physics_to_world(position: Vec2) -> Vec2 {
+        position * PIXELS_PER_METER
+    }
+
+    // `camera` is the game camera, the one with the PixelCamera
+    pub fn screen_to_world(camera: &Camera, camera_transform: &GlobalTransform, pixel_camera: &PixelCamera, screen: Vec2) -> Option<Vec2> {
+        camera.viewport_to_world_2d(camera_transform, pixel_camera.screen_to_canvas(screen))
+    }
+
+    pub fn world_to_screen(camera: &Camera, camera_transform: &GlobalTransform, pixel_camera: &PixelCamera, position: Vec2) -> Option<Vec2> {
+        camera
+            .world_to_viewport(camera_transform, position.extend(0.0))
+            .map(|canvas| pixel_camera.canvas_to_screen(canvas))
+    }
+
+    pub fn screen_to_grid(&self, camera: &Camera, camera_transform: &GlobalTransform, pixel_camera: &PixelCamera, screen: Vec2) -> Option<IVec2> {
+        Self::screen_to_world(camera, camera_transform, pixel_camera, screen).map(|position| self.world_to_grid(position))
+    }
+
+    pub fn grid_to_screen(&self, camera: &Camera, camera_transform: &GlobalTransform, pixel_camera: &PixelCamera, cell: IVec2) -> Option<Vec2> {
+        Self::world_to_screen(camera, camera_transform, pixel_camera, self.grid_to_world(cell))
+    }
+
+    // `camera` is the screen camera
+    pub fn screen_to_overlay(camera: &Camera, camera_transform: &GlobalTransform, screen: Vec2) -> Option<Vec2> {
+        camera.viewport_to_world_2d(camera_transform, screen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space() -> WorldSpace {
+        WorldSpace::new(64, 48)
+    }
+
+    #[test]
+    fn grid_cells_round_trip_through_world_space() {
+        let space = space();
+        for cell in [IVec2::ZERO, IVec2::new(10, 20), IVec2::new(63, 47), IVec2::new(-5, 70)] {
+            assert_eq!(space.world_to_grid(space.grid_to_world(cell)), cell);
+        }
+    }
+
+    #[test]
+    fn world_positions_round_trip_through_physics_space() {
+        for position in [Vec2::ZERO, Vec2::new(12.5, -3.25), Vec2::new(-100.0, 64.0)] {
+            let round_trip = WorldSpace::physics_to_world(WorldSpace::world_to_physics(position));
+            assert!(round_trip.abs_diff_eq(position, 1e-4), "{position} came back as {round_trip}");
+        }
+    }
+
+    #[test]
+    fn cells_are_centred_on_their_world_position() {
+        let space = space();
+        let cell = IVec2::new(10, 20);
+        let center = space.grid_to_world(cell);
+        assert_eq!(center, Vec2::new(-22.0, 4.0));
+        assert_eq!(space.world_to_grid_point(center), cell.as_vec2());
+
+        // Anywhere inside the cell maps back to it
+        let inside = [Vec2::new(0.49, 0.0), Vec2::new(-0.49, 0.0), Vec2::new(0.0, 0.49), Vec2::new(0.0, -0.49)];
+        for offset in inside {
+            assert_eq!(space.world_to_grid(center + offset), cell);
+        }
+        // Edges sit half a cell from the centre, and past them is the next cell
+        assert_eq!(space.world_to_grid_point(center + Vec2::new(0.5, 0.0)), Vec2::new(10.5, 20.0));
+        assert_eq!(space.world_to_grid_point(center + Vec2::new(0.0, 0.5)), Vec2::new(10.0, 19.5));
+        assert_eq!(space.world_to_grid(center + Vec2::new(0.51, 0.0)), cell + IVec2::X);
+        assert_eq!(space.world_to_grid(center + Vec2::new(0.0, -0.51)), cell + IVec2::Y);
+    }
+
+    #[test]
+    fn falling_in_the_grid_goes_down_on_screen() {
+        let space = space();
+        let cell = IVec2::new(10, 20);
+        let below = space.grid_to_world(cell + GRID_DOWN);
+        let above = space.grid_to_world(cell - GRID_DOWN);
+        assert!(below.y < space.grid_to_world(cell).y);
+        assert!(above.y > space.grid_to_world(cell).y);
+        assert!(WorldSpace::grid_to_world_vector(GRID_DOWN.as_vec2()).y < 0.0);
+    }
+}